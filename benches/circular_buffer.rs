@@ -0,0 +1,66 @@
+//! Compares the buffer traffic of one hop with the exact buffer size `DefaultConfig`
+//! derives, which wraps its indices with a modulo, against the power of two size
+//! `Config<_, _, true>` rounds it up to, which wraps with a mask. Run with
+//! `cargo bench --bench circular_buffer`.
+//!
+//! On a desktop CPU the modulo by a constant is a multiply, so expect a small difference.
+//! On Cortex-M0 it's a call to the division routine for every sample.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use vocoder::audio_processor::{BUFFER_SIZE, FFT_SIZE, HOP_SIZE};
+use vocoder::circular_buffer::CircularBuffer;
+use vocoder::config::Config;
+
+const MASK_SIZE: usize = Config::<FFT_SIZE, HOP_SIZE, true>::BUFFER_SIZE;
+const ITERATIONS: u32 = 200_000;
+
+/// The per-sample reads and writes of one hop, then `process_fft`'s copy into the frame
+/// and overlap-add out of it
+fn hop<const N: usize>(
+    in_buffer: &mut CircularBuffer<f32, N>,
+    out_buffer: &mut CircularBuffer<f32, N>,
+    frame: &mut [f32; FFT_SIZE],
+) -> f32 {
+    let mut sum = 0.0;
+    for n in 0..HOP_SIZE {
+        in_buffer.write(n as f32);
+        sum += out_buffer.read_and_reset();
+    }
+
+    in_buffer.push_read_back(FFT_SIZE - HOP_SIZE);
+    in_buffer.read_into(frame);
+    out_buffer.add_slice(frame);
+    out_buffer.next_hop();
+    sum
+}
+
+fn time<const N: usize>() -> Duration {
+    let mut in_buffer: CircularBuffer<f32, N> = CircularBuffer::new(0.0, Some(0));
+    let mut out_buffer: CircularBuffer<f32, N> = CircularBuffer::new(0.0, Some(HOP_SIZE));
+    out_buffer.next_hop();
+    let mut frame = [0.0; FFT_SIZE];
+
+    // Warm up caches before timing
+    for _ in 0..ITERATIONS / 10 {
+        black_box(hop(&mut in_buffer, &mut out_buffer, &mut frame));
+    }
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(hop(&mut in_buffer, &mut out_buffer, &mut frame));
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let modulo = time::<BUFFER_SIZE>();
+    let mask = time::<MASK_SIZE>();
+
+    println!("modulo ({BUFFER_SIZE:>4}): {:>8.2?} per hop", modulo);
+    println!("mask   ({MASK_SIZE:>4}): {:>8.2?} per hop", mask);
+    println!(
+        "speedup:       {:>8.2}x",
+        modulo.as_secs_f64() / mask.as_secs_f64()
+    );
+}
@@ -0,0 +1,59 @@
+//! Compares the libm and micromath backends of `process_fft`: time per hop and the
+//! difference in rendered output. Run with `cargo bench --bench fast_math`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use libm::{log10f, sinf};
+use vocoder::audio_processor::{AudioProcessor, HOP_SIZE};
+use vocoder::math::{FastMath, Libm, MathBackend};
+
+const SAMPLES: usize = 44_100;
+const PITCH_SHIFT: f32 = 1.5;
+
+fn test_signal() -> Vec<f32> {
+    (0..SAMPLES)
+        .map(|n| {
+            let t = n as f32;
+            // A chord plus a slow chirp, so bins between the partials see some energy
+            0.3 * sinf(0.031 * t) + 0.2 * sinf(0.047 * t + 1.0) + 0.1 * sinf(1e-6 * t * t)
+        })
+        .collect()
+}
+
+/// Render the whole signal, returning the output and the time it took
+fn render<M: MathBackend>(input: &[f32]) -> (Vec<f32>, Duration) {
+    let mut processor = AudioProcessor::<M>::with_backend(PITCH_SHIFT);
+    let start = Instant::now();
+    let output = input
+        .iter()
+        .map(|sample| processor.process_sample(black_box(*sample)))
+        .collect();
+    (output, start.elapsed())
+}
+
+fn main() {
+    let input = test_signal();
+    let hops = (SAMPLES / HOP_SIZE) as u32;
+
+    let (reference, libm_time) = render::<Libm>(&input);
+    let (fast, fast_time) = render::<FastMath>(&input);
+
+    let signal_power: f32 = reference.iter().map(|x| x * x).sum();
+    let noise_power: f32 = reference
+        .iter()
+        .zip(fast.iter())
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum();
+
+    println!("libm:      {:>8.2?} per hop", libm_time / hops);
+    println!("micromath: {:>8.2?} per hop", fast_time / hops);
+    println!(
+        "speedup:   {:>8.2}x",
+        libm_time.as_secs_f64() / fast_time.as_secs_f64()
+    );
+    println!(
+        "micromath output against libm: {:.1} dB SNR",
+        10.0 * log10f(signal_power / noise_power)
+    );
+}
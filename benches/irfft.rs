@@ -0,0 +1,69 @@
+//! Compares the synthesis side of `process_fft` before and after switching to the
+//! inverse real FFT. Run with `cargo bench --bench irfft`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use libm::sinf;
+use microfft::Complex32;
+use vocoder::irfft;
+
+const FFT_SIZE: usize = 1024;
+const ITERATIONS: u32 = 20_000;
+
+fn test_spectrum() -> [Complex32; FFT_SIZE / 2] {
+    let mut signal = [0.0; FFT_SIZE];
+    for (n, value) in signal.iter_mut().enumerate() {
+        *value = sinf(0.05 * n as f32) + 0.25 * sinf(0.31 * n as f32);
+    }
+    *microfft::real::rfft_1024(&mut signal)
+}
+
+/// The original path: mirror the half spectrum into a full one and run a complex IFFT
+fn full_spectrum_ifft(spectrum: &[Complex32; FFT_SIZE / 2]) -> f32 {
+    let mut full_spectrum = [Complex32 { re: 0.0, im: 0.0 }; FFT_SIZE];
+    for i in 0..(FFT_SIZE / 2) {
+        full_spectrum[i] = spectrum[i];
+        if i > 0 {
+            full_spectrum[FFT_SIZE - i] = spectrum[i].conj();
+        }
+    }
+    let res = microfft::inverse::ifft_1024(&mut full_spectrum);
+    res.iter().map(|val| val.re).sum()
+}
+
+fn real_ifft(
+    spectrum: &[Complex32; FFT_SIZE / 2],
+    twiddles: &[Complex32; FFT_SIZE / 4],
+) -> f32 {
+    let mut half_spectrum = *spectrum;
+    let res = irfft::irfft_1024(&mut half_spectrum, twiddles);
+    res.iter().sum()
+}
+
+fn time(mut f: impl FnMut() -> f32) -> Duration {
+    // Warm up caches before timing
+    for _ in 0..ITERATIONS / 10 {
+        black_box(f());
+    }
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(f());
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let spectrum = test_spectrum();
+    let twiddles = irfft::generate_twiddles();
+
+    let full = time(|| full_spectrum_ifft(black_box(&spectrum)));
+    let real = time(|| real_ifft(black_box(&spectrum), black_box(&twiddles)));
+
+    println!("full spectrum ifft_1024: {:>8.2?} per hop", full);
+    println!("irfft_1024:              {:>8.2?} per hop", real);
+    println!(
+        "speedup:                 {:>8.2}x",
+        full.as_secs_f64() / real.as_secs_f64()
+    );
+}
@@ -0,0 +1,92 @@
+#ifndef VOCODER_H
+#define VOCODER_H
+
+/* Generated by cbindgen from ffi/src/lib.rs, regenerate with UPDATE_HEADER=1 cargo test -p vocoder-ffi */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Samples per FFT frame
+#define VOCODER_FFT_SIZE 1024
+
+// Samples between FFTs
+#define VOCODER_HOP_SIZE 128
+
+// Samples the output is delayed by
+#define VOCODER_LATENCY 1024
+
+// Bytes of storage `vocoder_init` needs, with room to spare on every target
+#define VOCODER_STORAGE_SIZE 30720
+
+// Alignment of the storage `vocoder_init` needs
+#define VOCODER_STORAGE_ALIGN 8
+
+// A pitch shifter. Opaque, only handled through pointers.
+typedef struct Vocoder Vocoder;
+
+// Settings a processor starts with
+typedef struct VocoderConfig {
+  // Ratio of output to input pitch, 1.0 leaves it unchanged
+  float pitch_shift;
+  // 0.0 moves formants with the pitch, 1.0 keeps them in place
+  float formant_preservation;
+} VocoderConfig;
+
+// Unshifted, with formants moving with the pitch
+struct VocoderConfig vocoder_default_config(void);
+
+// Start a processor in `storage`, which must stay valid until it's no longer used.
+// Returns NULL if `storage` is smaller than `VOCODER_STORAGE_SIZE` or not aligned to
+// `VOCODER_STORAGE_ALIGN`, or if the pitch shift isn't a positive ratio. There's
+// nothing to free afterwards.
+//
+// # Safety
+// `storage` must point to `size` writable bytes, and `config` to a config
+struct Vocoder *vocoder_init(uint8_t *storage, size_t size, const struct VocoderConfig *config);
+
+#if defined(VOCODER_STD)
+// Allocate and start a processor, freed with `vocoder_destroy`. Returns NULL if the
+// pitch shift isn't a positive ratio.
+//
+// # Safety
+// `config` must point to a config
+struct Vocoder *vocoder_create(const struct VocoderConfig *config);
+#endif
+
+#if defined(VOCODER_STD)
+// # Safety
+// `vocoder` must come from `vocoder_create`, and isn't valid afterwards
+void vocoder_destroy(struct Vocoder *vocoder);
+#endif
+
+// Shift `frames` samples from `input` into `output`, which may be the same buffer.
+// The output is `VOCODER_LATENCY` samples behind the input.
+//
+// # Safety
+// `input` and `output` must point to `frames` samples each, and either be the same
+// buffer or not overlap
+void vocoder_process(struct Vocoder *vocoder, const float *input, float *output, size_t frames);
+
+// Change the pitch shift from the next sample on. Takes effect on the next hop, so
+// there's no need to ramp it. Returns false and keeps the current one if it isn't a
+// positive ratio.
+//
+// # Safety
+// `vocoder` must be a started processor
+bool vocoder_set_pitch_shift(struct Vocoder *vocoder, float pitch_shift);
+
+// # Safety
+// `vocoder` must be a started processor
+void vocoder_set_formant_preservation(struct Vocoder *vocoder, float formant_preservation);
+
+// Forget all audio so far, as if just started with the current settings. Clears the
+// processor in place, so it needs no more stack than `vocoder_process`.
+//
+// # Safety
+// `vocoder` must be a started processor
+void vocoder_reset(struct Vocoder *vocoder);
+
+#endif  /* VOCODER_H */
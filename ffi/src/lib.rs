@@ -0,0 +1,183 @@
+//! C API over `AudioProcessor`, for firmware written in C. `include/vocoder.h` is
+//! generated from this file by cbindgen.
+//!
+//! Firmware without a heap puts the processor in storage it provides with `vocoder_init`.
+//! Everything else can use `vocoder_create` and `vocoder_destroy` from the `std` build.
+//! Unless noted, every function takes a pointer from one of those and isn't safe to call
+//! from two threads at once on the same processor.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+use core::mem::{align_of, size_of};
+use core::ptr::{self, addr_of_mut};
+
+use vocoder::audio_processor::{check_pitch_shift, AudioProcessor, FFT_SIZE, HOP_SIZE, LATENCY};
+
+/// Samples per FFT frame
+pub const VOCODER_FFT_SIZE: u32 = 1024;
+/// Samples between FFTs
+pub const VOCODER_HOP_SIZE: u32 = 128;
+/// Samples the output is delayed by
+pub const VOCODER_LATENCY: u32 = 1024;
+/// Bytes of storage `vocoder_init` needs, with room to spare on every target
+pub const VOCODER_STORAGE_SIZE: usize = 30720;
+/// Alignment of the storage `vocoder_init` needs
+pub const VOCODER_STORAGE_ALIGN: usize = 8;
+
+// cbindgen only copies literals into the header, so check they match the library
+const _: () = {
+    assert!(VOCODER_FFT_SIZE as usize == FFT_SIZE);
+    assert!(VOCODER_HOP_SIZE as usize == HOP_SIZE);
+    assert!(VOCODER_LATENCY as usize == LATENCY);
+    assert!(size_of::<Vocoder>() <= VOCODER_STORAGE_SIZE);
+    assert!(align_of::<Vocoder>() <= VOCODER_STORAGE_ALIGN);
+};
+
+/// Settings a processor starts with
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct VocoderConfig {
+    /// Ratio of output to input pitch, 1.0 leaves it unchanged
+    pub pitch_shift: f32,
+    /// 0.0 moves formants with the pitch, 1.0 keeps them in place
+    pub formant_preservation: f32,
+}
+
+/// A pitch shifter. Opaque, only handled through pointers.
+pub struct Vocoder {
+    processor: AudioProcessor,
+}
+
+impl Vocoder {
+    /// Start a processor in `vocoder` field by field, so the ~28 KB `Vocoder` never
+    /// passes through the caller's stack, which may be a small RTOS task's
+    ///
+    /// # Safety
+    /// `vocoder` must be valid for writes and aligned
+    unsafe fn init(vocoder: *mut Vocoder, config: VocoderConfig) {
+        let processor = addr_of_mut!((*vocoder).processor);
+        AudioProcessor::init_in_place(processor, config.pitch_shift);
+        (*processor).set_formant_preservation(config.formant_preservation);
+    }
+}
+
+/// Unshifted, with formants moving with the pitch
+#[no_mangle]
+pub extern "C" fn vocoder_default_config() -> VocoderConfig {
+    VocoderConfig {
+        pitch_shift: 1.0,
+        formant_preservation: 0.0,
+    }
+}
+
+/// Start a processor in `storage`, which must stay valid until it's no longer used.
+/// Returns NULL if `storage` is smaller than `VOCODER_STORAGE_SIZE` or not aligned to
+/// `VOCODER_STORAGE_ALIGN`, or if the pitch shift isn't a positive ratio. There's
+/// nothing to free afterwards.
+///
+/// # Safety
+/// `storage` must point to `size` writable bytes, and `config` to a config
+#[no_mangle]
+pub unsafe extern "C" fn vocoder_init(
+    storage: *mut u8,
+    size: usize,
+    config: *const VocoderConfig,
+) -> *mut Vocoder {
+    if storage.is_null() || size < VOCODER_STORAGE_SIZE || !storage.cast::<Vocoder>().is_aligned() {
+        return ptr::null_mut();
+    }
+    if check_pitch_shift((*config).pitch_shift).is_err() {
+        return ptr::null_mut();
+    }
+    let vocoder = storage.cast::<Vocoder>();
+    Vocoder::init(vocoder, *config);
+    vocoder
+}
+
+/// Allocate and start a processor, freed with `vocoder_destroy`. Returns NULL if the
+/// pitch shift isn't a positive ratio.
+///
+/// # Safety
+/// `config` must point to a config
+#[cfg(feature = "std")]
+#[no_mangle]
+pub unsafe extern "C" fn vocoder_create(config: *const VocoderConfig) -> *mut Vocoder {
+    if check_pitch_shift((*config).pitch_shift).is_err() {
+        return ptr::null_mut();
+    }
+    let mut vocoder = Box::<Vocoder>::new_uninit();
+    Vocoder::init(vocoder.as_mut_ptr(), *config);
+    Box::into_raw(vocoder.assume_init())
+}
+
+/// # Safety
+/// `vocoder` must come from `vocoder_create`, and isn't valid afterwards
+#[cfg(feature = "std")]
+#[no_mangle]
+pub unsafe extern "C" fn vocoder_destroy(vocoder: *mut Vocoder) {
+    if !vocoder.is_null() {
+        drop(Box::from_raw(vocoder));
+    }
+}
+
+/// Shift `frames` samples from `input` into `output`, which may be the same buffer.
+/// The output is `VOCODER_LATENCY` samples behind the input.
+///
+/// # Safety
+/// `input` and `output` must point to `frames` samples each, and either be the same
+/// buffer or not overlap
+#[no_mangle]
+pub unsafe extern "C" fn vocoder_process(
+    vocoder: *mut Vocoder,
+    input: *const f32,
+    output: *mut f32,
+    frames: usize,
+) {
+    let processor = &mut (*vocoder).processor;
+    for index in 0..frames {
+        *output.add(index) = processor.process_sample(*input.add(index));
+    }
+}
+
+/// Change the pitch shift from the next sample on. Takes effect on the next hop, so
+/// there's no need to ramp it. Returns false and keeps the current one if it isn't a
+/// positive ratio.
+///
+/// # Safety
+/// `vocoder` must be a started processor
+#[no_mangle]
+pub unsafe extern "C" fn vocoder_set_pitch_shift(vocoder: *mut Vocoder, pitch_shift: f32) -> bool {
+    if check_pitch_shift(pitch_shift).is_err() {
+        return false;
+    }
+    (*vocoder).processor.set_pitch_shift(pitch_shift);
+    true
+}
+
+/// # Safety
+/// `vocoder` must be a started processor
+#[no_mangle]
+pub unsafe extern "C" fn vocoder_set_formant_preservation(
+    vocoder: *mut Vocoder,
+    formant_preservation: f32,
+) {
+    (*vocoder)
+        .processor
+        .set_formant_preservation(formant_preservation);
+}
+
+/// Forget all audio so far, as if just started with the current settings. Clears the
+/// processor in place, so it needs no more stack than `vocoder_process`.
+///
+/// # Safety
+/// `vocoder` must be a started processor
+#[no_mangle]
+pub unsafe extern "C" fn vocoder_reset(vocoder: *mut Vocoder) {
+    (*vocoder).processor.reset();
+}
+
+#[cfg(not(any(feature = "std", test)))]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
@@ -0,0 +1,86 @@
+//! Compiles `process_wav.c` against the generated header and the static library, runs it
+//! on `test_1.wav` and checks it renders exactly what the Rust API does.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use hound::WavReader;
+use vocoder::audio_processor::AudioProcessor;
+
+const PITCH_SHIFT: f32 = 1.5;
+
+/// Where cargo put `libvocoder_ffi.a` for the tests, which is next to this test's
+/// executable. `target/<profile>` only has it after a `cargo build`.
+fn library_dir() -> PathBuf {
+    env::current_exe().unwrap().parent().unwrap().to_path_buf()
+}
+
+fn compile(crate_dir: &Path, out_dir: &Path) -> PathBuf {
+    let exe = out_dir.join("process_wav");
+    let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .args([
+            "-std=c11",
+            "-Wall",
+            "-Wextra",
+            "-Werror",
+            "-DVOCODER_STD",
+            "-I",
+        ])
+        .arg(crate_dir.join("include"))
+        .arg(crate_dir.join("tests/process_wav.c"))
+        .arg(library_dir().join("libvocoder_ffi.a"))
+        // What the Rust standard library needs from the system
+        .args(["-lm", "-lpthread", "-ldl", "-o"])
+        .arg(&exe)
+        .status()
+        .unwrap();
+    assert!(status.success(), "compiling process_wav.c failed");
+    exe
+}
+
+/// The first channel of `path`, scaled the way `process_wav.c` scales it
+fn read_first_channel(path: &Path) -> Vec<f32> {
+    let mut reader = WavReader::open(path).unwrap();
+    let channels = reader.spec().channels as usize;
+    let samples: Vec<i16> = reader.samples::<i16>().map(Result::unwrap).collect();
+    samples
+        .chunks(channels)
+        .map(|frame| frame[0] as f32 / 32768.0)
+        .collect()
+}
+
+#[test]
+fn c_program_renders_like_rust() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let exe = compile(crate_dir, &out_dir);
+
+    let input_path = crate_dir.join("../test_1.wav");
+    let output_path = out_dir.join("process_wav.f32");
+    let status = Command::new(&exe)
+        .arg(&input_path)
+        .arg(&output_path)
+        .arg(PITCH_SHIFT.to_string())
+        .status()
+        .unwrap();
+    assert!(status.success(), "process_wav failed");
+
+    let output: Vec<f32> = fs::read(&output_path)
+        .unwrap()
+        .chunks_exact(4)
+        .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+        .collect();
+    let mut processor = AudioProcessor::new(PITCH_SHIFT);
+    let expected: Vec<f32> = read_first_channel(&input_path)
+        .into_iter()
+        .map(|sample| processor.process_sample(sample))
+        .collect();
+    assert_eq!(output.len(), expected.len());
+    let mismatch = output
+        .iter()
+        .zip(&expected)
+        .position(|(output, expected)| output.to_bits() != expected.to_bits());
+    assert_eq!(mismatch, None, "C and Rust output differ");
+}
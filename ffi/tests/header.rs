@@ -0,0 +1,31 @@
+//! `include/vocoder.h` is checked in for C projects that don't run cargo, so check it's
+//! what cbindgen generates from the current source.
+
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn header_is_up_to_date() {
+    let crate_dir = env!("CARGO_MANIFEST_DIR");
+    let config = cbindgen::Config::from_file(Path::new(crate_dir).join("cbindgen.toml")).unwrap();
+    let mut generated = Vec::new();
+    cbindgen::Builder::new()
+        .with_crate(crate_dir)
+        .with_config(config)
+        .generate()
+        .unwrap()
+        .write(&mut generated);
+    let generated = String::from_utf8(generated).unwrap();
+
+    let path = Path::new(crate_dir).join("include/vocoder.h");
+    if std::env::var_os("UPDATE_HEADER").is_some() {
+        fs::write(&path, generated).unwrap();
+        return;
+    }
+    let checked_in = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        checked_in == generated,
+        "include/vocoder.h is out of date, regenerate it with \
+         UPDATE_HEADER=1 cargo test -p vocoder-ffi --test header"
+    );
+}
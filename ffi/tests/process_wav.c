@@ -0,0 +1,142 @@
+/* Shifts the first channel of a 16 bit PCM WAV file through the C API the way firmware
+ * would, from static storage a block at a time, and writes the output as raw native
+ * endian floats. Exits non-zero if the API misbehaves. Built and run by
+ * tests/c_program.rs:
+ *
+ *     process_wav input.wav output.f32 pitch_shift
+ */
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "vocoder.h"
+
+#define BLOCK_SIZE 256
+/* Samples rendered again after a reset */
+#define CHECK_LENGTH 8192
+
+/* Room for checking misaligned storage is refused */
+static _Alignas(VOCODER_STORAGE_ALIGN) uint8_t storage[VOCODER_STORAGE_SIZE + VOCODER_STORAGE_ALIGN];
+
+static int fail(const char *message) {
+    fprintf(stderr, "process_wav: %s\n", message);
+    return 1;
+}
+
+static uint32_t read_u32(const uint8_t *bytes) {
+    return bytes[0] | bytes[1] << 8 | bytes[2] << 16 | (uint32_t)bytes[3] << 24;
+}
+
+static uint16_t read_u16(const uint8_t *bytes) {
+    return bytes[0] | bytes[1] << 8;
+}
+
+/* The first channel of a 16 bit PCM WAV as floats, or NULL */
+static float *read_wav(const char *path, size_t *length) {
+    FILE *file = fopen(path, "rb");
+    if (!file) {
+        return NULL;
+    }
+    fseek(file, 0, SEEK_END);
+    long size = ftell(file);
+    fseek(file, 0, SEEK_SET);
+    uint8_t *bytes = malloc(size);
+    if (!bytes || fread(bytes, 1, size, file) != (size_t)size || size < 12 ||
+        memcmp(bytes, "RIFF", 4) || memcmp(bytes + 8, "WAVE", 4)) {
+        fclose(file);
+        free(bytes);
+        return NULL;
+    }
+    fclose(file);
+
+    uint16_t channels = 0;
+    float *samples = NULL;
+    for (long chunk = 12; chunk + 8 <= size;) {
+        uint32_t chunk_size = read_u32(bytes + chunk + 4);
+        const uint8_t *data = bytes + chunk + 8;
+        if (chunk + 8 + (long)chunk_size > size) {
+            break;
+        }
+        if (!memcmp(bytes + chunk, "fmt ", 4) && chunk_size >= 16) {
+            if (read_u16(data) != 1 || read_u16(data + 14) != 16) {
+                break;
+            }
+            channels = read_u16(data + 2);
+        } else if (!memcmp(bytes + chunk, "data", 4) && channels) {
+            *length = chunk_size / (2 * channels);
+            samples = malloc(*length * sizeof(float));
+            for (size_t n = 0; samples && n < *length; n++) {
+                samples[n] = (int16_t)read_u16(data + 2 * n * channels) / 32768.0f;
+            }
+            break;
+        }
+        chunk += 8 + chunk_size + (chunk_size & 1);
+    }
+    free(bytes);
+    return samples;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 4) {
+        return fail("usage: process_wav input.wav output.f32 pitch_shift");
+    }
+    size_t length;
+    float *input = read_wav(argv[1], &length);
+    if (!input || length < CHECK_LENGTH) {
+        return fail("can't read the input");
+    }
+    float *output = malloc(length * sizeof(float));
+    float *check = malloc(CHECK_LENGTH * sizeof(float));
+
+    VocoderConfig config = vocoder_default_config();
+    config.pitch_shift = (float)atof(argv[3]);
+    if (vocoder_init(storage, VOCODER_STORAGE_SIZE - 1, &config) ||
+        vocoder_init(storage + 1, VOCODER_STORAGE_SIZE, &config)) {
+        return fail("vocoder_init accepted storage that's too small or misaligned");
+    }
+    VocoderConfig folded = config;
+    folded.pitch_shift = -1.0f;
+    if (vocoder_init(storage, VOCODER_STORAGE_SIZE, &folded)) {
+        return fail("vocoder_init accepted a negative pitch shift");
+    }
+    Vocoder *vocoder = vocoder_init(storage, VOCODER_STORAGE_SIZE, &config);
+    if (!vocoder) {
+        return fail("vocoder_init refused its storage");
+    }
+    if (vocoder_set_pitch_shift(vocoder, 0.0f) ||
+        !vocoder_set_pitch_shift(vocoder, config.pitch_shift)) {
+        return fail("vocoder_set_pitch_shift accepted zero or refused the config's");
+    }
+
+    /* In place, a block at a time */
+    memcpy(output, input, length * sizeof(float));
+    for (size_t start = 0; start < length; start += BLOCK_SIZE) {
+        size_t frames = length - start < BLOCK_SIZE ? length - start : BLOCK_SIZE;
+        vocoder_process(vocoder, output + start, output + start, frames);
+    }
+
+    vocoder_reset(vocoder);
+    vocoder_process(vocoder, input, check, CHECK_LENGTH);
+    if (memcmp(check, output, CHECK_LENGTH * sizeof(float))) {
+        return fail("output after vocoder_reset differs");
+    }
+
+#ifdef VOCODER_STD
+    Vocoder *allocated = vocoder_create(&config);
+    vocoder_process(allocated, input, check, CHECK_LENGTH);
+    vocoder_destroy(allocated);
+    if (memcmp(check, output, CHECK_LENGTH * sizeof(float))) {
+        return fail("output of vocoder_create differs from vocoder_init");
+    }
+#endif
+
+    FILE *file = fopen(argv[2], "wb");
+    if (!file || fwrite(output, sizeof(float), length, file) != length || fclose(file)) {
+        return fail("can't write the output");
+    }
+    free(input);
+    free(output);
+    free(check);
+    return 0;
+}
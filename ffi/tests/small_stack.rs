@@ -0,0 +1,47 @@
+//! Firmware calls `vocoder_init` and `vocoder_reset` from tasks with a few KB of stack,
+//! so neither may build the processor on the stack.
+
+use std::thread;
+
+use vocoder_ffi::{
+    vocoder_default_config, vocoder_init, vocoder_process, vocoder_reset, Vocoder, VOCODER_LATENCY,
+    VOCODER_STORAGE_SIZE,
+};
+
+/// Far less than the processor, which is what building it on the stack would need
+const STACK_SIZE: usize = 16 * 1024;
+
+fn render(vocoder: *mut Vocoder, input: &[f32]) -> Vec<f32> {
+    let mut output = vec![0.0; input.len()];
+    unsafe { vocoder_process(vocoder, input.as_ptr(), output.as_mut_ptr(), input.len()) };
+    output
+}
+
+#[test]
+fn init_and_reset_fit_on_a_small_stack() {
+    let input: Vec<f32> = (0..4 * VOCODER_LATENCY as usize)
+        .map(|n| (n as f32 * 0.05).sin())
+        .collect();
+
+    let outputs = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            // u64s for VOCODER_STORAGE_ALIGN
+            let mut storage = vec![0u64; VOCODER_STORAGE_SIZE / 8];
+            let config = vocoder_default_config();
+            let vocoder =
+                unsafe { vocoder_init(storage.as_mut_ptr().cast(), VOCODER_STORAGE_SIZE, &config) };
+            assert!(!vocoder.is_null());
+            let first = render(vocoder, &input);
+            unsafe { vocoder_reset(vocoder) };
+            let second = render(vocoder, &input);
+            (first, second)
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+
+    let (first, second) = outputs;
+    assert!(first.iter().any(|sample| *sample != 0.0));
+    assert_eq!(first, second, "reset didn't start over");
+}
@@ -0,0 +1,497 @@
+//! The CLAP plugin, with pitch, formant, mix and quality parameters, its latency
+//! reported to the host and its parameters saved with the host's project.
+
+use std::cell::UnsafeCell;
+use std::ffi::{c_char, c_void, CStr};
+use std::ptr;
+
+use clap_sys::entry::clap_plugin_entry;
+use clap_sys::events::{
+    clap_event_header, clap_event_param_value, clap_input_events, clap_output_events,
+    CLAP_CORE_EVENT_SPACE_ID, CLAP_EVENT_PARAM_VALUE,
+};
+use clap_sys::ext::audio_ports::{
+    clap_audio_port_info, clap_plugin_audio_ports, CLAP_AUDIO_PORT_IS_MAIN, CLAP_EXT_AUDIO_PORTS,
+    CLAP_PORT_STEREO,
+};
+use clap_sys::ext::latency::{clap_plugin_latency, CLAP_EXT_LATENCY};
+use clap_sys::ext::params::{
+    clap_host_params, clap_param_info, clap_plugin_params, CLAP_EXT_PARAMS,
+    CLAP_PARAM_IS_AUTOMATABLE, CLAP_PARAM_IS_ENUM, CLAP_PARAM_IS_STEPPED, CLAP_PARAM_RESCAN_VALUES,
+};
+use clap_sys::ext::state::{clap_plugin_state, CLAP_EXT_STATE};
+use clap_sys::factory::plugin_factory::{clap_plugin_factory, CLAP_PLUGIN_FACTORY_ID};
+use clap_sys::host::clap_host;
+use clap_sys::id::clap_id;
+use clap_sys::plugin::{clap_plugin, clap_plugin_descriptor};
+use clap_sys::plugin_features::{
+    CLAP_PLUGIN_FEATURE_AUDIO_EFFECT, CLAP_PLUGIN_FEATURE_PITCH_SHIFTER, CLAP_PLUGIN_FEATURE_STEREO,
+};
+use clap_sys::process::{
+    clap_process, clap_process_status, CLAP_PROCESS_CONTINUE, CLAP_PROCESS_ERROR,
+};
+use clap_sys::stream::{clap_istream, clap_ostream};
+use clap_sys::version::CLAP_VERSION;
+use vocoder::audio_processor::LATENCY;
+
+use crate::dsp::{Dsp, CHANNELS};
+use crate::params::{Param, Params, STATE_SIZE};
+
+pub const PLUGIN_ID: &CStr = c"vocoder.pitch-shifter";
+
+struct Features([*const c_char; 4]);
+// Only pointers to static strings
+unsafe impl Sync for Features {}
+
+static FEATURES: Features = Features([
+    CLAP_PLUGIN_FEATURE_AUDIO_EFFECT.as_ptr(),
+    CLAP_PLUGIN_FEATURE_PITCH_SHIFTER.as_ptr(),
+    CLAP_PLUGIN_FEATURE_STEREO.as_ptr(),
+    ptr::null(),
+]);
+
+static DESCRIPTOR: clap_plugin_descriptor = clap_plugin_descriptor {
+    clap_version: CLAP_VERSION,
+    id: PLUGIN_ID.as_ptr(),
+    name: c"Vocoder Pitch Shifter".as_ptr(),
+    vendor: c"vocoder".as_ptr(),
+    url: c"".as_ptr(),
+    manual_url: c"".as_ptr(),
+    support_url: c"".as_ptr(),
+    version: c"0.1.0".as_ptr(),
+    description: c"Phase vocoder pitch shifter with formant preservation".as_ptr(),
+    features: FEATURES.0.as_ptr(),
+};
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+pub static clap_entry: clap_plugin_entry = clap_plugin_entry {
+    clap_version: CLAP_VERSION,
+    init: Some(entry_init),
+    deinit: Some(entry_deinit),
+    get_factory: Some(entry_get_factory),
+};
+
+unsafe extern "C" fn entry_init(_plugin_path: *const c_char) -> bool {
+    true
+}
+
+unsafe extern "C" fn entry_deinit() {}
+
+unsafe extern "C" fn entry_get_factory(factory_id: *const c_char) -> *const c_void {
+    if !factory_id.is_null() && CStr::from_ptr(factory_id) == CLAP_PLUGIN_FACTORY_ID {
+        &FACTORY as *const clap_plugin_factory as *const c_void
+    } else {
+        ptr::null()
+    }
+}
+
+static FACTORY: clap_plugin_factory = clap_plugin_factory {
+    get_plugin_count: Some(factory_plugin_count),
+    get_plugin_descriptor: Some(factory_plugin_descriptor),
+    create_plugin: Some(factory_create_plugin),
+};
+
+unsafe extern "C" fn factory_plugin_count(_factory: *const clap_plugin_factory) -> u32 {
+    1
+}
+
+unsafe extern "C" fn factory_plugin_descriptor(
+    _factory: *const clap_plugin_factory,
+    index: u32,
+) -> *const clap_plugin_descriptor {
+    if index == 0 {
+        &DESCRIPTOR
+    } else {
+        ptr::null()
+    }
+}
+
+unsafe extern "C" fn factory_create_plugin(
+    _factory: *const clap_plugin_factory,
+    host: *const clap_host,
+    plugin_id: *const c_char,
+) -> *const clap_plugin {
+    if plugin_id.is_null() || CStr::from_ptr(plugin_id) != PLUGIN_ID {
+        return ptr::null();
+    }
+
+    let plugin = Box::into_raw(Box::new(Plugin {
+        clap: clap_plugin {
+            desc: &DESCRIPTOR,
+            plugin_data: ptr::null_mut(),
+            init: Some(plugin_init),
+            destroy: Some(plugin_destroy),
+            activate: Some(plugin_activate),
+            deactivate: Some(plugin_deactivate),
+            start_processing: Some(plugin_start_processing),
+            stop_processing: Some(plugin_stop_processing),
+            reset: Some(plugin_reset),
+            process: Some(plugin_process),
+            get_extension: Some(plugin_get_extension),
+            on_main_thread: Some(plugin_on_main_thread),
+        },
+        host,
+        params: Params::new(),
+        dsp: UnsafeCell::new(None),
+    }));
+    (*plugin).clap.plugin_data = plugin as *mut c_void;
+    &(*plugin).clap
+}
+
+struct Plugin {
+    clap: clap_plugin,
+    host: *const clap_host,
+    params: Params,
+    /// Created on activate. CLAP's threading rules make this exclusive: it's only
+    /// touched on the audio thread while active and on the main thread otherwise.
+    dsp: UnsafeCell<Option<Dsp>>,
+}
+
+impl Plugin {
+    unsafe fn from_clap<'a>(plugin: *const clap_plugin) -> &'a Plugin {
+        &*((*plugin).plugin_data as *const Plugin)
+    }
+
+    /// Store a parameter change from the host, ignoring any other event
+    unsafe fn handle_event(&self, header: &clap_event_header) {
+        if header.space_id != CLAP_CORE_EVENT_SPACE_ID || header.type_ != CLAP_EVENT_PARAM_VALUE {
+            return;
+        }
+        let event = &*(header as *const clap_event_header as *const clap_event_param_value);
+        if let Some(param) = Param::from_id(event.param_id) {
+            self.params.set(param, event.value);
+        }
+    }
+
+    /// Tell the host the parameters changed under it, after loading state
+    unsafe fn rescan_params(&self) {
+        let host = &*self.host;
+        let Some(get_extension) = host.get_extension else {
+            return;
+        };
+        let params = get_extension(host, CLAP_EXT_PARAMS.as_ptr()) as *const clap_host_params;
+        if let Some(rescan) = params.as_ref().and_then(|params| params.rescan) {
+            rescan(host, CLAP_PARAM_RESCAN_VALUES);
+        }
+    }
+}
+
+unsafe fn input_events<'a>(
+    events: *const clap_input_events,
+) -> impl Iterator<Item = &'a clap_event_header> {
+    let events = events.as_ref();
+    let count = events
+        .and_then(|events| Some(events.size?(events)))
+        .unwrap_or(0);
+    (0..count).filter_map(move |index| {
+        let events = events?;
+        events.get?(events, index).as_ref()
+    })
+}
+
+unsafe extern "C" fn plugin_init(_plugin: *const clap_plugin) -> bool {
+    true
+}
+
+unsafe extern "C" fn plugin_destroy(plugin: *const clap_plugin) {
+    drop(Box::from_raw((*plugin).plugin_data as *mut Plugin));
+}
+
+unsafe extern "C" fn plugin_activate(
+    plugin: *const clap_plugin,
+    sample_rate: f64,
+    _min_frames_count: u32,
+    _max_frames_count: u32,
+) -> bool {
+    let plugin = Plugin::from_clap(plugin);
+    *plugin.dsp.get() = Some(Dsp::new(sample_rate, &plugin.params));
+    true
+}
+
+unsafe extern "C" fn plugin_deactivate(plugin: *const clap_plugin) {
+    *Plugin::from_clap(plugin).dsp.get() = None;
+}
+
+unsafe extern "C" fn plugin_start_processing(_plugin: *const clap_plugin) -> bool {
+    true
+}
+
+unsafe extern "C" fn plugin_stop_processing(_plugin: *const clap_plugin) {}
+
+unsafe extern "C" fn plugin_reset(plugin: *const clap_plugin) {
+    if let Some(dsp) = (*Plugin::from_clap(plugin).dsp.get()).as_mut() {
+        dsp.reset();
+    }
+}
+
+unsafe extern "C" fn plugin_process(
+    plugin: *const clap_plugin,
+    process: *const clap_process,
+) -> clap_process_status {
+    let plugin = Plugin::from_clap(plugin);
+    let process = &*process;
+    let Some(dsp) = (*plugin.dsp.get()).as_mut() else {
+        return CLAP_PROCESS_ERROR;
+    };
+    // Pick up changes made on the main thread, like loaded state
+    dsp.update(&plugin.params);
+
+    let mut events = input_events(process.in_events).peekable();
+    if process.audio_inputs_count > 0 && process.audio_outputs_count > 0 {
+        let input = &*process.audio_inputs;
+        let output = &*process.audio_outputs;
+        if input.data32.is_null() || output.data32.is_null() {
+            return CLAP_PROCESS_ERROR;
+        }
+        let input_channels = input.channel_count as usize;
+        let output_channels = (output.channel_count as usize).min(CHANNELS);
+
+        for index in 0..process.frames_count as usize {
+            // Apply parameter changes at the sample they're timed for
+            while let Some(event) = events.next_if(|event| event.time as usize <= index) {
+                plugin.handle_event(event);
+                dsp.update(&plugin.params);
+            }
+
+            // Read the whole frame before writing any of it, as the host may process in place
+            let mut frame = [0.0; CHANNELS];
+            if input_channels > 0 {
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    *sample = *(*input.data32.add(channel.min(input_channels - 1))).add(index);
+                }
+            }
+            dsp.process_frame(&mut frame);
+            for (channel, sample) in frame.iter().enumerate().take(output_channels) {
+                *(*output.data32.add(channel)).add(index) = *sample;
+            }
+        }
+    }
+    for event in events {
+        plugin.handle_event(event);
+    }
+    CLAP_PROCESS_CONTINUE
+}
+
+unsafe extern "C" fn plugin_get_extension(
+    _plugin: *const clap_plugin,
+    id: *const c_char,
+) -> *const c_void {
+    if id.is_null() {
+        return ptr::null();
+    }
+    let id = CStr::from_ptr(id);
+    if id == CLAP_EXT_AUDIO_PORTS {
+        &AUDIO_PORTS as *const clap_plugin_audio_ports as *const c_void
+    } else if id == CLAP_EXT_LATENCY {
+        &LATENCY_EXTENSION as *const clap_plugin_latency as *const c_void
+    } else if id == CLAP_EXT_PARAMS {
+        &PARAMS as *const clap_plugin_params as *const c_void
+    } else if id == CLAP_EXT_STATE {
+        &STATE as *const clap_plugin_state as *const c_void
+    } else {
+        ptr::null()
+    }
+}
+
+unsafe extern "C" fn plugin_on_main_thread(_plugin: *const clap_plugin) {}
+
+/// Copy `text` into a C string buffer, truncating it to fit
+fn write_c_str(buffer: &mut [c_char], text: &str) {
+    let Some(capacity) = buffer.len().checked_sub(1) else {
+        return;
+    };
+    let length = text.len().min(capacity);
+    for (out, byte) in buffer.iter_mut().zip(&text.as_bytes()[..length]) {
+        *out = *byte as c_char;
+    }
+    buffer[length] = 0;
+}
+
+static AUDIO_PORTS: clap_plugin_audio_ports = clap_plugin_audio_ports {
+    count: Some(audio_ports_count),
+    get: Some(audio_ports_get),
+};
+
+unsafe extern "C" fn audio_ports_count(_plugin: *const clap_plugin, _is_input: bool) -> u32 {
+    1
+}
+
+unsafe extern "C" fn audio_ports_get(
+    _plugin: *const clap_plugin,
+    index: u32,
+    is_input: bool,
+    info: *mut clap_audio_port_info,
+) -> bool {
+    if index != 0 {
+        return false;
+    }
+    let info = &mut *info;
+    info.id = 0;
+    write_c_str(&mut info.name, if is_input { "Input" } else { "Output" });
+    info.flags = CLAP_AUDIO_PORT_IS_MAIN;
+    info.channel_count = CHANNELS as u32;
+    info.port_type = CLAP_PORT_STEREO.as_ptr();
+    info.in_place_pair = 0;
+    true
+}
+
+static LATENCY_EXTENSION: clap_plugin_latency = clap_plugin_latency {
+    get: Some(latency_get),
+};
+
+unsafe extern "C" fn latency_get(_plugin: *const clap_plugin) -> u32 {
+    LATENCY as u32
+}
+
+static PARAMS: clap_plugin_params = clap_plugin_params {
+    count: Some(params_count),
+    get_info: Some(params_get_info),
+    get_value: Some(params_get_value),
+    value_to_text: Some(params_value_to_text),
+    text_to_value: Some(params_text_to_value),
+    flush: Some(params_flush),
+};
+
+unsafe extern "C" fn params_count(_plugin: *const clap_plugin) -> u32 {
+    Param::ALL.len() as u32
+}
+
+unsafe extern "C" fn params_get_info(
+    _plugin: *const clap_plugin,
+    param_index: u32,
+    param_info: *mut clap_param_info,
+) -> bool {
+    let Some(param) = Param::from_id(param_index) else {
+        return false;
+    };
+    let info = param.info();
+    let param_info = &mut *param_info;
+    param_info.id = param.id();
+    param_info.flags = if info.stepped {
+        CLAP_PARAM_IS_AUTOMATABLE | CLAP_PARAM_IS_STEPPED | CLAP_PARAM_IS_ENUM
+    } else {
+        CLAP_PARAM_IS_AUTOMATABLE
+    };
+    param_info.cookie = ptr::null_mut();
+    write_c_str(&mut param_info.name, info.name);
+    write_c_str(&mut param_info.module, "");
+    param_info.min_value = info.min;
+    param_info.max_value = info.max;
+    param_info.default_value = info.default;
+    true
+}
+
+unsafe extern "C" fn params_get_value(
+    plugin: *const clap_plugin,
+    param_id: clap_id,
+    out_value: *mut f64,
+) -> bool {
+    let Some(param) = Param::from_id(param_id) else {
+        return false;
+    };
+    *out_value = Plugin::from_clap(plugin).params.get(param);
+    true
+}
+
+unsafe extern "C" fn params_value_to_text(
+    _plugin: *const clap_plugin,
+    param_id: clap_id,
+    value: f64,
+    out_buffer: *mut c_char,
+    out_buffer_capacity: u32,
+) -> bool {
+    let Some(param) = Param::from_id(param_id) else {
+        return false;
+    };
+    let buffer = std::slice::from_raw_parts_mut(out_buffer, out_buffer_capacity as usize);
+    write_c_str(buffer, &param.format(value));
+    !buffer.is_empty()
+}
+
+unsafe extern "C" fn params_text_to_value(
+    _plugin: *const clap_plugin,
+    param_id: clap_id,
+    param_value_text: *const c_char,
+    out_value: *mut f64,
+) -> bool {
+    let Some(param) = Param::from_id(param_id) else {
+        return false;
+    };
+    let Ok(text) = CStr::from_ptr(param_value_text).to_str() else {
+        return false;
+    };
+    match param.parse(text) {
+        Some(value) => {
+            *out_value = value;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Parameter changes while the host isn't calling `process`. They're picked up by the
+/// next `process`.
+unsafe extern "C" fn params_flush(
+    plugin: *const clap_plugin,
+    in_: *const clap_input_events,
+    _out: *const clap_output_events,
+) {
+    let plugin = Plugin::from_clap(plugin);
+    for event in input_events(in_) {
+        plugin.handle_event(event);
+    }
+}
+
+static STATE: clap_plugin_state = clap_plugin_state {
+    save: Some(state_save),
+    load: Some(state_load),
+};
+
+unsafe extern "C" fn state_save(plugin: *const clap_plugin, stream: *const clap_ostream) -> bool {
+    let state = Plugin::from_clap(plugin).params.save();
+    let Some(write) = (*stream).write else {
+        return false;
+    };
+    // Streams may take less than asked for at a time
+    let mut written = 0;
+    while written < state.len() {
+        let remaining = &state[written..];
+        let count = write(
+            stream,
+            remaining.as_ptr() as *const c_void,
+            remaining.len() as u64,
+        );
+        if count <= 0 {
+            return false;
+        }
+        written += count as usize;
+    }
+    true
+}
+
+unsafe extern "C" fn state_load(plugin: *const clap_plugin, stream: *const clap_istream) -> bool {
+    let plugin = Plugin::from_clap(plugin);
+    let Some(read) = (*stream).read else {
+        return false;
+    };
+    let mut state = [0; STATE_SIZE];
+    let mut filled = 0;
+    while filled < state.len() {
+        let remaining = &mut state[filled..];
+        let count = read(
+            stream,
+            remaining.as_mut_ptr() as *mut c_void,
+            remaining.len() as u64,
+        );
+        if count <= 0 {
+            break;
+        }
+        filled += count as usize;
+    }
+    if plugin.params.load(&state[..filled]).is_err() {
+        return false;
+    }
+    plugin.rescan_params();
+    true
+}
@@ -0,0 +1,283 @@
+//! The stereo signal path: a processor per channel for each quality, a dry path delayed
+//! to line up with the shifted one, and the smoothed parameters.
+//!
+//! Only the selected quality runs. Switching resets the other one in place, runs both
+//! for `LATENCY` samples while the new one fills up, then crossfades between them.
+
+use vocoder::audio_processor::{AudioProcessor, LATENCY};
+use vocoder::math::{FastMath, Libm, MathBackend};
+
+use crate::params::{Param, Params, Quality, Smoother};
+
+pub const CHANNELS: usize = 2;
+/// How long a parameter change takes to ramp in
+const SMOOTHING_SECONDS: f64 = 0.02;
+
+fn pitch_ratio(semitones: f32) -> f32 {
+    (semitones / 12.0).exp2()
+}
+
+/// How much of the eco processors' output is heard, all or nothing outside a switch
+fn eco_weight(quality: Quality) -> f32 {
+    match quality {
+        Quality::High => 0.0,
+        Quality::Eco => 1.0,
+    }
+}
+
+/// Restart `processors` from silence at the current settings. They're reset in place,
+/// so this is safe on the audio thread.
+fn restart<M: MathBackend>(
+    processors: &mut [AudioProcessor<M>; CHANNELS],
+    semitones: f32,
+    formant: f32,
+) {
+    for processor in processors {
+        processor.reset();
+        processor.set_pitch_shift(pitch_ratio(semitones));
+        processor.set_formant_preservation(formant);
+    }
+}
+
+/// Owned by the audio thread while the plugin is active
+pub struct Dsp {
+    // Both qualities are allocated up front so switching never allocates on the audio
+    // thread
+    high: Box<[AudioProcessor<Libm>; CHANNELS]>,
+    eco: Box<[AudioProcessor<FastMath>; CHANNELS]>,
+    /// The selected quality, which a switch is heading to
+    quality: Quality,
+    /// Samples left before the newly selected processors have filled up and the
+    /// crossfade to them starts
+    warm_up: usize,
+    /// Weight of the eco output against the high one, see `eco_weight`
+    blend: Smoother,
+    dry: Box<[[f32; LATENCY]; CHANNELS]>,
+    dry_position: usize,
+    pitch: Smoother,
+    formant: Smoother,
+    mix: Smoother,
+    /// The values the smoothers were last pointed at, to spot changes in `Params`
+    targets: [f64; Param::ALL.len()],
+}
+
+impl Dsp {
+    pub fn new(sample_rate: f64, params: &Params) -> Dsp {
+        let length = (sample_rate * SMOOTHING_SECONDS) as u32;
+        let targets = Param::ALL.map(|param| params.get(param));
+        let pitch = targets[Param::Pitch as usize] as f32;
+        let formant = targets[Param::Formant as usize] as f32;
+        let mix = targets[Param::Mix as usize] as f32;
+
+        let quality = Quality::from_value(targets[Param::Quality as usize]);
+
+        let mut dsp = Dsp {
+            high: Box::new([(); CHANNELS].map(|_| AudioProcessor::with_backend(1.0))),
+            eco: Box::new([(); CHANNELS].map(|_| AudioProcessor::with_backend(1.0))),
+            quality,
+            warm_up: 0,
+            blend: Smoother::new(eco_weight(quality), length),
+            dry: Box::new([[0.0; LATENCY]; CHANNELS]),
+            dry_position: 0,
+            pitch: Smoother::new(pitch, length),
+            formant: Smoother::new(formant, length),
+            mix: Smoother::new(mix, length),
+            targets,
+        };
+        dsp.reset();
+        dsp
+    }
+
+    /// Clear all audio history and jump to the current targets
+    pub fn reset(&mut self) {
+        let [pitch, formant, mix, _] = self.targets.map(|target| target as f32);
+        self.pitch.reset(pitch);
+        self.formant.reset(formant);
+        self.mix.reset(mix);
+        self.warm_up = 0;
+        self.blend.reset(eco_weight(self.quality));
+        restart(&mut self.high, pitch, formant);
+        restart(&mut self.eco, pitch, formant);
+        for channel in self.dry.iter_mut() {
+            channel.fill(0.0);
+        }
+        self.dry_position = 0;
+    }
+
+    /// Start ramping towards any values in `params` that changed since the last call
+    pub fn update(&mut self, params: &Params) {
+        for param in Param::ALL {
+            let value = params.get(param);
+            if value == self.targets[param as usize] {
+                continue;
+            }
+            self.targets[param as usize] = value;
+            match param {
+                Param::Pitch => self.pitch.set_target(value as f32),
+                Param::Formant => self.formant.set_target(value as f32),
+                Param::Mix => self.mix.set_target(value as f32),
+                Param::Quality => self.switch(Quality::from_value(value)),
+            }
+        }
+    }
+
+    /// Start moving to `quality`'s processors
+    fn switch(&mut self, quality: Quality) {
+        if quality == self.quality {
+            return;
+        }
+        self.quality = quality;
+        let target = eco_weight(quality);
+        // Still heard from a switch that hasn't finished, so still running and full
+        if self.blend.current() != 1.0 - target || self.blend.is_smoothing() {
+            self.warm_up = 0;
+            self.blend.set_target(target);
+            return;
+        }
+        // Idle since the last switch, so its history is stale
+        let (pitch, formant) = (self.pitch.current(), self.formant.current());
+        match quality {
+            Quality::High => restart(&mut self.high, pitch, formant),
+            Quality::Eco => restart(&mut self.eco, pitch, formant),
+        }
+        self.warm_up = LATENCY;
+    }
+
+    /// Process one sample of every channel in place
+    pub fn process_frame(&mut self, frame: &mut [f32; CHANNELS]) {
+        let pitch_changed = self.pitch.is_smoothing();
+        let pitch = pitch_ratio(self.pitch.next_value());
+        let formant = self.formant.next_value();
+        let mix = self.mix.next_value();
+
+        if self.warm_up > 0 {
+            self.warm_up -= 1;
+            if self.warm_up == 0 {
+                self.blend.set_target(eco_weight(self.quality));
+            }
+        }
+        let eco_weight = self.blend.next_value();
+        let run_high = eco_weight < 1.0 || self.quality == Quality::High;
+        let run_eco = eco_weight > 0.0 || self.quality == Quality::Eco;
+
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            let high = if run_high {
+                let high = &mut self.high[channel];
+                shift(high, *sample, pitch_changed, pitch, formant)
+            } else {
+                0.0
+            };
+            let eco = if run_eco {
+                let eco = &mut self.eco[channel];
+                shift(eco, *sample, pitch_changed, pitch, formant)
+            } else {
+                0.0
+            };
+            let wet = high + eco_weight * (eco - high);
+            let dry = core::mem::replace(&mut self.dry[channel][self.dry_position], *sample);
+            *sample = dry + mix * (wet - dry);
+        }
+        self.dry_position = (self.dry_position + 1) % LATENCY;
+    }
+}
+
+fn shift<M: MathBackend>(
+    processor: &mut AudioProcessor<M>,
+    sample: f32,
+    pitch_changed: bool,
+    pitch: f32,
+    formant: f32,
+) -> f32 {
+    if pitch_changed {
+        processor.set_pitch_shift(pitch);
+    }
+    processor.set_formant_preservation(formant);
+    processor.process_sample(sample)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 48_000.0;
+    const BLOCK: usize = 256;
+
+    fn other(quality: Quality) -> Quality {
+        match quality {
+            Quality::High => Quality::Eco,
+            Quality::Eco => Quality::High,
+        }
+    }
+
+    /// RMS of the left channel in blocks of `BLOCK` samples of a sine, with `params`
+    /// changed by `change` after `before` blocks
+    fn block_levels(params: &Params, before: usize, change: impl Fn(&Params)) -> Vec<f32> {
+        let mut dsp = Dsp::new(SAMPLE_RATE, params);
+        let mut levels = Vec::new();
+        for block in 0..before + 4 * LATENCY / BLOCK {
+            if block == before {
+                change(params);
+                dsp.update(params);
+            }
+            let mut sum = 0.0;
+            for n in block * BLOCK..(block + 1) * BLOCK {
+                let sample = (n as f32 * 0.05).sin() * 0.5;
+                let mut frame = [sample; CHANNELS];
+                dsp.process_frame(&mut frame);
+                sum += frame[0] * frame[0];
+            }
+            levels.push((sum / BLOCK as f32).sqrt());
+        }
+        levels
+    }
+
+    #[test]
+    fn switching_quality_crossfades_without_a_gap() {
+        let params = Params::new();
+        params.set(Param::Pitch, 7.0);
+        params.set(Param::Mix, 1.0);
+        let before = 4 * LATENCY / BLOCK;
+        for quality in [Quality::Eco, Quality::High] {
+            params.set(Param::Quality, quality.value());
+            let other = other(quality);
+            let levels = block_levels(&params, before, |params| {
+                params.set(Param::Quality, other.value())
+            });
+            let steady = levels[before - 1];
+            for (block, level) in levels.iter().enumerate().skip(before) {
+                assert!(
+                    (level / steady - 1.0).abs() < 0.1,
+                    "{quality:?} to {other:?}, block {block}: {level} against {steady}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn switching_back_mid_crossfade_keeps_playing() {
+        let params = Params::new();
+        params.set(Param::Mix, 1.0);
+        let before = 4 * LATENCY / BLOCK;
+        let mut dsp = Dsp::new(SAMPLE_RATE, &params);
+        let mut frame = [0.0; CHANNELS];
+        let mut minimum = f32::MAX;
+        let mut level = 0.0;
+        for n in 0..before * BLOCK + 3 * LATENCY {
+            // Switch to eco and back again halfway through the crossfade
+            if n == before * BLOCK || n == before * BLOCK + LATENCY + 480 {
+                let quality = Quality::from_value(params.get(Param::Quality));
+                params.set(Param::Quality, other(quality).value());
+                dsp.update(&params);
+            }
+            let sample = (n as f32 * 0.05).sin() * 0.5;
+            frame.fill(sample);
+            dsp.process_frame(&mut frame);
+            level = level * 0.99 + frame[0].abs() * 0.01;
+            if n >= before * BLOCK {
+                minimum = minimum.min(level);
+            }
+        }
+        assert!(minimum > 0.25, "{minimum}");
+        assert_eq!(dsp.blend.current(), 0.0);
+    }
+}
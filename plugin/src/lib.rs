@@ -0,0 +1,10 @@
+//! Audio plugins around `vocoder::audio_processor::AudioProcessor`. One shared object
+//! carries both the CLAP entry point and the LV2 descriptor.
+//!
+//! `dsp` is the stereo signal path and `params` the parameters and their smoothing, both
+//! shared by the plugin formats.
+
+pub mod clap;
+pub mod dsp;
+pub mod lv2;
+pub mod params;
@@ -0,0 +1,166 @@
+//! The LV2 plugin: stereo audio, semitones and mix control ports, and a latency port so
+//! hosts can compensate.
+//!
+//! LV2 is a small C ABI plus Turtle files describing the plugin. The bundle in
+//! `plugin/lv2/vocoder.lv2` declares the ports, and its indices have to match `Port`.
+
+use std::ffi::{c_char, c_void, CStr};
+use std::ptr;
+
+use vocoder::audio_processor::LATENCY;
+
+use crate::dsp::{Dsp, CHANNELS};
+use crate::params::{Param, Params};
+
+pub const PLUGIN_URI: &CStr = c"urn:vocoder:pitch-shifter";
+
+/// `LV2_Feature` from lv2/core/lv2.h
+#[repr(C)]
+pub struct Lv2Feature {
+    pub uri: *const c_char,
+    pub data: *mut c_void,
+}
+
+/// `LV2_Descriptor` from lv2/core/lv2.h
+#[repr(C)]
+pub struct Lv2Descriptor {
+    pub uri: *const c_char,
+    pub instantiate: unsafe extern "C" fn(
+        descriptor: *const Lv2Descriptor,
+        sample_rate: f64,
+        bundle_path: *const c_char,
+        features: *const *const Lv2Feature,
+    ) -> *mut c_void,
+    pub connect_port: unsafe extern "C" fn(instance: *mut c_void, port: u32, data: *mut c_void),
+    pub activate: unsafe extern "C" fn(instance: *mut c_void),
+    pub run: unsafe extern "C" fn(instance: *mut c_void, sample_count: u32),
+    pub deactivate: unsafe extern "C" fn(instance: *mut c_void),
+    pub cleanup: unsafe extern "C" fn(instance: *mut c_void),
+    pub extension_data: unsafe extern "C" fn(uri: *const c_char) -> *const c_void,
+}
+
+// Only a pointer to a static string
+unsafe impl Sync for Lv2Descriptor {}
+
+/// Port indices, as declared in `vocoder.ttl`
+#[derive(Clone, Copy)]
+pub enum Port {
+    InputLeft,
+    InputRight,
+    OutputLeft,
+    OutputRight,
+    Semitones,
+    Mix,
+    Latency,
+}
+
+const PORT_COUNT: usize = 7;
+const INPUTS: [Port; CHANNELS] = [Port::InputLeft, Port::InputRight];
+const OUTPUTS: [Port; CHANNELS] = [Port::OutputLeft, Port::OutputRight];
+const CONTROLS: [(Port, Param); 2] = [(Port::Semitones, Param::Pitch), (Port::Mix, Param::Mix)];
+
+static DESCRIPTOR: Lv2Descriptor = Lv2Descriptor {
+    uri: PLUGIN_URI.as_ptr(),
+    instantiate,
+    connect_port,
+    activate,
+    run,
+    deactivate,
+    cleanup,
+    extension_data,
+};
+
+#[no_mangle]
+pub extern "C" fn lv2_descriptor(index: u32) -> *const Lv2Descriptor {
+    if index == 0 {
+        &DESCRIPTOR
+    } else {
+        ptr::null()
+    }
+}
+
+struct Instance {
+    ports: [*mut f32; PORT_COUNT],
+    params: Params,
+    dsp: Dsp,
+}
+
+impl Instance {
+    unsafe fn from_handle<'a>(instance: *mut c_void) -> &'a mut Instance {
+        &mut *(instance as *mut Instance)
+    }
+
+    fn port(&self, port: Port) -> *mut f32 {
+        self.ports[port as usize]
+    }
+
+    /// Point the smoothers at the control port values
+    unsafe fn read_controls(&mut self) {
+        for (port, param) in CONTROLS {
+            if let Some(value) = self.port(port).as_ref() {
+                self.params.set(param, *value as f64);
+            }
+        }
+        self.dsp.update(&self.params);
+    }
+}
+
+unsafe extern "C" fn instantiate(
+    _descriptor: *const Lv2Descriptor,
+    sample_rate: f64,
+    _bundle_path: *const c_char,
+    _features: *const *const Lv2Feature,
+) -> *mut c_void {
+    let params = Params::new();
+    let dsp = Dsp::new(sample_rate, &params);
+    Box::into_raw(Box::new(Instance {
+        ports: [ptr::null_mut(); PORT_COUNT],
+        params,
+        dsp,
+    })) as *mut c_void
+}
+
+unsafe extern "C" fn connect_port(instance: *mut c_void, port: u32, data: *mut c_void) {
+    if let Some(location) = Instance::from_handle(instance).ports.get_mut(port as usize) {
+        *location = data as *mut f32;
+    }
+}
+
+/// Start from silence at the current control values, without ramping to them
+unsafe extern "C" fn activate(instance: *mut c_void) {
+    let instance = Instance::from_handle(instance);
+    instance.read_controls();
+    instance.dsp.reset();
+}
+
+unsafe extern "C" fn run(instance: *mut c_void, sample_count: u32) {
+    let instance = Instance::from_handle(instance);
+    instance.read_controls();
+    if let Some(latency) = instance.port(Port::Latency).as_mut() {
+        *latency = LATENCY as f32;
+    }
+
+    let inputs = INPUTS.map(|port| instance.port(port));
+    let outputs = OUTPUTS.map(|port| instance.port(port));
+    if inputs.iter().chain(&outputs).any(|port| port.is_null()) {
+        return;
+    }
+    for index in 0..sample_count as usize {
+        // Read the whole frame before writing any of it, as hosts may process in place
+        let mut frame = inputs.map(|input| *input.add(index));
+        instance.dsp.process_frame(&mut frame);
+        for (output, sample) in outputs.iter().zip(frame) {
+            *output.add(index) = sample;
+        }
+    }
+}
+
+unsafe extern "C" fn deactivate(_instance: *mut c_void) {}
+
+unsafe extern "C" fn cleanup(instance: *mut c_void) {
+    drop(Box::from_raw(instance as *mut Instance));
+}
+
+unsafe extern "C" fn extension_data(_uri: *const c_char) -> *const c_void {
+    ptr::null()
+}
@@ -0,0 +1,349 @@
+//! The plugin's parameters, shared between the main thread and the audio thread as
+//! atomics, their saved state, and the smoothing applied to them on the audio thread.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Param {
+    /// Shift in semitones
+    Pitch,
+    /// How much of the spectral envelope stays in place, 0 to 1
+    Formant,
+    /// Shifted signal against the dry one, 0 to 1
+    Mix,
+    /// Math backend, see `Quality`
+    Quality,
+}
+
+pub struct ParamInfo {
+    pub name: &'static str,
+    pub min: f64,
+    pub max: f64,
+    pub default: f64,
+    pub stepped: bool,
+}
+
+impl Param {
+    pub const ALL: [Param; 4] = [Param::Pitch, Param::Formant, Param::Mix, Param::Quality];
+
+    /// The CLAP parameter id, which is also the index in `ALL`
+    pub fn id(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_id(id: u32) -> Option<Param> {
+        Param::ALL.get(id as usize).copied()
+    }
+
+    pub fn info(self) -> ParamInfo {
+        match self {
+            Param::Pitch => ParamInfo {
+                name: "Pitch",
+                min: -24.0,
+                max: 24.0,
+                default: 0.0,
+                stepped: false,
+            },
+            Param::Formant => ParamInfo {
+                name: "Formant",
+                min: 0.0,
+                max: 1.0,
+                default: 0.0,
+                stepped: false,
+            },
+            Param::Mix => ParamInfo {
+                name: "Mix",
+                min: 0.0,
+                max: 1.0,
+                default: 1.0,
+                stepped: false,
+            },
+            Param::Quality => ParamInfo {
+                name: "Quality",
+                min: 0.0,
+                max: 1.0,
+                default: 0.0,
+                stepped: true,
+            },
+        }
+    }
+
+    /// Clamp to the range, and round stepped parameters
+    pub fn clamp(self, value: f64) -> f64 {
+        let info = self.info();
+        let value = if value.is_nan() { info.default } else { value };
+        let value = value.clamp(info.min, info.max);
+        if info.stepped {
+            value.round()
+        } else {
+            value
+        }
+    }
+
+    pub fn format(self, value: f64) -> String {
+        match self {
+            Param::Pitch => format!("{value:+.2} st"),
+            Param::Formant | Param::Mix => format!("{:.0} %", value * 100.0),
+            Param::Quality => Quality::from_value(value).name().to_string(),
+        }
+    }
+
+    /// Inverse of `format`, also taking the bare number
+    pub fn parse(self, text: &str) -> Option<f64> {
+        let text = text.trim();
+        match self {
+            Param::Pitch => text.trim_end_matches("st").trim().parse().ok(),
+            Param::Formant | Param::Mix => text
+                .trim_end_matches('%')
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .map(|percent| percent / 100.0),
+            Param::Quality => [Quality::High, Quality::Eco]
+                .into_iter()
+                .find(|quality| quality.name().eq_ignore_ascii_case(text))
+                .map(Quality::value),
+        }
+        .map(|value| self.clamp(value))
+    }
+}
+
+/// Which math backend renders the shifted signal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quality {
+    /// libm
+    High,
+    /// micromath's approximations, see `vocoder::math`
+    Eco,
+}
+
+impl Quality {
+    pub fn from_value(value: f64) -> Quality {
+        if value.round() >= 1.0 {
+            Quality::Eco
+        } else {
+            Quality::High
+        }
+    }
+
+    pub fn value(self) -> f64 {
+        match self {
+            Quality::High => 0.0,
+            Quality::Eco => 1.0,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Quality::High => "High",
+            Quality::Eco => "Eco",
+        }
+    }
+}
+
+const STATE_MAGIC: [u8; 4] = *b"VCDR";
+const STATE_VERSION: u32 = 1;
+/// Magic, version, then every parameter in `Param::ALL` order as a little endian f64
+pub const STATE_SIZE: usize = 8 + 8 * Param::ALL.len();
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// Fewer bytes than `STATE_SIZE`
+    Truncated,
+    /// Not state saved by this plugin
+    BadMagic,
+    /// Saved by a newer version of the plugin
+    UnsupportedVersion(u32),
+}
+
+/// Current parameter values, written by whichever thread the host changes them on
+pub struct Params {
+    values: [AtomicU64; 4],
+}
+
+impl Params {
+    pub fn new() -> Params {
+        Params {
+            values: Param::ALL.map(|param| AtomicU64::new(param.info().default.to_bits())),
+        }
+    }
+
+    pub fn get(&self, param: Param) -> f64 {
+        f64::from_bits(self.values[param as usize].load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, param: Param, value: f64) {
+        self.values[param as usize].store(param.clamp(value).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn save(&self) -> [u8; STATE_SIZE] {
+        let mut state = [0; STATE_SIZE];
+        state[..4].copy_from_slice(&STATE_MAGIC);
+        state[4..8].copy_from_slice(&STATE_VERSION.to_le_bytes());
+        for (param, bytes) in Param::ALL.into_iter().zip(state[8..].chunks_exact_mut(8)) {
+            bytes.copy_from_slice(&self.get(param).to_le_bytes());
+        }
+        state
+    }
+
+    /// Restore state from `save`. Nothing changes unless all of it is valid.
+    pub fn load(&self, state: &[u8]) -> Result<(), StateError> {
+        if state.len() < STATE_SIZE {
+            return Err(StateError::Truncated);
+        }
+        if state[..4] != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = u32::from_le_bytes(state[4..8].try_into().unwrap());
+        if version > STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        for (param, bytes) in Param::ALL.into_iter().zip(state[8..].chunks_exact(8)) {
+            self.set(param, f64::from_le_bytes(bytes.try_into().unwrap()));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        Params::new()
+    }
+}
+
+/// Linear ramp to the latest target over a fixed number of samples, so parameter
+/// changes don't click
+pub struct Smoother {
+    current: f32,
+    target: f32,
+    step: f32,
+    remaining: u32,
+    length: u32,
+}
+
+impl Smoother {
+    pub fn new(value: f32, length: u32) -> Smoother {
+        Smoother {
+            current: value,
+            target: value,
+            step: 0.0,
+            remaining: 0,
+            length,
+        }
+    }
+
+    pub fn set_target(&mut self, target: f32) {
+        self.target = target;
+        if self.length == 0 {
+            self.reset(target);
+        } else {
+            self.step = (target - self.current) / self.length as f32;
+            self.remaining = self.length;
+        }
+    }
+
+    /// Jump straight to `value`
+    pub fn reset(&mut self, value: f32) {
+        self.current = value;
+        self.target = value;
+        self.remaining = 0;
+    }
+
+    pub fn current(&self) -> f32 {
+        self.current
+    }
+
+    pub fn is_smoothing(&self) -> bool {
+        self.remaining > 0
+    }
+
+    pub fn next_value(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.current = if self.remaining == 0 {
+                self.target
+            } else {
+                self.current + self.step
+            };
+        }
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smoother_ramps_to_the_target() {
+        let mut smoother = Smoother::new(0.0, 4);
+        smoother.set_target(1.0);
+        let ramp: Vec<f32> = (0..6).map(|_| smoother.next_value()).collect();
+        assert_eq!(ramp, [0.25, 0.5, 0.75, 1.0, 1.0, 1.0]);
+        assert!(!smoother.is_smoothing());
+
+        // A new target mid ramp starts a new ramp from where it got to
+        smoother.set_target(0.0);
+        smoother.next_value();
+        smoother.set_target(2.0);
+        let ramp: Vec<f32> = (0..4).map(|_| smoother.next_value()).collect();
+        assert_eq!(ramp, [1.0625, 1.375, 1.6875, 2.0]);
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let params = Params::new();
+        params.set(Param::Pitch, 7.0);
+        params.set(Param::Formant, 0.5);
+        params.set(Param::Mix, 0.25);
+        params.set(Param::Quality, 1.0);
+        let state = params.save();
+
+        let restored = Params::new();
+        restored.load(&state).unwrap();
+        for param in Param::ALL {
+            assert_eq!(restored.get(param), params.get(param));
+        }
+    }
+
+    #[test]
+    fn bad_state_is_rejected_whole() {
+        let params = Params::new();
+        params.set(Param::Pitch, 12.0);
+        let mut state = params.save();
+
+        let restored = Params::new();
+        assert_eq!(restored.load(&state[..20]), Err(StateError::Truncated));
+        state[8..16].copy_from_slice(&100.0f64.to_le_bytes());
+        state[0] = b'X';
+        assert_eq!(restored.load(&state), Err(StateError::BadMagic));
+        state[0] = b'V';
+        state[4] = 2;
+        assert_eq!(
+            restored.load(&state),
+            Err(StateError::UnsupportedVersion(2))
+        );
+        assert_eq!(restored.get(Param::Pitch), 0.0);
+
+        // Out of range values are clamped rather than rejected
+        state[4] = 1;
+        restored.load(&state).unwrap();
+        assert_eq!(restored.get(Param::Pitch), 24.0);
+    }
+
+    #[test]
+    fn text_round_trips() {
+        for (param, value) in [
+            (Param::Pitch, -3.5),
+            (Param::Formant, 0.25),
+            (Param::Mix, 1.0),
+            (Param::Quality, 1.0),
+        ] {
+            let text = param.format(value);
+            assert_eq!(param.parse(&text), Some(value), "{text}");
+        }
+        assert_eq!(Param::Pitch.parse("30"), Some(24.0));
+        assert_eq!(Param::Quality.parse("eco"), Some(1.0));
+        assert_eq!(Param::Mix.parse("loud"), None);
+    }
+}
@@ -0,0 +1,454 @@
+//! Hosts the plugin in process through its CLAP entry point, the way a DAW would after
+//! loading the `.clap` file, so the whole plugin can be tested without one.
+
+use std::ffi::{c_char, c_void, CStr};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use clap_sys::audio_buffer::clap_audio_buffer;
+use clap_sys::events::{
+    clap_event_header, clap_event_param_value, clap_input_events, clap_output_events,
+    CLAP_CORE_EVENT_SPACE_ID, CLAP_EVENT_PARAM_VALUE,
+};
+use clap_sys::ext::audio_ports::{
+    clap_audio_port_info, clap_plugin_audio_ports, CLAP_EXT_AUDIO_PORTS,
+};
+use clap_sys::ext::latency::{clap_plugin_latency, CLAP_EXT_LATENCY};
+use clap_sys::ext::params::{
+    clap_host_params, clap_param_info, clap_param_rescan_flags, clap_plugin_params, CLAP_EXT_PARAMS,
+};
+use clap_sys::ext::state::{clap_plugin_state, CLAP_EXT_STATE};
+use clap_sys::factory::plugin_factory::{clap_plugin_factory, CLAP_PLUGIN_FACTORY_ID};
+use clap_sys::host::clap_host;
+use clap_sys::plugin::clap_plugin;
+use clap_sys::process::{clap_process, CLAP_PROCESS_CONTINUE};
+use clap_sys::stream::{clap_istream, clap_ostream};
+use clap_sys::version::CLAP_VERSION;
+use vocoder::analysis::{null_test, sine_sweep};
+use vocoder::audio_processor::LATENCY;
+use vocoder_plugin::params::Param;
+use vocoder_plugin::clap::{clap_entry, PLUGIN_ID};
+
+const SAMPLE_RATE: u32 = 48_000;
+const BLOCK_SIZE: usize = 512;
+/// Samples a parameter change takes to ramp in at `SAMPLE_RATE`
+const SMOOTHING_LENGTH: usize = 960;
+
+unsafe extern "C" fn host_get_extension(
+    _host: *const clap_host,
+    extension_id: *const c_char,
+) -> *const c_void {
+    if CStr::from_ptr(extension_id) == CLAP_EXT_PARAMS {
+        &HOST_PARAMS as *const clap_host_params as *const c_void
+    } else {
+        ptr::null()
+    }
+}
+
+unsafe extern "C" fn host_request(_host: *const clap_host) {}
+
+static HOST_PARAMS: clap_host_params = clap_host_params {
+    rescan: Some(host_params_rescan),
+    clear: None,
+    request_flush: Some(host_request),
+};
+
+unsafe extern "C" fn host_params_rescan(host: *const clap_host, _flags: clap_param_rescan_flags) {
+    (*((*host).host_data as *const AtomicU32)).fetch_add(1, Ordering::Relaxed);
+}
+
+unsafe extern "C" fn events_size(list: *const clap_input_events) -> u32 {
+    (*((*list).ctx as *const Vec<clap_event_param_value>)).len() as u32
+}
+
+unsafe extern "C" fn events_get(
+    list: *const clap_input_events,
+    index: u32,
+) -> *const clap_event_header {
+    let events = &*((*list).ctx as *const Vec<clap_event_param_value>);
+    &events[index as usize].header
+}
+
+unsafe extern "C" fn ostream_write(
+    stream: *const clap_ostream,
+    buffer: *const c_void,
+    size: u64,
+) -> i64 {
+    // Take a few bytes at a time, like a stream that writes in chunks
+    let size = size.min(5) as usize;
+    let bytes = std::slice::from_raw_parts(buffer as *const u8, size);
+    (*((*stream).ctx as *mut Vec<u8>)).extend_from_slice(bytes);
+    size as i64
+}
+
+unsafe extern "C" fn istream_read(
+    stream: *const clap_istream,
+    buffer: *mut c_void,
+    size: u64,
+) -> i64 {
+    let remaining = &mut *((*stream).ctx as *mut &[u8]);
+    let size = remaining.len().min(size as usize).min(3);
+    ptr::copy_nonoverlapping(remaining.as_ptr(), buffer as *mut u8, size);
+    *remaining = &remaining[size..];
+    size as i64
+}
+
+fn param_event(time: u32, param: Param, value: f64) -> clap_event_param_value {
+    clap_event_param_value {
+        header: clap_event_header {
+            size: size_of::<clap_event_param_value>() as u32,
+            time,
+            space_id: CLAP_CORE_EVENT_SPACE_ID,
+            type_: CLAP_EVENT_PARAM_VALUE,
+            flags: 0,
+        },
+        param_id: param.id(),
+        cookie: ptr::null_mut(),
+        note_id: -1,
+        port_index: -1,
+        channel: -1,
+        key: -1,
+        value,
+    }
+}
+
+fn input_events(events: &Vec<clap_event_param_value>) -> clap_input_events {
+    clap_input_events {
+        ctx: events as *const Vec<clap_event_param_value> as *mut c_void,
+        size: Some(events_size),
+        get: Some(events_get),
+    }
+}
+
+/// A plugin instance and the host it was created with
+struct Instance {
+    plugin: *const clap_plugin,
+    // Boxed so the plugin's pointers to them stay valid
+    _host: Box<clap_host>,
+    rescans: Box<AtomicU32>,
+}
+
+impl Instance {
+    fn new() -> Instance {
+        let rescans = Box::new(AtomicU32::new(0));
+        let host = Box::new(clap_host {
+            clap_version: CLAP_VERSION,
+            host_data: &*rescans as *const AtomicU32 as *mut c_void,
+            name: c"test host".as_ptr(),
+            vendor: c"".as_ptr(),
+            url: c"".as_ptr(),
+            version: c"0".as_ptr(),
+            get_extension: Some(host_get_extension),
+            request_restart: Some(host_request),
+            request_process: Some(host_request),
+            request_callback: Some(host_request),
+        });
+
+        unsafe {
+            assert!(clap_entry.init.unwrap()(c"".as_ptr()));
+            let factory = clap_entry.get_factory.unwrap()(CLAP_PLUGIN_FACTORY_ID.as_ptr())
+                as *const clap_plugin_factory;
+            let factory = factory.as_ref().expect("no plugin factory");
+            assert_eq!(factory.get_plugin_count.unwrap()(factory), 1);
+            let descriptor = &*factory.get_plugin_descriptor.unwrap()(factory, 0);
+            assert_eq!(CStr::from_ptr(descriptor.id), PLUGIN_ID);
+
+            let plugin = factory.create_plugin.unwrap()(factory, &*host, descriptor.id);
+            assert!(!plugin.is_null());
+            assert!((*plugin).init.unwrap()(plugin));
+            Instance {
+                plugin,
+                _host: host,
+                rescans,
+            }
+        }
+    }
+
+    fn extension<T>(&self, id: &CStr) -> &T {
+        unsafe {
+            let extension = (*self.plugin).get_extension.unwrap()(self.plugin, id.as_ptr());
+            (extension as *const T)
+                .as_ref()
+                .unwrap_or_else(|| panic!("no {id:?} extension"))
+        }
+    }
+
+    fn activate(&self) {
+        unsafe {
+            let plugin = &*self.plugin;
+            assert!(plugin.activate.unwrap()(
+                self.plugin,
+                SAMPLE_RATE as f64,
+                1,
+                BLOCK_SIZE as u32
+            ));
+            assert!(plugin.start_processing.unwrap()(self.plugin));
+        }
+    }
+
+    /// Set parameters outside `process`, as a host does before activating
+    fn flush(&self, events: &[(Param, f64)]) {
+        let events = events
+            .iter()
+            .map(|(param, value)| param_event(0, *param, *value))
+            .collect();
+        let params: &clap_plugin_params = self.extension(CLAP_EXT_PARAMS);
+        let output = clap_output_events {
+            ctx: ptr::null_mut(),
+            try_push: None,
+        };
+        unsafe { params.flush.unwrap()(self.plugin, &input_events(&events), &output) };
+    }
+
+    fn get(&self, param: Param) -> f64 {
+        let params: &clap_plugin_params = self.extension(CLAP_EXT_PARAMS);
+        let mut value = f64::NAN;
+        unsafe {
+            assert!(params.get_value.unwrap()(
+                self.plugin,
+                param.id(),
+                &mut value
+            ))
+        };
+        value
+    }
+
+    /// Process stereo `input` in blocks, with parameter `events` timed from the start
+    fn process(&self, input: &[Vec<f32>; 2], events: &[(usize, Param, f64)]) -> [Vec<f32>; 2] {
+        let mut output = [vec![0.0; input[0].len()], vec![0.0; input[0].len()]];
+        for start in (0..input[0].len()).step_by(BLOCK_SIZE) {
+            let end = (start + BLOCK_SIZE).min(input[0].len());
+            let events = events
+                .iter()
+                .filter(|(time, ..)| (start..end).contains(time))
+                .map(|(time, param, value)| param_event((time - start) as u32, *param, *value))
+                .collect();
+
+            let mut input_channels = input.clone().map(|channel| channel[start..end].to_vec());
+            let mut input_pointers = input_channels
+                .each_mut()
+                .map(|channel| channel.as_mut_ptr());
+            let mut output_pointers = output
+                .each_mut()
+                .map(|channel| channel[start..].as_mut_ptr());
+            let audio_input = clap_audio_buffer {
+                data32: input_pointers.as_mut_ptr(),
+                data64: ptr::null_mut(),
+                channel_count: 2,
+                latency: 0,
+                constant_mask: 0,
+            };
+            let mut audio_output = clap_audio_buffer {
+                data32: output_pointers.as_mut_ptr(),
+                ..audio_input
+            };
+            let output_events = clap_output_events {
+                ctx: ptr::null_mut(),
+                try_push: None,
+            };
+            let process = clap_process {
+                steady_time: start as i64,
+                frames_count: (end - start) as u32,
+                transport: ptr::null(),
+                audio_inputs: &audio_input,
+                audio_outputs: &mut audio_output,
+                audio_inputs_count: 1,
+                audio_outputs_count: 1,
+                in_events: &input_events(&events),
+                out_events: &output_events,
+            };
+            let status = unsafe { (*self.plugin).process.unwrap()(self.plugin, &process) };
+            assert_eq!(status, CLAP_PROCESS_CONTINUE);
+        }
+        output
+    }
+
+    fn save(&self) -> Vec<u8> {
+        let state: &clap_plugin_state = self.extension(CLAP_EXT_STATE);
+        let mut saved = Vec::new();
+        let stream = clap_ostream {
+            ctx: &mut saved as *mut Vec<u8> as *mut c_void,
+            write: Some(ostream_write),
+        };
+        unsafe { assert!(state.save.unwrap()(self.plugin, &stream)) };
+        saved
+    }
+
+    fn load(&self, mut saved: &[u8]) -> bool {
+        let state: &clap_plugin_state = self.extension(CLAP_EXT_STATE);
+        let stream = clap_istream {
+            ctx: &mut saved as *mut &[u8] as *mut c_void,
+            read: Some(istream_read),
+        };
+        unsafe { state.load.unwrap()(self.plugin, &stream) }
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        unsafe {
+            let plugin = &*self.plugin;
+            plugin.stop_processing.unwrap()(self.plugin);
+            plugin.deactivate.unwrap()(self.plugin);
+            plugin.destroy.unwrap()(self.plugin);
+        }
+    }
+}
+
+/// Two seconds of sweep, quieter on the right
+fn stereo_sweep() -> [Vec<f32>; 2] {
+    let left: Vec<f32> = sine_sweep(SAMPLE_RATE)
+        .into_iter()
+        .take(2 * SAMPLE_RATE as usize)
+        .map(|sample| 0.8 * sample)
+        .collect();
+    let right = left.iter().map(|sample| 0.5 * sample).collect();
+    [left, right]
+}
+
+#[test]
+fn describes_ports_params_and_latency() {
+    let instance = Instance::new();
+
+    let latency: &clap_plugin_latency = instance.extension(CLAP_EXT_LATENCY);
+    assert_eq!(
+        unsafe { latency.get.unwrap()(instance.plugin) },
+        LATENCY as u32
+    );
+
+    let ports: &clap_plugin_audio_ports = instance.extension(CLAP_EXT_AUDIO_PORTS);
+    for is_input in [true, false] {
+        assert_eq!(
+            unsafe { ports.count.unwrap()(instance.plugin, is_input) },
+            1
+        );
+        let mut info: clap_audio_port_info = unsafe { std::mem::zeroed() };
+        assert!(unsafe { ports.get.unwrap()(instance.plugin, 0, is_input, &mut info) });
+        assert_eq!(info.channel_count, 2);
+    }
+
+    let params: &clap_plugin_params = instance.extension(CLAP_EXT_PARAMS);
+    let names: Vec<String> = (0..unsafe { params.count.unwrap()(instance.plugin) })
+        .map(|index| {
+            let mut info: clap_param_info = unsafe { std::mem::zeroed() };
+            assert!(unsafe { params.get_info.unwrap()(instance.plugin, index, &mut info) });
+            assert_eq!(
+                instance.get(Param::from_id(info.id).unwrap()),
+                info.default_value
+            );
+            unsafe { CStr::from_ptr(info.name.as_ptr()) }
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+    assert_eq!(names, ["Pitch", "Formant", "Mix", "Quality"]);
+
+    let mut text = [0 as c_char; 32];
+    let mut value = 0.0;
+    unsafe {
+        assert!(params.value_to_text.unwrap()(
+            instance.plugin,
+            0,
+            7.0,
+            text.as_mut_ptr(),
+            32
+        ));
+        assert_eq!(CStr::from_ptr(text.as_ptr()), c"+7.00 st");
+        assert!(params.text_to_value.unwrap()(
+            instance.plugin,
+            3,
+            c"Eco".as_ptr(),
+            &mut value
+        ));
+    }
+    assert_eq!(value, 1.0);
+}
+
+#[test]
+fn unity_pitch_reconstructs_input_after_the_latency() {
+    let instance = Instance::new();
+    instance.activate();
+    let input = stereo_sweep();
+    let output = instance.process(&input, &[]);
+
+    for (input, output) in input.iter().zip(&output) {
+        let null = null_test(input, output);
+        assert_eq!(null.delay, LATENCY);
+        assert!(null.residual_db < -80.0, "{null}");
+    }
+}
+
+#[test]
+fn dry_mix_is_the_input_delayed_by_the_latency() {
+    let instance = Instance::new();
+    instance.flush(&[(Param::Pitch, 7.0), (Param::Mix, 0.0)]);
+    instance.activate();
+    let input = stereo_sweep();
+    let output = instance.process(&input, &[]);
+
+    for (input, output) in input.iter().zip(&output) {
+        assert!(output[..LATENCY].iter().all(|sample| *sample == 0.0));
+        assert_eq!(output[LATENCY..], input[..input.len() - LATENCY]);
+    }
+}
+
+#[test]
+fn parameter_changes_ramp_in_from_their_sample() {
+    let input = stereo_sweep();
+    let render = |mix: f64, events: &[(usize, Param, f64)]| {
+        let instance = Instance::new();
+        instance.flush(&[(Param::Pitch, 7.0), (Param::Mix, mix)]);
+        instance.activate();
+        let output = instance.process(&input, events);
+        assert_eq!(
+            instance.get(Param::Mix),
+            events.last().map_or(mix, |event| event.2)
+        );
+        output
+    };
+    let wet = render(1.0, &[]);
+    let dry = render(0.0, &[]);
+    // Not on a block boundary, to check it lands on its sample
+    let change = 5000;
+    let ramped = render(1.0, &[(change, Param::Mix, 0.0)]);
+
+    for ((wet, dry), ramped) in wet.iter().zip(&dry).zip(&ramped) {
+        assert_eq!(ramped[..change], wet[..change]);
+        for n in change..input[0].len() {
+            let mix = 1.0 - ((n - change + 1) as f32 / SMOOTHING_LENGTH as f32).min(1.0);
+            let expected = dry[n] + mix * (wet[n] - dry[n]);
+            assert!(
+                (ramped[n] - expected).abs() < 1e-5,
+                "{n}: {} {expected}",
+                ramped[n]
+            );
+        }
+    }
+}
+
+#[test]
+fn state_restores_into_a_new_instance() {
+    let instance = Instance::new();
+    let settings = [
+        (Param::Pitch, -5.0),
+        (Param::Formant, 0.75),
+        (Param::Mix, 0.5),
+        (Param::Quality, 1.0),
+    ];
+    instance.flush(&settings);
+    let saved = instance.save();
+
+    let restored = Instance::new();
+    assert!(restored.load(&saved));
+    for (param, value) in settings {
+        assert_eq!(restored.get(param), value);
+    }
+    assert_eq!(restored.rescans.load(Ordering::Relaxed), 1);
+
+    // Anything else is refused without touching the parameters
+    let fresh = Instance::new();
+    assert!(!fresh.load(&saved[..saved.len() - 1]));
+    assert!(!fresh.load(b"not a saved state at all, but long enough"));
+    assert_eq!(fresh.get(Param::Pitch), 0.0);
+    assert_eq!(fresh.rescans.load(Ordering::Relaxed), 0);
+}
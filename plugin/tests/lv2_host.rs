@@ -0,0 +1,129 @@
+//! Runs the LV2 plugin through `lv2_descriptor` the way a host would, and checks the
+//! bundle's Turtle files describe the binary and ports it actually has. CI also loads the
+//! bundle into `lv2apply`.
+
+use std::ffi::{c_void, CStr};
+use std::fs;
+use std::path::PathBuf;
+use std::ptr;
+
+use vocoder::analysis::{null_test, sine_sweep};
+use vocoder::audio_processor::LATENCY;
+use vocoder_plugin::lv2::{lv2_descriptor, Lv2Descriptor, Port, PLUGIN_URI};
+
+const SAMPLE_RATE: u32 = 48_000;
+const BLOCK_SIZE: usize = 256;
+
+fn bundle_file(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("lv2/vocoder.lv2")
+        .join(name);
+    fs::read_to_string(&path).unwrap_or_else(|err| panic!("{}: {err}", path.display()))
+}
+
+/// Render `input` in place, as hosts are allowed to, returning it and the reported latency
+fn render(semitones: f32, mix: f32, mut input: [Vec<f32>; 2]) -> ([Vec<f32>; 2], f32) {
+    let descriptor: &Lv2Descriptor = unsafe { &*lv2_descriptor(0) };
+    assert_eq!(unsafe { CStr::from_ptr(descriptor.uri) }, PLUGIN_URI);
+
+    let mut semitones = semitones;
+    let mut mix = mix;
+    let mut latency = -1.0;
+    unsafe {
+        let instance = (descriptor.instantiate)(
+            descriptor,
+            SAMPLE_RATE as f64,
+            c"".as_ptr(),
+            [ptr::null()].as_ptr(),
+        );
+        assert!(!instance.is_null());
+        let connect = |port: Port, data: *mut f32| {
+            (descriptor.connect_port)(instance, port as u32, data as *mut c_void)
+        };
+        connect(Port::Semitones, &mut semitones);
+        connect(Port::Mix, &mut mix);
+        connect(Port::Latency, &mut latency);
+        (descriptor.activate)(instance);
+
+        let length = input[0].len();
+        for start in (0..length).step_by(BLOCK_SIZE) {
+            let [left, right] = input
+                .each_mut()
+                .map(|channel| channel[start..].as_mut_ptr());
+            connect(Port::InputLeft, left);
+            connect(Port::InputRight, right);
+            connect(Port::OutputLeft, left);
+            connect(Port::OutputRight, right);
+            (descriptor.run)(instance, BLOCK_SIZE.min(length - start) as u32);
+        }
+
+        (descriptor.deactivate)(instance);
+        (descriptor.cleanup)(instance);
+    }
+    (input, latency)
+}
+
+fn stereo_sweep() -> [Vec<f32>; 2] {
+    let left: Vec<f32> = sine_sweep(SAMPLE_RATE)
+        .into_iter()
+        .take(SAMPLE_RATE as usize)
+        .map(|sample| 0.8 * sample)
+        .collect();
+    let right = left.iter().map(|sample| -0.5 * sample).collect();
+    [left, right]
+}
+
+#[test]
+fn bundle_matches_the_binary() {
+    let manifest = bundle_file("manifest.ttl");
+    let plugin = format!("<{}>", PLUGIN_URI.to_str().unwrap());
+    assert!(
+        manifest.contains(&plugin),
+        "{plugin} missing from manifest.ttl"
+    );
+    let binary = format!("lib{}.so", env!("CARGO_PKG_NAME").replace('-', "_"));
+    assert!(manifest.contains(&format!("lv2:binary <{binary}>")));
+
+    let description = bundle_file("vocoder.ttl");
+    assert!(description.contains(&plugin));
+    for (port, symbol) in [
+        (Port::InputLeft, "in_l"),
+        (Port::InputRight, "in_r"),
+        (Port::OutputLeft, "out_l"),
+        (Port::OutputRight, "out_r"),
+        (Port::Semitones, "semitones"),
+        (Port::Mix, "mix"),
+        (Port::Latency, "latency"),
+    ] {
+        let declaration = format!("lv2:index {} ;\n\t\tlv2:symbol \"{symbol}\"", port as u32);
+        assert!(
+            description.contains(&declaration),
+            "{symbol} isn't port {}",
+            port as u32
+        );
+    }
+}
+
+#[test]
+fn unity_reconstructs_input_and_reports_latency() {
+    let input = stereo_sweep();
+    let (output, latency) = render(0.0, 1.0, input.clone());
+    assert_eq!(latency, LATENCY as f32);
+
+    for (input, output) in input.iter().zip(&output) {
+        let null = null_test(input, output);
+        assert_eq!(null.delay, LATENCY);
+        assert!(null.residual_db < -80.0, "{null}");
+    }
+}
+
+#[test]
+fn dry_mix_is_the_input_delayed_by_the_latency() {
+    let input = stereo_sweep();
+    let (output, _) = render(7.0, 0.0, input.clone());
+
+    for (input, output) in input.iter().zip(&output) {
+        assert!(output[..LATENCY].iter().all(|sample| *sample == 0.0));
+        assert_eq!(output[LATENCY..], input[..input.len() - LATENCY]);
+    }
+}
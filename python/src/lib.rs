@@ -0,0 +1,181 @@
+//! Python bindings, built with maturin into a module called `vocoder` (see
+//! `pyproject.toml`). Signals go in as 1-D arrays of anything numpy can convert to float32
+//! and come back as float32 arrays. The GIL is released while processing, so processors
+//! in different threads run in parallel.
+
+use numpy::ndarray::Array2;
+use numpy::{AllowTypeChange, IntoPyArray, PyArray1, PyArray2, PyArrayLike1};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use vocoder::audio_processor::{
+    self, check_pitch_shift, AudioProcessor, FFT_SIZE, HOP_SIZE, LATENCY,
+};
+use vocoder::hann_window::{generate_hanning_window, HANN_WINDOW};
+
+/// Bins per hop in `analyze`'s output, DC to Nyquist
+pub const BINS: usize = audio_processor::BINS;
+
+/// Run `samples` through `processor`, which carries on from where it was. The output is
+/// `LATENCY` samples behind the input.
+pub fn process(processor: &mut AudioProcessor, samples: &[f32]) -> Vec<f32> {
+    samples
+        .iter()
+        .map(|sample| processor.process_sample(*sample))
+        .collect()
+}
+
+/// Shift a whole signal with a new processor, taking out the latency so the output lines
+/// up with `samples` and is as long
+pub fn shift(samples: &[f32], pitch_shift: f32, formant_preservation: f32) -> Vec<f32> {
+    let mut processor = AudioProcessor::new(pitch_shift);
+    processor.set_formant_preservation(formant_preservation);
+    samples
+        .iter()
+        .copied()
+        .chain(std::iter::repeat_n(0.0, LATENCY))
+        .map(|sample| processor.process_sample(sample))
+        .skip(LATENCY)
+        .collect()
+}
+
+/// `pitch_shift` if the processor can shift by it, or a `ValueError`
+fn checked(pitch_shift: f32) -> PyResult<f32> {
+    check_pitch_shift(pitch_shift).map_err(|err| PyValueError::new_err(err.to_string()))
+}
+
+/// Magnitudes and frequencies (in bins) the processor measures on each hop of `samples`,
+/// `BINS` values per hop. Hop `k` analyses the `FFT_SIZE` samples up to
+/// `(k + 1) * HOP_SIZE - 1`, with zeros before the signal starts.
+pub fn analyze(samples: &[f32]) -> (Vec<f32>, Vec<f32>) {
+    let hops = samples.len() / HOP_SIZE;
+    let mut magnitudes = Vec::with_capacity(hops * BINS);
+    let mut frequencies = Vec::with_capacity(hops * BINS);
+    let mut processor = AudioProcessor::new(1.0);
+    // A hop runs on the sample after its frame, so push one more for the last frame
+    for sample in samples[..hops * HOP_SIZE].iter().chain([&0.0]) {
+        let hops = processor.hops();
+        processor.process_sample(*sample);
+        if processor.hops() != hops {
+            let (hop_magnitudes, hop_frequencies) = processor.analysis();
+            magnitudes.extend_from_slice(hop_magnitudes);
+            frequencies.extend_from_slice(hop_frequencies);
+        }
+    }
+    (magnitudes, frequencies)
+}
+
+/// Phase vocoder pitch shifter, fed a block at a time
+#[pyclass(name = "Processor", module = "vocoder")]
+struct PyProcessor {
+    processor: Box<AudioProcessor>,
+}
+
+#[pymethods]
+impl PyProcessor {
+    /// Raises `ValueError` if the pitch shift isn't a positive ratio
+    #[new]
+    #[pyo3(signature = (pitch_shift = 1.0, formant_preservation = 0.0))]
+    fn new(pitch_shift: f32, formant_preservation: f32) -> PyResult<PyProcessor> {
+        let mut processor = Box::new(AudioProcessor::new(checked(pitch_shift)?));
+        processor.set_formant_preservation(formant_preservation);
+        Ok(PyProcessor { processor })
+    }
+
+    /// Shift a block, carrying on from the last one. The output is `latency` samples
+    /// behind the input.
+    fn process<'py>(
+        &mut self,
+        py: Python<'py>,
+        samples: PyArrayLike1<'py, f32, AllowTypeChange>,
+    ) -> Bound<'py, PyArray1<f32>> {
+        let samples = samples.as_array().to_vec();
+        let processor = &mut self.processor;
+        py.detach(|| process(processor, &samples)).into_pyarray(py)
+    }
+
+    /// Ratio of output to input pitch, from the next hop on. Raises `ValueError` and
+    /// keeps the current one if it isn't positive.
+    fn set_pitch_shift(&mut self, pitch_shift: f32) -> PyResult<()> {
+        self.processor.set_pitch_shift(checked(pitch_shift)?);
+        Ok(())
+    }
+
+    /// 0.0 moves formants with the pitch, 1.0 keeps them in place
+    fn set_formant_preservation(&mut self, formant_preservation: f32) {
+        self.processor
+            .set_formant_preservation(formant_preservation);
+    }
+
+    /// Forget all audio so far, as if just created with the current settings
+    fn reset(&mut self) {
+        self.processor.reset();
+    }
+
+    /// Samples the output is delayed by
+    #[getter]
+    fn latency(&self) -> usize {
+        self.processor.latency()
+    }
+}
+
+/// Shift a whole signal, with the output lined up with the input and as long. Raises
+/// `ValueError` if the pitch shift isn't a positive ratio.
+#[pyfunction(name = "shift")]
+#[pyo3(signature = (samples, pitch_shift, formant_preservation = 0.0))]
+fn py_shift<'py>(
+    py: Python<'py>,
+    samples: PyArrayLike1<'py, f32, AllowTypeChange>,
+    pitch_shift: f32,
+    formant_preservation: f32,
+) -> PyResult<Bound<'py, PyArray1<f32>>> {
+    let pitch_shift = checked(pitch_shift)?;
+    let samples = samples.as_array().to_vec();
+    Ok(py
+        .detach(|| shift(&samples, pitch_shift, formant_preservation))
+        .into_pyarray(py))
+}
+
+/// The analysis stage on each hop of a signal, as `(magnitudes, frequencies)` arrays of
+/// shape `(len(samples) // HOP_SIZE, FFT_SIZE // 2 + 1)` from DC to Nyquist. Frequencies
+/// are in bins.
+#[pyfunction(name = "analyze")]
+fn py_analyze<'py>(
+    py: Python<'py>,
+    samples: PyArrayLike1<'py, f32, AllowTypeChange>,
+) -> (Bound<'py, PyArray2<f32>>, Bound<'py, PyArray2<f32>>) {
+    let samples = samples.as_array().to_vec();
+    let (magnitudes, frequencies) = py.detach(|| analyze(&samples));
+    let hops = magnitudes.len() / BINS;
+    let to_array = |values| {
+        Array2::from_shape_vec((hops, BINS), values)
+            .unwrap()
+            .into_pyarray(py)
+    };
+    (to_array(magnitudes), to_array(frequencies))
+}
+
+/// Hann window of `FFT_SIZE` samples, computed
+#[pyfunction]
+fn hann_window(py: Python<'_>) -> Bound<'_, PyArray1<f32>> {
+    generate_hanning_window().to_vec().into_pyarray(py)
+}
+
+/// The precomputed Hann window the processor uses
+#[pyfunction]
+fn hann_window_table(py: Python<'_>) -> Bound<'_, PyArray1<f32>> {
+    HANN_WINDOW.to_vec().into_pyarray(py)
+}
+
+#[pymodule(name = "vocoder")]
+fn vocoder_module(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyProcessor>()?;
+    module.add_function(wrap_pyfunction!(py_shift, module)?)?;
+    module.add_function(wrap_pyfunction!(py_analyze, module)?)?;
+    module.add_function(wrap_pyfunction!(hann_window, module)?)?;
+    module.add_function(wrap_pyfunction!(hann_window_table, module)?)?;
+    module.add("FFT_SIZE", FFT_SIZE)?;
+    module.add("HOP_SIZE", HOP_SIZE)?;
+    module.add("LATENCY", LATENCY)?;
+    Ok(())
+}
@@ -0,0 +1,49 @@
+//! The Rust side of the bindings. `python/tests/test_vocoder.py` checks the module itself
+//! once it's built with maturin.
+
+use vocoder::analysis::{null_test, sine_sweep};
+use vocoder::audio_processor::{AudioProcessor, FFT_SIZE, HOP_SIZE, LATENCY};
+use vocoder_python::{analyze, process, shift, BINS};
+
+const SAMPLE_RATE: u32 = 48_000;
+
+#[test]
+fn analyze_has_a_row_per_hop_ending_on_its_last_sample() {
+    // An impulse shows up in the first frame that reaches it, and stays for FFT_SIZE
+    let impulse = 5 * HOP_SIZE + 17;
+    let mut samples = vec![0.0; 20 * HOP_SIZE + HOP_SIZE / 2];
+    samples[impulse] = 1.0;
+
+    let (magnitudes, frequencies) = analyze(&samples);
+    assert_eq!(magnitudes.len(), 20 * BINS);
+    assert_eq!(frequencies.len(), magnitudes.len());
+    for (hop, row) in magnitudes.chunks_exact(BINS).enumerate() {
+        let end = (hop + 1) * HOP_SIZE - 1;
+        let in_frame = end >= impulse && end < impulse + FFT_SIZE;
+        assert_eq!(row.iter().any(|magnitude| *magnitude > 0.0), in_frame, "hop {hop}");
+    }
+}
+
+#[test]
+fn shift_takes_out_the_latency() {
+    let input: Vec<f32> = sine_sweep(SAMPLE_RATE)
+        .into_iter()
+        .take(SAMPLE_RATE as usize / 2)
+        .collect();
+
+    let output = shift(&input, 1.0, 0.0);
+    assert_eq!(output.len(), input.len());
+    let null = null_test(&input, &output);
+    assert_eq!(null.delay, 0);
+    assert!(null.residual_db < -80.0, "{null}");
+
+    // The same as processing in blocks and dropping the first LATENCY samples
+    let mut processor = AudioProcessor::new(0.8);
+    let mut padded = input.clone();
+    padded.resize(input.len() + LATENCY, 0.0);
+    let blocks: Vec<f32> = padded
+        .chunks(1000)
+        .flat_map(|block| process(&mut processor, block))
+        .collect();
+    assert_eq!(shift(&input, 0.8, 0.0), blocks[LATENCY..]);
+}
@@ -0,0 +1,98 @@
+"""Checks the built module, after `maturin develop -m python/Cargo.toml`."""
+
+import threading
+
+import numpy as np
+import pytest
+
+import vocoder
+
+SAMPLE_RATE = 48_000
+
+
+def sine(frequency, seconds=0.5):
+    t = np.arange(int(SAMPLE_RATE * seconds)) / SAMPLE_RATE
+    return np.sin(2 * np.pi * frequency * t)
+
+
+def test_process_matches_shift():
+    samples = sine(440.0)
+    processor = vocoder.Processor(1.5)
+    assert processor.latency == vocoder.LATENCY
+
+    padded = np.concatenate([samples, np.zeros(vocoder.LATENCY)])
+    blocks = np.concatenate([processor.process(block) for block in np.array_split(padded, 7)])
+    assert blocks.dtype == np.float32
+    np.testing.assert_array_equal(blocks[vocoder.LATENCY:], vocoder.shift(samples, 1.5))
+
+
+def test_reset_forgets_the_input():
+    samples = sine(440.0, 0.1).astype(np.float32)
+    processor = vocoder.Processor(0.75, formant_preservation=0.5)
+    first = processor.process(samples)
+    processor.reset()
+    np.testing.assert_array_equal(processor.process(samples), first)
+
+
+def test_pitch_shifts_that_are_not_positive_are_refused():
+    for pitch_shift in [0.0, -1.0, float("nan")]:
+        with pytest.raises(ValueError):
+            vocoder.Processor(pitch_shift)
+        with pytest.raises(ValueError):
+            vocoder.shift(sine(440.0, 0.1), pitch_shift)
+    processor = vocoder.Processor(1.5)
+    with pytest.raises(ValueError):
+        processor.set_pitch_shift(-1.0)
+
+
+def test_unity_reconstructs_the_input():
+    samples = sine(440.0)
+    output = vocoder.shift(samples, 1.0)
+    assert output.shape == samples.shape
+    # Past the first frame, where the overlap isn't complete yet
+    settled = slice(vocoder.FFT_SIZE, -vocoder.FFT_SIZE)
+    np.testing.assert_allclose(output[settled], samples[settled], atol=1e-3)
+
+
+def test_shift_moves_the_peak():
+    output = vocoder.shift(sine(440.0), 2.0)
+    spectrum = np.abs(np.fft.rfft(output[vocoder.FFT_SIZE:]))
+    peak = np.argmax(spectrum) * SAMPLE_RATE / (len(output) - vocoder.FFT_SIZE)
+    assert abs(peak - 880.0) < 5.0
+
+
+def test_analyze_shape_and_frequencies():
+    # 20.3 bins, between two bins
+    samples = np.sin(2 * np.pi * 20.3 * np.arange(8 * vocoder.FFT_SIZE) / vocoder.FFT_SIZE)
+    magnitudes, frequencies = vocoder.analyze(samples)
+    hops = len(samples) // vocoder.HOP_SIZE
+    assert magnitudes.shape == frequencies.shape == (hops, vocoder.FFT_SIZE // 2 + 1)
+
+    last = magnitudes[-1]
+    assert np.argmax(last) == 20
+    assert abs(frequencies[-1, 20] - 20.3) < 0.01
+
+
+def test_hann_windows():
+    window = vocoder.hann_window()
+    assert window.shape == (vocoder.FFT_SIZE,)
+    assert window[0] == 0.0 and window[-1] < 1e-6
+    np.testing.assert_allclose(window, vocoder.hann_window_table(), atol=1e-6)
+    np.testing.assert_allclose(window, np.hanning(vocoder.FFT_SIZE), atol=1e-6)
+
+
+def test_processors_run_in_threads():
+    samples = sine(440.0)
+    expected = vocoder.shift(samples, 1.25)
+    results = [None] * 4
+
+    def render(index):
+        results[index] = vocoder.shift(samples, 1.25)
+
+    threads = [threading.Thread(target=render, args=(index,)) for index in range(len(results))]
+    for thread in threads:
+        thread.start()
+    for thread in threads:
+        thread.join()
+    for result in results:
+        np.testing.assert_array_equal(result, expected)
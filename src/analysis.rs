@@ -0,0 +1,432 @@
+//! Objective quality metrics for rendered output, for comparing processing options by
+//! number rather than by ear.
+//!
+//! The metrics take a renderer, `render(input, pitch_shift) -> output`, so they work the
+//! same for `AudioProcessor`, `FixedPointProcessor` or anything else that processes a
+//! whole signal. Output is expected to be delayed against the input by a fixed latency,
+//! which `estimate_delay` finds by cross-correlation.
+
+use core::fmt;
+
+use libm::{atan2f, cosf, expf, log10f, logf, sinf, sqrtf};
+use microfft::Complex32;
+
+use crate::hann_window::HANN_WINDOW;
+
+const PI: f32 = core::f32::consts::PI;
+const FRAME_SIZE: usize = 1024;
+/// Frame size for pitch estimates, long enough to resolve the low end of the sweep
+const PITCH_FRAME_SIZE: usize = 4096;
+/// Samples at the start of a render to leave out, while the first frames fill up
+const STARTUP: usize = 2 * FRAME_SIZE;
+/// Longest latency `analyze` looks for
+const MAX_DELAY: usize = 4096;
+
+/// Everything `analyze` measures
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QualityReport {
+    /// Latency of the renderer in samples
+    pub delay: usize,
+    /// SNR of the output at unity shift against the input, in dB, the negated residual of
+    /// `null_test`. Higher is better.
+    pub unity_snr_db: f32,
+    /// Log-spectral distance of the output at unity shift from the input, in dB. Lower
+    /// is better.
+    pub log_spectral_distance_db: f32,
+    /// Mean error of the pitch of a shifted sine sweep, in cents
+    pub pitch_error_cents: f32,
+    /// Mean spread of shifted clicks, in milliseconds. Lower is sharper.
+    pub transient_spread_ms: f32,
+    /// Loss of phase coherence between neighbouring bins of spectral peaks in the shifted
+    /// output, from 0 for clean sinusoids to about 1 for unrelated phases
+    pub phasiness: f32,
+    /// `phasiness` of the input, since noisy input scores above 0 on its own
+    pub input_phasiness: f32,
+}
+
+impl fmt::Display for QualityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "delay:                  {} samples", self.delay)?;
+        writeln!(f, "unity SNR:              {:.1} dB", self.unity_snr_db)?;
+        writeln!(
+            f,
+            "log-spectral distance:  {:.2} dB",
+            self.log_spectral_distance_db
+        )?;
+        writeln!(
+            f,
+            "sweep pitch error:      {:.1} cents",
+            self.pitch_error_cents
+        )?;
+        writeln!(
+            f,
+            "transient spread:       {:.2} ms",
+            self.transient_spread_ms
+        )?;
+        write!(
+            f,
+            "phasiness:              {:.3} (input {:.3})",
+            self.phasiness, self.input_phasiness
+        )
+    }
+}
+
+/// How far output rendered at unity shift is from the input, once lined up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NullTest {
+    /// Latency of the renderer in samples
+    pub delay: usize,
+    /// Energy of the difference between output and input, relative to the input, in dB.
+    /// Lower is better.
+    pub residual_db: f32,
+    /// Largest difference between an output and an input sample
+    pub peak_residual: f32,
+}
+
+impl fmt::Display for NullTest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "delay:                  {} samples", self.delay)?;
+        writeln!(f, "residual:               {:.1} dB", self.residual_db)?;
+        write!(f, "peak residual:          {:.2e}", self.peak_residual)
+    }
+}
+
+/// Null `output`, rendered from `input` at unity shift, against `input`. At unity the
+/// phase vocoder should reconstruct its input, so anything left over points at the
+/// windows, the overlap scaling or the DC and Nyquist bins.
+pub fn null_test(input: &[f32], output: &[f32]) -> NullTest {
+    let delay = estimate_delay(input, output, MAX_DELAY);
+    let (reference, aligned) = align(input, output, delay);
+    let peak_residual = reference
+        .iter()
+        .zip(aligned)
+        .map(|(a, b)| (a - b).abs())
+        .fold(0.0, f32::max);
+    NullTest {
+        delay,
+        residual_db: -snr_db(reference, aligned),
+        peak_residual,
+    }
+}
+
+/// Run every metric. The unity metrics compare against `input`, the others render test
+/// signals generated at `sample_rate` with `pitch_shift`.
+pub fn analyze(
+    mut render: impl FnMut(&[f32], f32) -> Vec<f32>,
+    input: &[f32],
+    sample_rate: u32,
+    pitch_shift: f32,
+) -> QualityReport {
+    let unity = render(input, 1.0);
+    let null = null_test(input, &unity);
+    let delay = null.delay;
+    let (reference, aligned) = align(input, &unity, delay);
+
+    let sweep = sine_sweep(sample_rate);
+    let shifted_sweep = render(&sweep, pitch_shift);
+    let clicks = click_train();
+    let shifted_clicks = render(&clicks, pitch_shift);
+    let shifted = render(input, pitch_shift);
+
+    QualityReport {
+        delay,
+        unity_snr_db: -null.residual_db,
+        log_spectral_distance_db: log_spectral_distance_db(reference, aligned),
+        pitch_error_cents: pitch_error_cents(&sweep, &shifted_sweep, delay, pitch_shift),
+        transient_spread_ms: transient_spread_ms(&clicks, &shifted_clicks, delay, sample_rate),
+        phasiness: phasiness(&shifted[(delay + STARTUP).min(shifted.len())..]),
+        input_phasiness: phasiness(input),
+    }
+}
+
+/// The lag up to `max_delay` at which `output` best correlates with `input`
+pub fn estimate_delay(input: &[f32], output: &[f32], max_delay: usize) -> usize {
+    // A third of a second is plenty to find the peak
+    let len = input.len().min(output.len()).min(1 << 14);
+    let mut best = (0, f32::MIN);
+    for delay in 0..max_delay.min(len) {
+        let correlation: f32 = input[..len - delay]
+            .iter()
+            .zip(&output[delay..len])
+            .map(|(a, b)| a * b)
+            .sum();
+        if correlation > best.1 {
+            best = (delay, correlation);
+        }
+    }
+    best.0
+}
+
+/// `input` and `output` trimmed to the part they overlap at `delay`, less the startup
+fn align<'a>(input: &'a [f32], output: &'a [f32], delay: usize) -> (&'a [f32], &'a [f32]) {
+    let output = &output[(delay + STARTUP).min(output.len())..];
+    let input = &input[STARTUP.min(input.len())..];
+    let len = input.len().min(output.len());
+    (&input[..len], &output[..len])
+}
+
+/// Signal to noise ratio of `output`, taking `reference` as the signal
+pub fn snr_db(reference: &[f32], output: &[f32]) -> f32 {
+    let mut signal_power = 0.0;
+    let mut noise_power = 0.0;
+    for (reference, output) in reference.iter().zip(output) {
+        signal_power += reference * reference;
+        noise_power += (reference - output) * (reference - output);
+    }
+    10.0 * log10f(signal_power / noise_power)
+}
+
+/// Hann windowed spectra of `FRAME_SIZE` frames, hopping by half a frame
+fn spectra(samples: &[f32]) -> impl Iterator<Item = [Complex32; FRAME_SIZE / 2]> + '_ {
+    samples
+        .windows(FRAME_SIZE)
+        .step_by(FRAME_SIZE / 2)
+        .map(|frame| {
+            let mut windowed = [0.0; FRAME_SIZE];
+            for ((out, sample), window) in windowed.iter_mut().zip(frame).zip(HANN_WINDOW) {
+                *out = sample * window;
+            }
+            *microfft::real::rfft_1024(&mut windowed)
+        })
+}
+
+fn power(bin: &Complex32) -> f32 {
+    bin.re * bin.re + bin.im * bin.im
+}
+
+/// Root mean square difference of the power spectra in dB, averaged over frames. Bins
+/// more than 80 dB below the loudest one are floored, so silence doesn't dominate.
+pub fn log_spectral_distance_db(reference: &[f32], output: &[f32]) -> f32 {
+    let peak = spectra(reference)
+        .flat_map(|spectrum| spectrum.map(|bin| power(&bin)))
+        .fold(0.0, f32::max);
+    let floor = peak * 1e-8;
+
+    let mut total = 0.0;
+    let mut frames = 0;
+    for (reference, output) in spectra(reference).zip(spectra(output)) {
+        let mut squared = 0.0;
+        for (reference, output) in reference.iter().zip(&output) {
+            let difference = 10.0 * log10f((power(reference) + floor) / (power(output) + floor));
+            squared += difference * difference;
+        }
+        total += sqrtf(squared / (FRAME_SIZE / 2) as f32);
+        frames += 1;
+    }
+    total / frames.max(1) as f32
+}
+
+/// Four seconds of exponential sine sweep from 200 Hz to 3.2 kHz, low enough to shift
+/// up an octave at 44.1 kHz or more
+pub fn sine_sweep(sample_rate: u32) -> Vec<f32> {
+    let (start, end, seconds) = (200.0, 3200.0, 4.0);
+    let rate = logf(end / start) / seconds;
+    (0..(seconds * sample_rate as f32) as usize)
+        .map(|n| {
+            let t = n as f32 / sample_rate as f32;
+            0.5 * sinf(2.0 * PI * start * (expf(rate * t) - 1.0) / rate)
+        })
+        .collect()
+}
+
+/// Frequency of the strongest partial of a frame, in bins
+fn peak_bin(frame: &[f32], window: &[f32]) -> Option<f32> {
+    let mut windowed = [0.0; PITCH_FRAME_SIZE];
+    for ((out, sample), window) in windowed.iter_mut().zip(frame).zip(window) {
+        *out = sample * window;
+    }
+    let spectrum = microfft::real::rfft_4096(&mut windowed);
+    let magnitudes = spectrum.map(|bin| power(&bin));
+    let (peak, &peak_power) = magnitudes
+        .iter()
+        .enumerate()
+        .skip(1)
+        .take(magnitudes.len() - 2)
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+    if peak_power <= 0.0 {
+        return None;
+    }
+
+    // Parabolic interpolation of the log magnitude around the peak
+    let (left, centre, right) = (
+        logf(magnitudes[peak - 1] + 1e-20),
+        logf(peak_power),
+        logf(magnitudes[peak + 1] + 1e-20),
+    );
+    let offset = 0.5 * (left - right) / (left - 2.0 * centre + right);
+    Some(peak as f32 + offset)
+}
+
+/// Mean absolute error in cents between the pitch of `output` and `pitch_shift` times the
+/// pitch of `input` `delay` samples earlier, for a single partial like `sine_sweep`
+pub fn pitch_error_cents(input: &[f32], output: &[f32], delay: usize, pitch_shift: f32) -> f32 {
+    let window: Vec<f32> = (0..PITCH_FRAME_SIZE)
+        .map(|n| 0.5 * (1.0 - cosf(2.0 * PI * n as f32 / (PITCH_FRAME_SIZE - 1) as f32)))
+        .collect();
+
+    let mut total = 0.0;
+    let mut frames = 0;
+    let mut start = STARTUP;
+    while start + delay + PITCH_FRAME_SIZE <= output.len()
+        && start + PITCH_FRAME_SIZE <= input.len()
+    {
+        let input_frame = &input[start..start + PITCH_FRAME_SIZE];
+        let output_frame = &output[start + delay..start + delay + PITCH_FRAME_SIZE];
+        if let (Some(input_bin), Some(output_bin)) = (
+            peak_bin(input_frame, &window),
+            peak_bin(output_frame, &window),
+        ) {
+            let ratio = output_bin / (input_bin * pitch_shift);
+            total += (1200.0 * logf(ratio) / logf(2.0)).abs();
+            frames += 1;
+        }
+        start += PITCH_FRAME_SIZE / 4;
+    }
+    total / frames.max(1) as f32
+}
+
+/// Distance between the clicks of `click_train`
+const CLICK_SPACING: usize = 8192;
+
+/// Single sample clicks, spaced far enough apart for their responses not to overlap
+pub fn click_train() -> Vec<f32> {
+    let mut clicks = vec![0.0; 10 * CLICK_SPACING];
+    for n in (CLICK_SPACING / 2..clicks.len()).step_by(CLICK_SPACING) {
+        clicks[n] = 1.0;
+    }
+    clicks
+}
+
+/// Mean spread of the response to each click in `input`, as the standard deviation of its
+/// energy over time in milliseconds. A click that comes out as a click scores 0.
+pub fn transient_spread_ms(input: &[f32], output: &[f32], delay: usize, sample_rate: u32) -> f32 {
+    let half = CLICK_SPACING / 2;
+    let mut total = 0.0;
+    let mut clicks = 0;
+    for (position, _) in input.iter().enumerate().filter(|(_, x)| **x != 0.0) {
+        let centre = position + delay;
+        if centre < half + STARTUP || centre + half > output.len() {
+            continue;
+        }
+        let response = &output[centre - half..centre + half];
+        let energy: f32 = response.iter().map(|x| x * x).sum();
+        if energy <= 0.0 {
+            continue;
+        }
+        let mean = response
+            .iter()
+            .enumerate()
+            .map(|(n, x)| n as f32 * x * x)
+            .sum::<f32>()
+            / energy;
+        let variance = response
+            .iter()
+            .enumerate()
+            .map(|(n, x)| (n as f32 - mean) * (n as f32 - mean) * x * x)
+            .sum::<f32>()
+            / energy;
+        total += sqrtf(variance);
+        clicks += 1;
+    }
+    1000.0 * total / (clicks.max(1) as f32 * sample_rate as f32)
+}
+
+/// How far the phases of bins next to spectral peaks are from the relationship a
+/// stationary sinusoid gives them.
+///
+/// With a symmetric window starting at the frame start, every bin in the main lobe of a
+/// sinusoid has the phase of its neighbour less `pi * (N - 1) / N`. A phase vocoder
+/// advances each bin on its own, which breaks that, so phasiness is 1 less the magnitude
+/// weighted mean cosine of the error. Clean sinusoids score about 0, unrelated phases
+/// about 1.
+pub fn phasiness(output: &[f32]) -> f32 {
+    let expected = -PI * (FRAME_SIZE - 1) as f32 / FRAME_SIZE as f32;
+    let mut coherence = 0.0;
+    let mut weight = 0.0;
+    for spectrum in spectra(output) {
+        let powers = spectrum.map(|bin| power(&bin));
+        let threshold = powers.iter().cloned().fold(0.0, f32::max) * 1e-4;
+        for k in 2..powers.len() - 2 {
+            let is_peak =
+                powers[k] > threshold && powers[k] >= powers[k - 1] && powers[k] > powers[k + 1];
+            if !is_peak {
+                continue;
+            }
+            let phase = |bin: &Complex32| atan2f(bin.im, bin.re);
+            for neighbour in [k - 1, k + 1] {
+                let (low, high) = (neighbour.min(k), neighbour.max(k));
+                let error = phase(&spectrum[high]) - phase(&spectrum[low]) - expected;
+                let magnitude = sqrtf(powers[neighbour]);
+                coherence += magnitude * cosf(error);
+                weight += magnitude;
+            }
+        }
+    }
+    if weight == 0.0 {
+        return 0.0;
+    }
+    1.0 - coherence / weight
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48_000;
+
+    fn delayed(input: &[f32], delay: usize) -> Vec<f32> {
+        let mut output = vec![0.0; delay];
+        output.extend_from_slice(input);
+        output.truncate(input.len());
+        output
+    }
+
+    #[test]
+    fn finds_the_delay_of_a_sweep() {
+        let sweep = sine_sweep(SAMPLE_RATE);
+        assert_eq!(
+            estimate_delay(&sweep, &delayed(&sweep, 1023), MAX_DELAY),
+            1023
+        );
+    }
+
+    #[test]
+    fn delayed_copy_scores_perfectly() {
+        let sweep = sine_sweep(SAMPLE_RATE);
+        let report = analyze(|input, _| delayed(input, 300), &sweep, SAMPLE_RATE, 1.0);
+        assert_eq!(report.delay, 300);
+        assert!(report.unity_snr_db > 100.0, "{report}");
+        assert!(report.log_spectral_distance_db < 0.01, "{report}");
+        assert!(report.pitch_error_cents < 2.0, "{report}");
+        assert!(report.transient_spread_ms < 0.01, "{report}");
+        assert!(report.phasiness < 0.05, "{report}");
+    }
+
+    #[test]
+    fn pitch_error_measures_the_wrong_ratio() {
+        let sweep = sine_sweep(SAMPLE_RATE);
+        // Claiming the unshifted sweep was shifted up a semitone is 100 cents out
+        let error = pitch_error_cents(&sweep, &sweep, 0, 2.0f32.powf(1.0 / 12.0));
+        assert!((error - 100.0).abs() < 2.0, "{error}");
+    }
+
+    #[test]
+    fn noise_is_phasier_than_sinusoids() {
+        let chord: Vec<f32> = (0..1 << 15)
+            .map(|n| {
+                (1..=4)
+                    .map(|k| 0.2 * sinf(0.05 * k as f32 * n as f32 + k as f32))
+                    .sum()
+            })
+            .collect();
+        let mut seed = 1u32;
+        let noise: Vec<f32> = (0..1 << 15)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                seed as f32 / u32::MAX as f32 - 0.5
+            })
+            .collect();
+        assert!(phasiness(&chord) < 0.01, "{}", phasiness(&chord));
+        assert!(phasiness(&noise) > 0.1, "{}", phasiness(&noise));
+    }
+}
@@ -0,0 +1,318 @@
+//! Audio files in and out of the command line modes, whatever their format.
+//!
+//! WAV goes through hound, FLAC and AIFF are decoded by symphonia and FLAC is encoded by
+//! flacenc, all pure Rust. Raw PCM is read and written like `stream` does. A file's
+//! format comes from its extension unless one is given, which raw PCM always has to be.
+//! Samples are read as `f32` per channel, and `Audio` keeps the file's sample format so
+//! the output can be written the way the input was.
+
+use std::fs::File;
+use std::path::Path;
+
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+
+use crate::stream::{Encoding, StreamSpec, DEFAULT_RAW_SPEC};
+
+/// A whole file, decoded
+#[derive(Clone, Debug, PartialEq)]
+pub struct Audio {
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    pub sample_format: SampleFormat,
+    /// Samples of each channel, all the same length
+    pub channels: Vec<Vec<f32>>,
+}
+
+impl Audio {
+    /// `channels` in place of these, in the same format
+    pub fn with_channels(&self, channels: Vec<Vec<f32>>) -> Audio {
+        Audio { channels, ..*self }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.channels.first().map_or(0, Vec::len)
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.frames() as f64 / self.sample_rate as f64
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    Wav,
+    Flac,
+    /// Read only
+    Aiff,
+    /// Headerless interleaved PCM, which says nothing about itself. Reading takes the
+    /// rate and channels from the spec, writing only its encoding.
+    Raw(StreamSpec),
+}
+
+impl std::str::FromStr for FileFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<FileFormat, String> {
+        match format.to_ascii_lowercase().as_str() {
+            "wav" => Ok(FileFormat::Wav),
+            "flac" => Ok(FileFormat::Flac),
+            "aiff" | "aif" => Ok(FileFormat::Aiff),
+            "f32" => Ok(FileFormat::Raw(DEFAULT_RAW_SPEC)),
+            "s16" => Ok(FileFormat::Raw(StreamSpec {
+                encoding: Encoding::S16,
+                ..DEFAULT_RAW_SPEC
+            })),
+            _ => Err(format!(
+                "unknown format {format}, expected wav, flac, aiff, f32 or s16"
+            )),
+        }
+    }
+}
+
+impl FileFormat {
+    /// The format `path`'s extension names
+    pub fn from_path(path: &Path) -> Result<FileFormat, String> {
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        extension
+            .parse()
+            .map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    pub fn read(self, path: &Path) -> Result<Audio, String> {
+        match self {
+            FileFormat::Wav => read_wav(path),
+            FileFormat::Flac => decode(path, "flac"),
+            FileFormat::Aiff => decode(path, "aiff"),
+            FileFormat::Raw(spec) => read_raw(path, spec),
+        }
+    }
+
+    pub fn write(self, path: &Path, audio: &Audio) -> Result<(), String> {
+        match self {
+            FileFormat::Wav => write_wav(path, audio),
+            FileFormat::Flac => write_flac(path, audio),
+            FileFormat::Aiff => Err("AIFF can be read but not written".to_string()),
+            FileFormat::Raw(spec) => write_raw(path, spec.encoding, audio),
+        }
+    }
+}
+
+/// Read `path` as `format`, or as its extension says
+pub fn read(path: &Path, format: Option<FileFormat>) -> Result<Audio, String> {
+    match format {
+        Some(format) => format.read(path),
+        None => FileFormat::from_path(path)?.read(path),
+    }
+}
+
+/// Write `audio` to `path` as `format`, or as its extension says
+pub fn write(path: &Path, format: Option<FileFormat>, audio: &Audio) -> Result<(), String> {
+    match format {
+        Some(format) => format.write(path, audio),
+        None => FileFormat::from_path(path)?.write(path, audio),
+    }
+}
+
+/// Full scale of integer samples of `bits`
+fn int_scale(bits: u16) -> f32 {
+    (1u32 << (bits - 1)) as f32
+}
+
+fn quantize(sample: f32, scale: f32) -> i32 {
+    (sample * scale).round().clamp(-scale, scale - 1.0) as i32
+}
+
+fn read_wav(path: &Path) -> Result<Audio, String> {
+    let mut reader = WavReader::open(path).map_err(|err| err.to_string())?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>(),
+        SampleFormat::Int => {
+            let scale = int_scale(spec.bits_per_sample);
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 / scale))
+                .collect::<Result<_, _>>()
+        }
+    }
+    .map_err(|err| err.to_string())?;
+    let channels = spec.channels as usize;
+    Ok(Audio {
+        sample_rate: spec.sample_rate,
+        bits_per_sample: spec.bits_per_sample,
+        sample_format: spec.sample_format,
+        channels: (0..channels)
+            .map(|channel| {
+                let samples = samples.iter().skip(channel).step_by(channels);
+                samples.copied().collect()
+            })
+            .collect(),
+    })
+}
+
+fn write_wav(path: &Path, audio: &Audio) -> Result<(), String> {
+    let spec = WavSpec {
+        channels: audio.channels.len() as u16,
+        sample_rate: audio.sample_rate,
+        bits_per_sample: audio.bits_per_sample,
+        sample_format: audio.sample_format,
+    };
+    let mut writer = WavWriter::create(path, spec).map_err(|err| err.to_string())?;
+    let scale = int_scale(audio.bits_per_sample);
+    for frame in 0..audio.frames() {
+        for channel in &audio.channels {
+            match audio.sample_format {
+                SampleFormat::Float => writer.write_sample(channel[frame]),
+                SampleFormat::Int => writer.write_sample(quantize(channel[frame], scale)),
+            }
+            .map_err(|err| err.to_string())?;
+        }
+    }
+    writer.finalize().map_err(|err| err.to_string())
+}
+
+/// A trailing partial frame is dropped
+fn read_raw(path: &Path, spec: StreamSpec) -> Result<Audio, String> {
+    if spec.channels == 0 {
+        return Err("no channels".to_string());
+    }
+    let bytes = std::fs::read(path).map_err(|err| err.to_string())?;
+    let channel_count = spec.channels as usize;
+    let sample_bytes = spec.encoding.bytes();
+    let mut channels = vec![Vec::new(); channel_count];
+    for frame in bytes.chunks_exact(channel_count * sample_bytes) {
+        for (channel, bytes) in channels.iter_mut().zip(frame.chunks_exact(sample_bytes)) {
+            channel.push(spec.encoding.decode(bytes));
+        }
+    }
+    let (sample_format, bits_per_sample) = match spec.encoding {
+        Encoding::F32 => (SampleFormat::Float, 32),
+        Encoding::S16 => (SampleFormat::Int, 16),
+    };
+    Ok(Audio {
+        sample_rate: spec.sample_rate,
+        bits_per_sample,
+        sample_format,
+        channels,
+    })
+}
+
+fn write_raw(path: &Path, encoding: Encoding, audio: &Audio) -> Result<(), String> {
+    let mut bytes = Vec::with_capacity(audio.frames() * audio.channels.len() * encoding.bytes());
+    for frame in 0..audio.frames() {
+        for channel in &audio.channels {
+            encoding.encode(channel[frame], &mut bytes);
+        }
+    }
+    std::fs::write(path, bytes).map_err(|err| err.to_string())
+}
+
+/// Decode the first audio track of a file symphonia can read
+fn decode(path: &Path, extension: &str) -> Result<Audio, String> {
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::errors::Error;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+    use symphonia::core::sample;
+
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(extension);
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|err| err.to_string())?;
+    let mut reader = probed.format;
+    let track = reader.default_track().ok_or("no audio track")?;
+    let track_id = track.id;
+    let params = &track.codec_params;
+    let sample_rate = params.sample_rate.ok_or("no sample rate")?;
+    let channel_count = params.channels.ok_or("no channel layout")?.count();
+    let (sample_format, bits_per_sample) = match params.sample_format {
+        Some(sample::SampleFormat::F32 | sample::SampleFormat::F64) => (SampleFormat::Float, 32),
+        _ => (
+            SampleFormat::Int,
+            params.bits_per_sample.unwrap_or(16) as u16,
+        ),
+    };
+    let mut decoder = symphonia::default::get_codecs()
+        .make(params, &DecoderOptions::default())
+        .map_err(|err| err.to_string())?;
+
+    let mut channels = vec![Vec::new(); channel_count];
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            // How symphonia says the file has ended
+            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.to_string()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = decoder.decode(&packet).map_err(|err| err.to_string())?;
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+        buffer.copy_interleaved_ref(decoded);
+        for frame in buffer.samples().chunks_exact(channel_count) {
+            for (channel, sample) in channels.iter_mut().zip(frame) {
+                channel.push(*sample);
+            }
+        }
+    }
+    Ok(Audio {
+        sample_rate,
+        bits_per_sample,
+        sample_format,
+        channels,
+    })
+}
+
+/// FLAC holds integer samples of up to 24 bits, so float and wider audio is written as
+/// 24 bit
+fn write_flac(path: &Path, audio: &Audio) -> Result<(), String> {
+    use flacenc::component::BitRepr;
+    use flacenc::error::Verify;
+
+    let bits = match audio.sample_format {
+        SampleFormat::Int => audio.bits_per_sample.min(24),
+        SampleFormat::Float => 24,
+    };
+    let scale = int_scale(bits);
+    let samples: Vec<i32> = (0..audio.frames())
+        .flat_map(|frame| {
+            let channels = audio.channels.iter();
+            channels.map(move |channel| quantize(channel[frame], scale))
+        })
+        .collect();
+    let source = flacenc::source::MemSource::from_samples(
+        &samples,
+        audio.channels.len(),
+        bits as usize,
+        audio.sample_rate as usize,
+    );
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|(_, err)| err.to_string())?;
+    let mut flac = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|err| err.to_string())?;
+    // The header's block sizes leave out the shorter last block, as the format says.
+    // flacenc counts it, and gives the configured size even when no block is that long,
+    // which symphonia won't read.
+    let block_size = audio.frames().min(config.block_size);
+    if block_size > 0 {
+        let info = flac.stream_info_mut();
+        info.set_block_sizes(block_size, block_size)
+            .map_err(|err| err.to_string())?;
+    }
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    flac.write(&mut sink).map_err(|err| err.to_string())?;
+    std::fs::write(path, sink.as_slice()).map_err(|err| err.to_string())
+}
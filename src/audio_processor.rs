@@ -0,0 +1,622 @@
+use crate::circular_buffer::CircularBuffer;
+use crate::config::DefaultConfig;
+use crate::math::{DefaultMath, MathBackend};
+use crate::{hann_window, irfft};
+
+use libm::{floorf, fmodf};
+use microfft::Complex32;
+
+use core::iter;
+use core::marker::PhantomData;
+use core::ptr::{self, addr_of_mut};
+
+const PI: f32 = core::f32::consts::PI;
+pub const FFT_SIZE: usize = DefaultConfig::FFT_SIZE;
+pub const HOP_SIZE: usize = DefaultConfig::HOP_SIZE;
+pub const BUFFER_SIZE: usize = DefaultConfig::BUFFER_SIZE;
+/// Bins from DC to Nyquist in the spectrum of a real frame
+pub const BINS: usize = FFT_SIZE / 2 + 1;
+/// `rfft_1024` packs the real Nyquist bin into the imaginary part of the real DC bin
+const NYQUIST: usize = FFT_SIZE / 2;
+
+/// Samples between an input sample going in and the output it affects coming out. Each
+/// frame ends on the sample before the one that triggers its hop and starts coming out
+/// straight away, so the delay is a whole frame.
+pub const LATENCY: usize = FFT_SIZE;
+
+/// Run `input` through `process`, one processor's `process_sample`, with the latency
+/// taken out: the last `LATENCY` items are flushed out with `silence` and the first
+/// `LATENCY` out are dropped, so the output lines up with `input` and is as long. Items
+/// can be samples of any processor with this latency, `FixedPointProcessor`'s included,
+/// or whole frames of one processor per channel.
+pub fn render_aligned<T: Clone, O>(
+    input: impl IntoIterator<Item = T>,
+    silence: T,
+    process: impl FnMut(T) -> O,
+) -> impl Iterator<Item = O> {
+    input
+        .into_iter()
+        .chain(iter::repeat_n(silence, LATENCY))
+        .map(process)
+        .skip(LATENCY)
+}
+
+/// Shift a whole channel with a new processor, with the latency taken out. Every offline
+/// render goes through this, from the binary's modes to batch and the tests.
+#[cfg(all(feature = "std", not(feature = "fixed-point")))]
+pub fn shift_channel(samples: &[f32], pitch_shift: f32) -> Vec<f32> {
+    shift_channel_with::<DefaultMath>(samples, pitch_shift)
+}
+
+/// Same as above, but running the fixed point pipeline the way an FPU-less MCU would
+#[cfg(all(feature = "std", feature = "fixed-point"))]
+pub fn shift_channel(samples: &[f32], pitch_shift: f32) -> Vec<f32> {
+    use crate::fixed_point::Q15;
+    use crate::fixed_processor::{pitch_shift_to_q16, FixedPointProcessor};
+
+    let mut processor = Box::new(FixedPointProcessor::new(pitch_shift_to_q16(pitch_shift)));
+    let samples = samples.iter().map(|sample| Q15::from_f32(*sample));
+    render_aligned(samples, Q15::ZERO, |sample| {
+        processor.process_sample(sample).to_f32()
+    })
+    .collect()
+}
+
+/// `shift_channel` on the float pipeline with math backend `M`, whatever the features, for
+/// comparing against output that has to stay put
+#[cfg(feature = "std")]
+pub fn shift_channel_with<M: MathBackend>(samples: &[f32], pitch_shift: f32) -> Vec<f32> {
+    let mut processor = Box::new(AudioProcessor::<M>::with_backend(pitch_shift));
+    render_aligned(samples.iter().copied(), 0.0, |sample| {
+        processor.process_sample(sample)
+    })
+    .collect()
+}
+
+/// A pitch shift the processor can't use. Zero, negative and non-finite ratios round
+/// every bin down to DC, so the output would be silence or a DC offset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InvalidPitchShift(pub f32);
+
+impl core::fmt::Display for InvalidPitchShift {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "pitch shift {} isn't a positive ratio", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidPitchShift {}
+
+/// `pitch_shift` if it's a ratio the processor can shift by, which every interface that
+/// takes one from outside checks it with
+pub fn check_pitch_shift(pitch_shift: f32) -> Result<f32, InvalidPitchShift> {
+    if pitch_shift.is_finite() && pitch_shift > 0.0 {
+        Ok(pitch_shift)
+    } else {
+        Err(InvalidPitchShift(pitch_shift))
+    }
+}
+
+/// Gain of overlap-adding frames windowed twice by `HANN_WINDOW` every `HOP_SIZE`
+/// samples, which the output is divided by. Hann squared isn't quite constant overlap-add
+/// at these sizes, but the ripple is below -100 dB.
+pub const OVERLAP_GAIN: f32 = {
+    let mut sum = 0.0;
+    let mut n = 0;
+    while n < FFT_SIZE {
+        sum += hann_window::HANN_WINDOW[n] * hann_window::HANN_WINDOW[n];
+        n += 1;
+    }
+    sum / HOP_SIZE as f32
+};
+
+/// Working memory for one hop. It lives in the processor so `process_fft` doesn't put
+/// ~14 KB of arrays on the stack every hop.
+struct Scratch {
+    unwrapped_buffer: [f32; FFT_SIZE],
+    analysis_magnitudes: [f32; BINS],
+    analysis_frequencies: [f32; BINS],
+    synthesis_magnitudes: [f32; BINS],
+    synthesis_frequencies: [f32; BINS],
+    envelope: [f32; BINS],
+}
+
+/// Bins either side of each bin averaged into the spectral envelope. Wide enough to
+/// smooth over the harmonics of a voice, narrow enough to keep its formants.
+const ENVELOPE_RADIUS: usize = 8;
+
+/// Phase vocoder pitch shifter, fed and drained one sample at a time.
+///
+/// All state and per-hop scratch memory is owned by the processor, so processing never
+/// allocates. In a release build for `thumbv7em-none-eabihf`, `process_sample` needs at
+/// most 512 bytes of stack including the hop that runs the FFTs (480 bytes measured
+/// with `-Zemit-stack-sizes`, with either math backend), which makes it safe to call
+/// from an interrupt or a small RTOS task. The processor itself is about 28 KB, so give
+/// it a `static` or a heap allocation rather than putting it on a small stack.
+pub struct AudioProcessor<M: MathBackend = DefaultMath> {
+    in_buffer: CircularBuffer<f32, BUFFER_SIZE>,
+    out_buffer: CircularBuffer<f32, BUFFER_SIZE>,
+    last_input_phases: [f32; BINS],
+    last_output_phases: [f32; BINS],
+    twiddles: [Complex32; FFT_SIZE / 4],
+    scratch: Scratch,
+    hop_counter: usize,
+    hops: usize,
+    pitch_shift: f32,
+    formant_preservation: f32,
+    math: PhantomData<M>,
+}
+
+impl AudioProcessor {
+    pub fn new(pitch_shift: f32) -> AudioProcessor {
+        AudioProcessor::with_backend(pitch_shift)
+    }
+}
+
+impl<M: MathBackend> AudioProcessor<M> {
+    /// Create a processor with an explicit math backend, for comparing backends in one build
+    pub fn with_backend(pitch_shift: f32) -> AudioProcessor<M> {
+        // The first hop runs once HOP_SIZE samples have been read, so start adding frames
+        // a hop ahead of the read index
+        let mut out_buffer = CircularBuffer::new(0.0, Some(HOP_SIZE));
+        out_buffer.next_hop();
+
+        AudioProcessor {
+            in_buffer: CircularBuffer::new(0.0, Some(0)),
+            out_buffer,
+            last_input_phases: [0.0; BINS],
+            last_output_phases: [0.0; BINS],
+            twiddles: irfft::generate_twiddles(),
+            scratch: Scratch {
+                unwrapped_buffer: [0.0; FFT_SIZE],
+                analysis_magnitudes: [0.0; BINS],
+                analysis_frequencies: [0.0; BINS],
+                synthesis_magnitudes: [0.0; BINS],
+                synthesis_frequencies: [0.0; BINS],
+                envelope: [0.0; BINS],
+            },
+            hop_counter: 0,
+            hops: 0,
+            pitch_shift,
+            formant_preservation: 0.0,
+            math: PhantomData,
+        }
+    }
+
+    /// `with_backend`, written straight into `processor` field by field. Building a
+    /// processor and moving it in puts all of it on the stack first, which storage
+    /// handed over from C or a small thread can't afford.
+    ///
+    /// # Safety
+    /// `processor` must be valid for writes and aligned. What it held isn't dropped.
+    pub unsafe fn init_in_place(processor: *mut Self, pitch_shift: f32) {
+        CircularBuffer::init_in_place(addr_of_mut!((*processor).in_buffer), 0.0, Some(0));
+        let out_buffer = addr_of_mut!((*processor).out_buffer);
+        CircularBuffer::init_in_place(out_buffer, 0.0, Some(HOP_SIZE));
+        (*out_buffer).next_hop();
+        // The arrays are all f32, which are 0.0 as zero bytes, so they're zeroed in place
+        // rather than written from zeroed arrays on the stack
+        ptr::write_bytes(addr_of_mut!((*processor).last_input_phases), 0, 1);
+        ptr::write_bytes(addr_of_mut!((*processor).last_output_phases), 0, 1);
+        ptr::write_bytes(addr_of_mut!((*processor).scratch), 0, 1);
+        let twiddles = addr_of_mut!((*processor).twiddles);
+        ptr::write_bytes(twiddles, 0, 1);
+        irfft::fill_twiddles(&mut *twiddles);
+        addr_of_mut!((*processor).hop_counter).write(0);
+        addr_of_mut!((*processor).hops).write(0);
+        addr_of_mut!((*processor).pitch_shift).write(pitch_shift);
+        addr_of_mut!((*processor).formant_preservation).write(0.0);
+        addr_of_mut!((*processor).math).write(PhantomData);
+    }
+
+    /// Clear all audio history, as if the processor had just been created with its
+    /// current settings. Everything is cleared in place, so unlike assigning a new
+    /// processor this doesn't put one on the stack.
+    pub fn reset(&mut self) {
+        self.in_buffer.reset();
+        self.out_buffer.reset();
+        self.out_buffer.next_hop();
+        self.last_input_phases.fill(0.0);
+        self.last_output_phases.fill(0.0);
+        let scratch = &mut self.scratch;
+        scratch.unwrapped_buffer.fill(0.0);
+        scratch.analysis_magnitudes.fill(0.0);
+        scratch.analysis_frequencies.fill(0.0);
+        scratch.synthesis_magnitudes.fill(0.0);
+        scratch.synthesis_frequencies.fill(0.0);
+        scratch.envelope.fill(0.0);
+        self.hop_counter = 0;
+        self.hops = 0;
+    }
+
+    /// Ratio of output to input pitch, from the next hop on. Check ratios from outside
+    /// with `check_pitch_shift` first.
+    pub fn set_pitch_shift(&mut self, pitch_shift: f32) {
+        self.pitch_shift = pitch_shift;
+    }
+
+    /// How much of the spectral envelope stays where it was, from 0.0 where formants
+    /// move with the pitch to 1.0 where they stay put, so a shifted voice keeps its
+    /// character. The envelope is a moving average of the magnitudes, which is cheap but
+    /// only roughly follows the formants.
+    pub fn set_formant_preservation(&mut self, formant_preservation: f32) {
+        self.formant_preservation = formant_preservation.clamp(0.0, 1.0);
+    }
+
+    /// Delay of the output in samples, see `LATENCY`
+    pub fn latency(&self) -> usize {
+        LATENCY
+    }
+
+    /// Hops run so far, wrapping on overflow. It goes up on every `HOP_SIZE`th sample
+    /// after the first, and `analysis` changes with it.
+    pub fn hops(&self) -> usize {
+        self.hops
+    }
+
+    /// Magnitudes and frequencies of each bin from DC to Nyquist in the last hop's input
+    /// frame, before shifting. Frequencies are in bins, with the measured deviation from the bin's
+    /// centre added. The frame is the `FFT_SIZE` samples up to the one before the hop's.
+    pub fn analysis(&self) -> (&[f32; BINS], &[f32; BINS]) {
+        (
+            &self.scratch.analysis_magnitudes,
+            &self.scratch.analysis_frequencies,
+        )
+    }
+
+    /// Push one input sample and get one output sample back, running the FFTs once
+    /// every `HOP_SIZE` samples.
+    pub fn process_sample(&mut self, sample: f32) -> f32 {
+        // Store the sample in the input buffer
+        self.in_buffer.write(sample);
+
+        // Run the hop before reading, so each frame is added from the sample about to be
+        // read rather than behind the read index
+        if self.hop_counter >= HOP_SIZE {
+            self.hop_counter = 0;
+            self.hops = self.hops.wrapping_add(1);
+            self.process_fft();
+            // update the output buffer write index to the start of the next hop
+            self.out_buffer.next_hop();
+            // The output buffer is read before the first frame is added, so only its
+            // overruns mean anything
+            debug_assert!(
+                self.in_buffer.check().is_ok() && self.out_buffer.overruns() == 0,
+                "BUFFER_SIZE is too small for FFT_SIZE and HOP_SIZE"
+            );
+        }
+        self.hop_counter += 1;
+
+        // Read from the output buffer and reset the value
+        let out_sample = self.out_buffer.read_and_reset();
+
+        // Scale the output down by the overlap factor
+        out_sample / OVERLAP_GAIN
+    }
+
+    fn process_fft(&mut self) {
+        let analysis_window_buffer: [f32; FFT_SIZE] = hann_window::HANN_WINDOW;
+
+        let Scratch {
+            unwrapped_buffer,
+            analysis_magnitudes,
+            analysis_frequencies,
+            synthesis_magnitudes,
+            synthesis_frequencies,
+            envelope,
+        } = &mut self.scratch;
+        let in_buffer = &mut self.in_buffer;
+        let last_input_phases = &mut self.last_input_phases;
+        let last_output_phases = &mut self.last_output_phases;
+        let pitch_shift = self.pitch_shift;
+        let formant_preservation = self.formant_preservation;
+
+        // copy buffer into FFT input, starting one window ago
+        in_buffer.push_read_back(FFT_SIZE - HOP_SIZE);
+        in_buffer.read_into(unwrapped_buffer);
+        for (value, window) in unwrapped_buffer.iter_mut().zip(analysis_window_buffer.iter()) {
+            *value *= window;
+        }
+
+        // Process the FFT based on the time domain input
+        let fft = microfft::real::rfft_1024(unwrapped_buffer);
+
+        // ANALYSIS
+        for i in 0..BINS {
+            // Turn real and imaginary components into amplitude and phase
+            let bin = unpack_bin(fft, i);
+            let amplitude = M::sqrt(bin.re * bin.re + bin.im * bin.im);
+            let phase = M::atan2(bin.im, bin.re);
+
+            // Calculate the phase difference in this bin between the last
+            // hop and this one, which will indirectly give us the exact frequency
+            let mut phase_diff = phase - last_input_phases[i];
+
+            // Subtract the amount of phase increment we'd expect to see based
+            // on the centre frequency of this bin (2*pi*n/gFftSize) for this
+            // hop size, then wrap to the range -pi to pi
+            phase_diff = wrap_phase(phase_diff - expected_phase_advance(i));
+
+            // Find deviation from the centre frequency
+            let bin_deviation = phase_diff * FFT_SIZE as f32 / HOP_SIZE as f32 / (2.0 * PI);
+
+            // Add the original bin number to get the fractional bin where this partial belongs
+            analysis_frequencies[i] = i as f32 + bin_deviation;
+            // Save the magnitude for later
+            analysis_magnitudes[i] = amplitude;
+            // Save the phase for next hop
+            last_input_phases[i] = phase;
+        }
+
+        // Zero out the synthesis bins, ready for new data, since the scratch buffers are reused
+        synthesis_magnitudes.fill(0.0);
+        synthesis_frequencies.fill(0.0);
+
+        if formant_preservation > 0.0 {
+            spectral_envelope(analysis_magnitudes, envelope);
+        }
+
+        // Handle the pitch shift, storing frequencies into new bins
+        for i in 0..BINS {
+            // find the nearest bin to the shifted frequency
+            let new_bin = floorf(i as f32  * pitch_shift + 0.5) as usize;
+
+            // Ignore any bins that have shifted above Nyquist
+            if new_bin < BINS {
+                let mut magnitude = analysis_magnitudes[i];
+                // Move the partial from the level of the envelope where it was to the
+                // level where it lands
+                if formant_preservation > 0.0 && envelope[i] > 0.0 {
+                    let correction = envelope[new_bin] / envelope[i];
+                    magnitude *= 1.0 + formant_preservation * (correction - 1.0);
+                }
+                synthesis_magnitudes[new_bin] += magnitude;
+                synthesis_frequencies[new_bin] = analysis_frequencies[i] * pitch_shift;
+            }
+        }
+
+        // SYNTHESIS
+        for i in 0..BINS {
+            let amplitude = synthesis_magnitudes[i];
+            // Get the fractional offset from the bin centre frequency
+
+            let bin_deviation = synthesis_frequencies[i] - i as f32;
+            // Multiply to get back to a phase value
+            let mut phase_diff = bin_deviation * 2.0 * PI * HOP_SIZE as f32 /FFT_SIZE as f32;
+            // Add the expected phase increment based on the bin centre frequency
+            phase_diff += expected_phase_advance(i);
+            // Advance the phase from the previous hop
+            let out_phase = wrap_phase(last_output_phases[i] + phase_diff);
+
+            // Now convert magnitude and phase back to real and imaginary components
+            let bin = Complex32 {
+                re: amplitude * M::cos(out_phase),
+                im: amplitude * M::sin(out_phase),
+            };
+            pack_bin(fft, i, bin);
+
+            // Save the phase for the next hop
+            last_output_phases[i] = out_phase;
+        }
+
+        // Run the inverse real FFT, which only needs the half spectrum
+        let res = irfft::irfft_1024(fft, &self.twiddles);
+
+        // Window again and add time domain into the output buffer
+        for (val, window) in res.iter_mut().zip(analysis_window_buffer.iter()) {
+            *val *= window;
+        }
+        self.out_buffer.add_slice(res);
+    }
+}
+
+/// Bin `i` of a packed half spectrum, with DC and Nyquist as real bins of their own
+fn unpack_bin(fft: &[Complex32; FFT_SIZE / 2], i: usize) -> Complex32 {
+    match i {
+        0 => Complex32 { re: fft[0].re, im: 0.0 },
+        NYQUIST => Complex32 { re: fft[0].im, im: 0.0 },
+        _ => fft[i],
+    }
+}
+
+/// Store bin `i` into a packed half spectrum. DC and Nyquist are real, so only the real
+/// part of `bin` is kept for them.
+fn pack_bin(fft: &mut [Complex32; FFT_SIZE / 2], i: usize, bin: Complex32) {
+    match i {
+        0 => fft[0].re = bin.re,
+        NYQUIST => fft[0].im = bin.re,
+        _ => fft[i] = bin,
+    }
+}
+
+/// Moving average of `magnitudes` over `ENVELOPE_RADIUS` bins either side
+fn spectral_envelope(magnitudes: &[f32; BINS], envelope: &mut [f32; BINS]) {
+    let mut sum: f32 = magnitudes[..ENVELOPE_RADIUS].iter().sum();
+    for (i, value) in envelope.iter_mut().enumerate() {
+        if let Some(entering) = magnitudes.get(i + ENVELOPE_RADIUS) {
+            sum += entering;
+        }
+        if i > ENVELOPE_RADIUS {
+            sum -= magnitudes[i - ENVELOPE_RADIUS - 1];
+        }
+        let width = (i + ENVELOPE_RADIUS).min(BINS - 1) + 1 - i.saturating_sub(ENVELOPE_RADIUS);
+        *value = sum / width as f32;
+    }
+}
+
+/// Phase advance over one hop at the centre frequency of `bin`, `2 * pi * bin * HOP_SIZE
+/// / FFT_SIZE`, less whole turns. Taking the turns off in integers first keeps the
+/// precision f32 would lose on the ~400 radians of the top bins.
+fn expected_phase_advance(bin: usize) -> f32 {
+    2.0 * PI * ((bin * HOP_SIZE) % FFT_SIZE) as f32 / FFT_SIZE as f32
+}
+
+/// Wrap a phase into the range -pi to pi, excluding pi
+pub fn wrap_phase(phase_in: f32) -> f32 {
+    if phase_in >= 0.0 {
+        return fmodf(phase_in + PI, 2.0 * PI) - PI;
+    }
+    let wrapped = fmodf(phase_in - PI, -2.0 * PI) + PI;
+    // Odd multiples of -pi come out as pi here, fold them back to -pi
+    if wrapped >= PI {
+        return -PI;
+    }
+    wrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// The period `wrap_phase` actually wraps with, `2.0 * PI` rounded to f32
+    const TWO_PI: f64 = (2.0 * PI) as f64;
+
+    proptest! {
+        #[test]
+        fn wrap_phase_lands_in_range(phase in -1000.0f32..1000.0) {
+            let wrapped = wrap_phase(phase);
+            prop_assert!((-PI..PI).contains(&wrapped), "{phase} wrapped to {wrapped}");
+        }
+
+        #[test]
+        fn wrap_phase_preserves_the_angle(phase in -1000.0f32..1000.0) {
+            let turns = (wrap_phase(phase) as f64 - phase as f64) / TWO_PI;
+            // Adding and taking away PI rounds to the precision of the input
+            let error = (turns - turns.round()) * TWO_PI;
+            prop_assert!(error.abs() <= 1e-6 + phase.abs() as f64 * 1e-6, "{phase}: {error}");
+        }
+    }
+
+    #[test]
+    fn reset_matches_a_new_processor() {
+        let input: Vec<f32> = (0..4 * FFT_SIZE + 77)
+            .map(|n| libm::sinf(0.05 * n as f32) + 0.3)
+            .collect();
+        let mut fresh = AudioProcessor::new(1.5);
+        fresh.set_formant_preservation(0.5);
+        let expected: Vec<f32> = input.iter().map(|x| fresh.process_sample(*x)).collect();
+
+        let mut reused = AudioProcessor::new(1.5);
+        reused.set_formant_preservation(0.5);
+        for n in 0..3 * FFT_SIZE + 5 {
+            reused.process_sample(libm::cosf(0.3 * n as f32));
+        }
+        reused.reset();
+        assert_eq!(reused.hops(), 0);
+        let output: Vec<f32> = input.iter().map(|x| reused.process_sample(*x)).collect();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn init_in_place_matches_a_new_processor() {
+        let input: Vec<f32> = (0..4 * FFT_SIZE).map(|n| libm::sinf(0.05 * n as f32)).collect();
+        let mut fresh = AudioProcessor::new(1.5);
+        let expected: Vec<f32> = input.iter().map(|x| fresh.process_sample(*x)).collect();
+
+        let mut storage = Box::<AudioProcessor>::new_uninit();
+        let mut processor = unsafe {
+            AudioProcessor::init_in_place(storage.as_mut_ptr(), 1.5);
+            storage.assume_init()
+        };
+        let output: Vec<f32> = input.iter().map(|x| processor.process_sample(*x)).collect();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn render_aligned_takes_the_latency_out() {
+        let mut processor = AudioProcessor::new(1.0);
+        let input = (0..8192).map(|n| if n == 3000 { 1.0 } else { 0.0 });
+        let output: Vec<f32> =
+            render_aligned(input, 0.0, |sample| processor.process_sample(sample)).collect();
+        assert_eq!(output.len(), 8192);
+        let peak = (0..output.len())
+            .max_by(|a, b| output[*a].abs().total_cmp(&output[*b].abs()))
+            .unwrap();
+        assert_eq!(peak, 3000);
+    }
+
+    #[test]
+    fn only_positive_finite_pitch_shifts_are_valid() {
+        assert_eq!(check_pitch_shift(0.5), Ok(0.5));
+        for pitch_shift in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(check_pitch_shift(pitch_shift).is_err(), "{pitch_shift}");
+        }
+    }
+
+    #[test]
+    fn impulse_comes_out_after_the_latency() {
+        let mut processor = AudioProcessor::new(1.0);
+        let output: Vec<f32> = (0..8192)
+            .map(|n| processor.process_sample(if n == 3000 { 1.0 } else { 0.0 }))
+            .collect();
+        let peak = (0..output.len())
+            .max_by(|a, b| output[*a].abs().total_cmp(&output[*b].abs()))
+            .unwrap();
+        assert_eq!(peak, 3000 + processor.latency());
+    }
+
+    /// Magnitude weighted mean bin of the last frame of rendering `input`
+    fn spectral_centroid(processor: &mut AudioProcessor, input: &[f32]) -> f32 {
+        let mut frame = [0.0; FFT_SIZE];
+        for (n, sample) in input.iter().enumerate() {
+            frame[n % FFT_SIZE] = processor.process_sample(*sample);
+        }
+        let spectrum = microfft::real::rfft_1024(&mut frame);
+        let magnitudes = spectrum.map(|bin| libm::sqrtf(bin.re * bin.re + bin.im * bin.im));
+        let weighted: f32 = magnitudes.iter().enumerate().map(|(i, m)| i as f32 * m).sum();
+        weighted / magnitudes.iter().sum::<f32>()
+    }
+
+    #[test]
+    fn formant_preservation_keeps_the_envelope_in_place() {
+        // Harmonics of bin 4 under a formant around bin 60
+        let input: Vec<f32> = (0..16 * FFT_SIZE)
+            .map(|n| {
+                (1..60)
+                    .map(|k| {
+                        let distance = (4 * k) as f32 - 60.0;
+                        let level = libm::expf(-distance * distance / 200.0);
+                        level * libm::sinf(2.0 * PI * (4 * k * n) as f32 / FFT_SIZE as f32)
+                    })
+                    .sum()
+            })
+            .collect();
+
+        let mut unshifted = AudioProcessor::new(1.0);
+        let mut shifted = AudioProcessor::new(1.5);
+        let mut preserved = AudioProcessor::new(1.5);
+        preserved.set_formant_preservation(1.0);
+
+        let unshifted = spectral_centroid(&mut unshifted, &input);
+        let shifted = spectral_centroid(&mut shifted, &input);
+        let preserved = spectral_centroid(&mut preserved, &input);
+        assert!((shifted / unshifted - 1.5).abs() < 0.1, "{unshifted} {shifted}");
+        assert!((preserved / unshifted - 1.0).abs() < 0.15, "{unshifted} {preserved}");
+    }
+
+    #[test]
+    fn analysis_measures_frequencies_between_bins() {
+        let mut processor = AudioProcessor::new(1.0);
+        for n in 0..4 * FFT_SIZE {
+            processor.process_sample(libm::sinf(2.0 * PI * 20.3 * n as f32 / FFT_SIZE as f32));
+            assert_eq!(processor.hops(), n / HOP_SIZE);
+        }
+
+        let (magnitudes, frequencies) = processor.analysis();
+        let peak = (0..BINS)
+            .max_by(|a, b| magnitudes[*a].total_cmp(&magnitudes[*b]))
+            .unwrap();
+        assert_eq!(peak, 20);
+        assert!((frequencies[peak] - 20.3).abs() < 0.01, "{}", frequencies[peak]);
+    }
+
+    #[test]
+    fn wrap_phase_edges() {
+        assert_eq!(wrap_phase(0.0), 0.0);
+        assert_eq!(wrap_phase(PI), -PI);
+        assert_eq!(wrap_phase(-PI), -PI);
+        assert_eq!(wrap_phase(3.0 * PI), -PI);
+        assert_eq!(wrap_phase(-3.0 * PI), -PI);
+    }
+}
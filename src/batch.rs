@@ -0,0 +1,176 @@
+//! Batch rendering of many files in parallel, for `vocoder batch`.
+//!
+//! Every file gets its own processors, one per channel, so files render independently on
+//! as many worker threads as there are cores. A file that fails is reported in the
+//! summary and doesn't stop the others.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use crate::audio_io::{self, FileFormat};
+use crate::audio_processor::shift_channel;
+
+/// Output name used when none is given, see `output_path`
+pub const DEFAULT_TEMPLATE: &str = "{name}_shifted.wav";
+
+pub struct BatchOptions {
+    pub pitch_shift: f32,
+    /// Output file name, with `{name}` replaced by the input's name without its
+    /// extension and `{pitch}` by the pitch shift
+    pub template: String,
+    /// Worker threads, all cores if `None`
+    pub jobs: Option<usize>,
+}
+
+/// How one file went
+pub struct FileReport {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Seconds of audio rendered, or why the file failed
+    pub result: Result<f64, String>,
+    pub elapsed: Duration,
+}
+
+pub struct Summary {
+    pub files: Vec<FileReport>,
+    pub elapsed: Duration,
+    pub jobs: usize,
+}
+
+impl Summary {
+    pub fn failures(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|file| file.result.is_err())
+            .count()
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for file in &self.files {
+            let seconds = file.elapsed.as_secs_f64();
+            match &file.result {
+                Ok(audio) => writeln!(
+                    f,
+                    "ok     {} -> {} ({audio:.2} s of audio in {seconds:.2} s)",
+                    file.input.display(),
+                    file.output.display()
+                )?,
+                Err(err) => writeln!(f, "FAILED {}: {err}", file.input.display())?,
+            }
+        }
+        write!(
+            f,
+            "{} succeeded, {} failed in {:.2} s on {} workers",
+            self.files.len() - self.failures(),
+            self.failures(),
+            self.elapsed.as_secs_f64(),
+            self.jobs
+        )
+    }
+}
+
+/// The WAV, FLAC and AIFF files in a directory, or the files matching a glob pattern,
+/// sorted. Raw PCM says nothing about its rate and channels, so a directory's are left out.
+pub fn find_inputs(pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let mut inputs = Vec::new();
+    if Path::new(pattern).is_dir() {
+        let entries = std::fs::read_dir(pattern).map_err(|err| format!("{pattern}: {err}"))?;
+        for entry in entries {
+            let path = entry.map_err(|err| format!("{pattern}: {err}"))?.path();
+            let format = FileFormat::from_path(&path);
+            if path.is_file() && format.is_ok_and(|format| !matches!(format, FileFormat::Raw(_))) {
+                inputs.push(path);
+            }
+        }
+    } else {
+        let paths = glob::glob(pattern).map_err(|err| format!("{pattern}: {err}"))?;
+        for path in paths {
+            let path = path.map_err(|err| err.to_string())?;
+            if path.is_file() {
+                inputs.push(path);
+            }
+        }
+    }
+    inputs.sort();
+    Ok(inputs)
+}
+
+/// Where `input` renders to in `output_dir`, following `template`
+pub fn output_path(template: &str, input: &Path, output_dir: &Path, pitch_shift: f32) -> PathBuf {
+    let name = input.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = template
+        .replace("{name}", &name)
+        .replace("{pitch}", &pitch_shift.to_string());
+    output_dir.join(file_name)
+}
+
+/// Render every input into `output_dir`, which is created if needed
+pub fn run(inputs: &[PathBuf], output_dir: &Path, options: &BatchOptions) -> Summary {
+    let start = Instant::now();
+    let outputs: Vec<PathBuf> = inputs
+        .iter()
+        .map(|input| output_path(&options.template, input, output_dir, options.pitch_shift))
+        .collect();
+    // Two inputs named alike would overwrite each other's output
+    let mut claimed = HashMap::new();
+    for (input, output) in inputs.iter().zip(&outputs) {
+        claimed.entry(output).or_insert_with(Vec::new).push(input);
+    }
+
+    let render = |(input, output): (&PathBuf, &PathBuf)| {
+        let start = Instant::now();
+        let result = match claimed[output].as_slice() {
+            [_] => std::fs::create_dir_all(output_dir)
+                .map_err(|err| err.to_string())
+                .and_then(|()| render_file(input, output, options.pitch_shift)),
+            others => Err(format!(
+                "{} inputs would be written to {}",
+                others.len(),
+                output.display()
+            )),
+        };
+        FileReport {
+            input: input.clone(),
+            output: output.clone(),
+            result,
+            elapsed: start.elapsed(),
+        }
+    };
+
+    let mut pool = rayon::ThreadPoolBuilder::new();
+    if let Some(jobs) = options.jobs {
+        pool = pool.num_threads(jobs);
+    }
+    let pool = pool.build().expect("starting the worker threads failed");
+    let files = pool.install(|| inputs.par_iter().zip(&outputs).map(render).collect());
+    Summary {
+        files,
+        elapsed: start.elapsed(),
+        jobs: pool.current_num_threads(),
+    }
+}
+
+/// Render one file into another, returning its length in seconds. The output is in the
+/// format its extension names, with the input's sample format where that fits.
+fn render_file(input: &Path, output: &Path, pitch_shift: f32) -> Result<f64, String> {
+    if output.exists() && output.canonicalize().ok() == input.canonicalize().ok() {
+        return Err("the output would overwrite the input".to_string());
+    }
+    let audio = audio_io::read(input, None)?;
+    if audio.channels.is_empty() {
+        return Err("no channels".to_string());
+    }
+    let shifted = audio
+        .channels
+        .iter()
+        .map(|channel| shift_channel(channel, pitch_shift))
+        .collect();
+    audio_io::write(output, None, &audio.with_channels(shifted))?;
+    Ok(audio.seconds())
+}
@@ -1,3 +1,13 @@
+use core::ops::Range;
+use core::ptr::addr_of_mut;
+
+/// Reported when a writer laps its reader or a reader catches up with its writer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferError {
+    Overrun,
+    Underrun,
+}
+
 pub struct CircularBuffer<T, const N: usize> {
     buffer: [T; N],
     read_index: usize,
@@ -5,6 +15,16 @@ pub struct CircularBuffer<T, const N: usize> {
     hop_pointer: usize,
     hop_size: usize,
     default_value: T,
+    // Positions counted from the start of the stream rather than wrapped to N, used to
+    // tell how far apart the reader and writer are. They wrap at usize::MAX, so only
+    // ever compare them by wrapping subtraction.
+    read_position: usize,
+    write_position: usize,
+    hop_position: usize,
+    /// One past the furthest position written so far
+    written_end: usize,
+    overruns: usize,
+    underruns: usize,
 }
 
 impl<T, const N: usize> CircularBuffer<T, N>
@@ -21,25 +41,139 @@ where
             hop_size,
             hop_pointer: 0,
             default_value,
+            read_position: 0,
+            write_position: 0,
+            hop_position: 0,
+            written_end: 0,
+            overruns: 0,
+            underruns: 0,
+        }
+    }
+
+    /// `new`, written straight into `buffer` rather than built on the stack and moved
+    ///
+    /// # Safety
+    /// `buffer` must be valid for writes and aligned. What it held isn't dropped.
+    pub unsafe fn init_in_place(buffer: *mut Self, default_value: T, hop_size: Option<usize>) {
+        let values = addr_of_mut!((*buffer).buffer).cast::<T>();
+        for index in 0..N {
+            values.add(index).write(default_value);
+        }
+        addr_of_mut!((*buffer).read_index).write(0);
+        addr_of_mut!((*buffer).write_index).write(0);
+        addr_of_mut!((*buffer).hop_pointer).write(0);
+        addr_of_mut!((*buffer).hop_size).write(hop_size.unwrap_or(0));
+        addr_of_mut!((*buffer).default_value).write(default_value);
+        addr_of_mut!((*buffer).read_position).write(0);
+        addr_of_mut!((*buffer).write_position).write(0);
+        addr_of_mut!((*buffer).hop_position).write(0);
+        addr_of_mut!((*buffer).written_end).write(0);
+        addr_of_mut!((*buffer).overruns).write(0);
+        addr_of_mut!((*buffer).underruns).write(0);
+    }
+
+    /// Go back to the state `new` left the buffer in, without building a new one
+    pub fn reset(&mut self) {
+        self.buffer.fill(self.default_value);
+        self.read_index = 0;
+        self.write_index = 0;
+        self.hop_pointer = 0;
+        self.read_position = 0;
+        self.write_position = 0;
+        self.hop_position = 0;
+        self.written_end = 0;
+        self.overruns = 0;
+        self.underruns = 0;
+    }
+
+    /// Number of values written ahead of the read index that haven't been read yet
+    pub fn fill_level(&self) -> usize {
+        let unread = self.written_end.wrapping_sub(self.read_position) as isize;
+        unread.clamp(0, N as isize) as usize
+    }
+
+    /// Number of values written more than a full buffer ahead of the reader, which
+    /// overwrote data it hadn't read yet, or behind the reader, where it won't see them
+    pub fn overruns(&self) -> usize {
+        self.overruns
+    }
+
+    /// Number of values read that hadn't been written yet, or had already been
+    /// overwritten when `push_read_back` went back to them. The initial default values
+    /// count as written, so reading back from the start of a stream is fine.
+    pub fn underruns(&self) -> usize {
+        self.underruns
+    }
+
+    /// The first overrun or underrun, if there has been one
+    pub fn check(&self) -> Result<(), BufferError> {
+        if self.overruns > 0 {
+            Err(BufferError::Overrun)
+        } else if self.underruns > 0 {
+            Err(BufferError::Underrun)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Account for `len` values written or added at the write position
+    fn note_write(&mut self, len: usize) {
+        // Values written where the reader has already been won't be read until it comes
+        // back round, which is just as lost
+        let late = self.read_position.wrapping_sub(self.write_position) as isize;
+        if late > 0 {
+            self.overruns += (late as usize).min(len);
+        }
+
+        let end = self.write_position.wrapping_add(len);
+        let ahead = end.wrapping_sub(self.read_position) as isize;
+        if ahead > N as isize {
+            self.overruns += (ahead as usize - N).min(len);
+        }
+        if end.wrapping_sub(self.written_end) as isize > 0 {
+            self.written_end = end;
+        }
+        self.write_position = end;
+    }
+
+    /// Account for `len` values read at the read position
+    fn note_read(&mut self, len: usize) {
+        let end = self.read_position.wrapping_add(len);
+        let beyond = end.wrapping_sub(self.written_end) as isize;
+        if beyond > 0 {
+            self.underruns += (beyond as usize).min(len);
+        }
+        self.read_position = end;
+    }
+
+    /// Wrap an index into the buffer. `N` is a constant, so this is a mask when it's a
+    /// power of two and a modulo, which is a division on Cortex-M, otherwise.
+    const fn wrap(index: usize) -> usize {
+        if N.is_power_of_two() {
+            index & (N - 1)
+        } else {
+            index % N
         }
     }
 
     fn increment_index(&mut self, index: usize) -> usize {
-        (index + 1) % N
+        Self::wrap(index + 1)
     }
 
     pub fn read(&mut self) -> T {
         let current_index = self.read_index;
         self.read_index = self.increment_index(self.read_index);
+        self.note_read(1);
 
         self.buffer[current_index]
     }
 
-    pub fn write(&mut self, value: T) -> () {
+    pub fn write(&mut self, value: T) {
         self.buffer[self.write_index] = value;
 
         //if we are at the max buffer size, circle back to 0
         self.write_index = self.increment_index(self.write_index);
+        self.note_write(1);
     }
 
     pub fn read_and_reset(&mut self) -> T {
@@ -48,6 +182,7 @@ where
         self.buffer[self.read_index] = self.default_value;
 
         self.read_index = self.increment_index(self.read_index);
+        self.note_read(1);
 
         value
     }
@@ -55,19 +190,311 @@ where
     pub fn add_value(&mut self, value: T) {
         self.buffer[self.write_index] += value;
         self.write_index = self.increment_index(self.write_index);
+        self.note_write(1);
     }
 
     pub fn next_hop(&mut self) {
-      let hop_index = (self.hop_pointer + self.hop_size) % self.buffer.len();
+      let hop_index = Self::wrap(self.hop_pointer + self.hop_size);
       self.hop_pointer = hop_index;
       self.write_index = hop_index;
-
+      self.hop_position = self.hop_position.wrapping_add(self.hop_size);
+      self.write_position = self.hop_position;
     }
 
     pub fn push_read_back(&mut self, window_size: usize) {
-        let push_back = ((self.read_index as isize - window_size as isize + self.buffer.len() as isize) % self.buffer.len() as isize) as usize;
+        let push_back = Self::wrap(self.read_index + N - Self::wrap(window_size));
         self.read_index = push_back;
+
+        // Going back further than a buffer behind the newest value reads data that has
+        // already been overwritten
+        self.read_position = self.read_position.wrapping_sub(window_size);
+        let behind = self.written_end.wrapping_sub(self.read_position) as isize;
+        if behind > N as isize {
+            self.underruns += (behind as usize - N).min(window_size);
+        }
+    }
+
+    /// Split `len` values starting at `index` into the part before the end of the
+    /// buffer and the part that wraps around to the start.
+    fn segment_ranges(index: usize, len: usize) -> (Range<usize>, Range<usize>) {
+        assert!(len <= N, "{len} values don't fit in a buffer of {N}");
+        let first_len = len.min(N - index);
+        (index..index + first_len, 0..len - first_len)
+    }
+
+    /// The next `len` values from the read index, split where the buffer wraps.
+    /// Doesn't move the read index, see `skip_read`.
+    pub fn read_segments(&self, len: usize) -> (&[T], &[T]) {
+        let (first, second) = Self::segment_ranges(self.read_index, len);
+        (&self.buffer[first], &self.buffer[second])
+    }
+
+    /// The next `len` values from the write index, split where the buffer wraps.
+    /// Doesn't move the write index, see `skip_write`.
+    pub fn write_segments(&mut self, len: usize) -> (&mut [T], &mut [T]) {
+        let (first, second) = Self::segment_ranges(self.write_index, len);
+        let (head, tail) = self.buffer.split_at_mut(first.start);
+        (&mut tail[..first.len()], &mut head[second])
+    }
+
+    pub fn skip_read(&mut self, len: usize) {
+        self.read_index = Self::wrap(self.read_index + len);
+        self.note_read(len);
+    }
+
+    pub fn skip_write(&mut self, len: usize) {
+        self.write_index = Self::wrap(self.write_index + len);
+        self.note_write(len);
+    }
+
+    /// Bulk `write`
+    pub fn write_slice(&mut self, values: &[T]) {
+        let (first, second) = self.write_segments(values.len());
+        let (values_first, values_second) = values.split_at(first.len());
+        first.copy_from_slice(values_first);
+        second.copy_from_slice(values_second);
+        self.skip_write(values.len());
+    }
+
+    /// Bulk `read`, filling all of `out`
+    pub fn read_into(&mut self, out: &mut [T]) {
+        let (first, second) = self.read_segments(out.len());
+        let (out_first, out_second) = out.split_at_mut(first.len());
+        out_first.copy_from_slice(first);
+        out_second.copy_from_slice(second);
+        self.skip_read(out.len());
+    }
+
+    /// Bulk `add_value`
+    pub fn add_slice(&mut self, values: &[T]) {
+        let (first, second) = self.write_segments(values.len());
+        let (values_first, values_second) = values.split_at(first.len());
+        for (out, value) in first.iter_mut().zip(values_first) {
+            *out += *value;
+        }
+        for (out, value) in second.iter_mut().zip(values_second) {
+            *out += *value;
+        }
+        self.skip_write(values.len());
     }
 }
 
-// TODO add test
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn slices_wrap_like_single_values() {
+        let mut single: CircularBuffer<i32, 8> = CircularBuffer::new(0, None);
+        let mut bulk: CircularBuffer<i32, 8> = CircularBuffer::new(0, None);
+        let values = [1, 2, 3, 4, 5];
+
+        // Three writes of five values wrap around an eight value buffer twice
+        for _ in 0..3 {
+            for value in values {
+                single.write(value);
+            }
+            bulk.write_slice(&values);
+        }
+        for _ in 0..3 {
+            let mut out = [0; 5];
+            bulk.read_into(&mut out);
+            for value in out {
+                assert_eq!(value, single.read());
+            }
+        }
+
+        for value in values {
+            single.add_value(value);
+        }
+        bulk.add_slice(&values);
+        let mut out = [0; 8];
+        bulk.read_into(&mut out);
+        for value in out {
+            assert_eq!(value, single.read());
+        }
+    }
+
+    #[test]
+    fn sizes_that_arent_a_power_of_two_wrap_too() {
+        let mut buffer: CircularBuffer<i32, 7> = CircularBuffer::new(0, Some(3));
+        for value in 0..10 {
+            buffer.write(value);
+        }
+        buffer.skip_read(10);
+        buffer.push_read_back(5);
+        let mut out = [0; 5];
+        buffer.read_into(&mut out);
+        assert_eq!(out, [5, 6, 7, 8, 9]);
+
+        for _ in 0..3 {
+            buffer.next_hop();
+        }
+        buffer.add_value(1);
+        assert_eq!(buffer.read_segments(7), (&[3, 4, 5, 6][..], &[7, 8, 10][..]));
+    }
+
+    #[test]
+    fn segments_split_at_the_end_of_the_buffer() {
+        let mut buffer: CircularBuffer<i32, 8> = CircularBuffer::new(0, None);
+        buffer.write_slice(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        buffer.skip_read(6);
+
+        assert_eq!(buffer.read_segments(4), (&[6, 7][..], &[0, 1][..]));
+        assert_eq!(buffer.read_segments(2), (&[6, 7][..], &[][..]));
+
+        buffer.skip_write(5);
+        let (first, second) = buffer.write_segments(5);
+        assert_eq!((first.len(), second.len()), (3, 2));
+        first[0] = 10;
+        second[1] = 11;
+        assert_eq!(buffer.read_segments(8), (&[6, 7][..], &[0, 11, 2, 3, 4, 10][..]));
+    }
+
+    #[test]
+    fn fill_level_follows_reads_and_writes() {
+        let mut buffer: CircularBuffer<i32, 8> = CircularBuffer::new(0, None);
+        assert_eq!(buffer.fill_level(), 0);
+        buffer.write_slice(&[1, 2, 3, 4, 5]);
+        assert_eq!(buffer.fill_level(), 5);
+        buffer.read();
+        buffer.skip_read(2);
+        assert_eq!(buffer.fill_level(), 2);
+        buffer.push_read_back(3);
+        assert_eq!(buffer.fill_level(), 5);
+        assert_eq!(buffer.check(), Ok(()));
+    }
+
+    #[test]
+    fn writer_lapping_reader_is_an_overrun() {
+        let mut buffer: CircularBuffer<i32, 8> = CircularBuffer::new(0, None);
+        buffer.write_slice(&[0; 6]);
+        buffer.skip_read(2);
+        buffer.write_slice(&[0; 4]);
+        assert_eq!(buffer.overruns(), 0);
+        assert_eq!(buffer.fill_level(), 8);
+
+        buffer.write(0);
+        buffer.add_slice(&[0; 2]);
+        assert_eq!(buffer.overruns(), 3);
+        assert_eq!(buffer.check(), Err(BufferError::Overrun));
+
+        // Adding a hop that starts behind the reader loses the values it already passed
+        let mut hopped: CircularBuffer<i32, 8> = CircularBuffer::new(0, Some(2));
+        hopped.read_and_reset();
+        hopped.add_slice(&[1, 2, 3]);
+        assert_eq!(hopped.overruns(), 1);
+        hopped.next_hop();
+        hopped.add_slice(&[1, 2, 3]);
+        assert_eq!(hopped.overruns(), 1);
+    }
+
+    #[test]
+    fn reading_past_the_writer_is_an_underrun() {
+        let mut buffer: CircularBuffer<i32, 8> = CircularBuffer::new(0, None);
+        buffer.write_slice(&[1, 2]);
+        let mut out = [0; 4];
+        buffer.read_into(&mut out);
+        assert_eq!(buffer.underruns(), 2);
+        assert_eq!(buffer.check(), Err(BufferError::Underrun));
+
+        // Hopping doesn't count as writing, only what is added at the hop does
+        let mut hopped: CircularBuffer<i32, 8> = CircularBuffer::new(0, Some(4));
+        hopped.next_hop();
+        hopped.read_and_reset();
+        assert_eq!(hopped.underruns(), 1);
+    }
+
+    #[test]
+    fn reading_back_beyond_the_history_is_an_underrun() {
+        let mut buffer: CircularBuffer<i32, 8> = CircularBuffer::new(0, None);
+        for value in 0..12 {
+            buffer.write(value);
+        }
+        buffer.skip_read(12);
+
+        // The last eight values are still there, the four before them were overwritten
+        buffer.push_read_back(8);
+        assert_eq!(buffer.underruns(), 0);
+        buffer.skip_read(8);
+        buffer.push_read_back(10);
+        assert_eq!(buffer.underruns(), 2);
+    }
+
+    #[test]
+    fn writes_wrap_around_and_overwrite() {
+        let mut buffer: CircularBuffer<i32, 4> = CircularBuffer::new(0, None);
+        for value in 1..=6 {
+            buffer.write(value);
+        }
+        assert_eq!(buffer.read_segments(4), (&[5, 6, 3, 4][..], &[][..]));
+    }
+
+    #[test]
+    fn read_and_reset_leaves_the_default_behind() {
+        let mut buffer: CircularBuffer<i32, 4> = CircularBuffer::new(-1, None);
+        buffer.write_slice(&[1, 2, 3]);
+        assert_eq!(buffer.read_and_reset(), 1);
+        assert_eq!(buffer.read_and_reset(), 2);
+        assert_eq!(buffer.read(), 3);
+        assert_eq!(buffer.read(), -1);
+        assert_eq!(buffer.read(), -1);
+        assert_eq!(buffer.read(), -1);
+        assert_eq!(buffer.read(), 3);
+    }
+
+    #[test]
+    fn next_hop_moves_a_hop_on_from_the_last_hop() {
+        let mut buffer: CircularBuffer<i32, 8> = CircularBuffer::new(0, Some(3));
+        buffer.add_slice(&[1; 5]);
+        // Where the last frame ended doesn't matter, the next one starts a hop on
+        buffer.next_hop();
+        buffer.add_slice(&[1; 5]);
+        buffer.next_hop();
+        buffer.add_slice(&[1; 5]);
+        assert_eq!(buffer.read_segments(8), (&[2, 2, 2, 2, 2, 1, 2, 2][..], &[][..]));
+    }
+
+    #[test]
+    fn push_read_back_rereads_the_latest_values() {
+        let mut buffer: CircularBuffer<i32, 8> = CircularBuffer::new(0, None);
+        for value in 0..11 {
+            buffer.write(value);
+            buffer.read();
+        }
+        buffer.push_read_back(4);
+        let mut out = [0; 4];
+        buffer.read_into(&mut out);
+        assert_eq!(out, [7, 8, 9, 10]);
+        assert_eq!(buffer.check(), Ok(()));
+    }
+
+    proptest! {
+        /// Interleaved bulk writes and reads give the values back in order, as long as
+        /// the writer never gets more than a buffer ahead
+        #[test]
+        fn bulk_reads_return_writes_in_order(
+            chunks in prop::collection::vec((0usize..=8, 0usize..=8), 1..50)
+        ) {
+            let mut buffer: CircularBuffer<u32, 8> = CircularBuffer::new(0, None);
+            let mut next_write = 0;
+            let mut next_read = 0;
+            for (writes, reads) in chunks {
+                let writes = writes.min(8 - buffer.fill_level());
+                let values: [u32; 8] = core::array::from_fn(|n| next_write + n as u32);
+                buffer.write_slice(&values[..writes]);
+                next_write += writes as u32;
+
+                let reads = reads.min(buffer.fill_level());
+                let mut out = [0; 8];
+                buffer.read_into(&mut out[..reads]);
+                for value in &out[..reads] {
+                    prop_assert_eq!(*value, next_read);
+                    next_read += 1;
+                }
+            }
+            prop_assert_eq!(buffer.check(), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Sizes of the FFT frame, the hop between frames and the circular buffers holding them.
+//!
+//! `Config` checks the sizes when its constants are evaluated, so an invalid combination
+//! fails to compile wherever they're used. `validate` does the same checks at runtime
+//! for sizes that aren't known at compile time.
+//!
+//! The processors aren't generic over `Config`: `AudioProcessor`, `FixedPointProcessor`
+//! and their buffers are built with the constants of `DefaultConfig`, re-exported from
+//! `audio_processor`. Other sizes mean editing that alias and rebuilding, and the checks
+//! are what keep such an edit valid.
+
+/// Why a combination of sizes can't be used
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The FFTs only handle power of two frame sizes
+    FftSizeNotPowerOfTwo,
+    /// Hops have to be at least one sample
+    ZeroHop,
+    /// Frames have to overlap, so the hop has to be smaller than the frame
+    HopNotSmallerThanFft,
+    /// The hop has to be a clean division of the frame (1/2, 1/4, 1/8, ...) for the
+    /// overlap-add to sum to a constant gain
+    HopDoesNotDivideFft,
+    /// The buffers need to hold a whole frame plus the hop being read out of them
+    BufferTooSmall,
+}
+
+/// Smallest circular buffer that holds a frame of `fft_size` plus a hop of `hop_size`
+pub const fn buffer_size(fft_size: usize, hop_size: usize) -> usize {
+    fft_size + hop_size
+}
+
+/// Check a combination of sizes
+pub const fn validate(
+    fft_size: usize,
+    hop_size: usize,
+    buffer_size: usize,
+) -> Result<(), ConfigError> {
+    if !fft_size.is_power_of_two() {
+        Err(ConfigError::FftSizeNotPowerOfTwo)
+    } else if hop_size == 0 {
+        Err(ConfigError::ZeroHop)
+    } else if hop_size >= fft_size {
+        Err(ConfigError::HopNotSmallerThanFft)
+    } else if !(fft_size / hop_size).is_power_of_two() || !fft_size.is_multiple_of(hop_size) {
+        Err(ConfigError::HopDoesNotDivideFft)
+    } else if buffer_size < self::buffer_size(fft_size, hop_size) {
+        Err(ConfigError::BufferTooSmall)
+    } else {
+        Ok(())
+    }
+}
+
+/// Sizes checked at compile time, with the buffer size derived from the other two.
+///
+/// The buffers are exactly `buffer_size` long unless `POWER_OF_TWO_BUFFERS` rounds them
+/// up to a power of two. Then `CircularBuffer` wraps its indices with a mask rather than
+/// a modulo, which is a division on Cortex-M, at the cost of memory: 2048 samples
+/// instead of 1152 per buffer at the default sizes, about 7 KB more per processor.
+///
+/// Reading any of the constants forces the check, so this doesn't compile:
+///
+/// ```compile_fail
+/// let _ = vocoder::config::Config::<1024, 1000>::BUFFER_SIZE;
+/// ```
+pub struct Config<
+    const FFT_SIZE: usize,
+    const HOP_SIZE: usize,
+    const POWER_OF_TWO_BUFFERS: bool = false,
+>;
+
+impl<const FFT_SIZE: usize, const HOP_SIZE: usize, const POWER_OF_TWO_BUFFERS: bool>
+    Config<FFT_SIZE, HOP_SIZE, POWER_OF_TWO_BUFFERS>
+{
+    const DERIVED_BUFFER_SIZE: usize = if POWER_OF_TWO_BUFFERS {
+        buffer_size(FFT_SIZE, HOP_SIZE).next_power_of_two()
+    } else {
+        buffer_size(FFT_SIZE, HOP_SIZE)
+    };
+    const VALID: () = match validate(FFT_SIZE, HOP_SIZE, Self::DERIVED_BUFFER_SIZE) {
+        Ok(()) => (),
+        Err(ConfigError::FftSizeNotPowerOfTwo) => panic!("FFT_SIZE must be a power of two"),
+        Err(ConfigError::ZeroHop) => panic!("HOP_SIZE must not be zero"),
+        Err(ConfigError::HopNotSmallerThanFft) => panic!("HOP_SIZE must be smaller than FFT_SIZE"),
+        Err(ConfigError::HopDoesNotDivideFft) => {
+            panic!("HOP_SIZE must be FFT_SIZE divided by a power of two")
+        }
+        Err(ConfigError::BufferTooSmall) => panic!("BUFFER_SIZE is too small"),
+    };
+
+    pub const FFT_SIZE: usize = {
+        let () = Self::VALID;
+        FFT_SIZE
+    };
+    pub const HOP_SIZE: usize = {
+        let () = Self::VALID;
+        HOP_SIZE
+    };
+    pub const BUFFER_SIZE: usize = {
+        let () = Self::VALID;
+        Self::DERIVED_BUFFER_SIZE
+    };
+}
+
+/// The sizes every processor is built with, the only `Config` they use. The FFT size is
+/// fixed at 1024 by the FFT routines; the hop size and the buffer rounding can be changed
+/// here before building.
+pub type DefaultConfig = Config<1024, 128>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_derives_buffer_size() {
+        assert_eq!(DefaultConfig::BUFFER_SIZE, 1024 + 128);
+        assert_eq!(Config::<1024, 512>::BUFFER_SIZE, 1536);
+        assert_eq!(Config::<1024, 128, true>::BUFFER_SIZE, 2048);
+        assert_eq!(Config::<256, 128, true>::BUFFER_SIZE, 512);
+    }
+
+    #[test]
+    fn invalid_sizes_are_rejected() {
+        assert_eq!(
+            validate(1000, 100, 2000),
+            Err(ConfigError::FftSizeNotPowerOfTwo)
+        );
+        assert_eq!(validate(1024, 0, 2048), Err(ConfigError::ZeroHop));
+        assert_eq!(
+            validate(1024, 1024, 2048),
+            Err(ConfigError::HopNotSmallerThanFft)
+        );
+        assert_eq!(
+            validate(1024, 2048, 4096),
+            Err(ConfigError::HopNotSmallerThanFft)
+        );
+        assert_eq!(
+            validate(1024, 100, 2048),
+            Err(ConfigError::HopDoesNotDivideFft)
+        );
+        assert_eq!(
+            validate(1024, 384, 2048),
+            Err(ConfigError::HopDoesNotDivideFft)
+        );
+        assert_eq!(validate(1024, 128, 1151), Err(ConfigError::BufferTooSmall));
+        assert_eq!(validate(1024, 128, 3000), Ok(()));
+    }
+}
@@ -0,0 +1,136 @@
+//! CORDIC magnitude, phase and rotation for the fixed point pipeline.
+//!
+//! Angles are binary angles: the full `u32` range is one turn, so `0x4000_0000` is
+//! pi/2 and wrapping arithmetic on angles is phase wrapping for free.
+
+use crate::fixed_point::mul_q31;
+
+const ITERATIONS: usize = 24;
+
+/// Largest vector length that can go through `vector` or come out of `rotate`
+/// without the CORDIC gain overflowing an `i32`.
+pub const MAX_MAGNITUDE: i32 = 1 << 30;
+
+/// `atan(2^-i)` as binary angles
+const ATAN_TABLE: [u32; ITERATIONS] = [
+    0x20000000, 0x12E4051E, 0x09FB385B, 0x051111D4, 0x028B0D43, 0x0145D7E1, 0x00A2F61E, 0x00517C55,
+    0x0028BE53, 0x00145F2F, 0x000A2F98, 0x000517CC, 0x00028BE6, 0x000145F3, 0x0000A2FA, 0x0000517D,
+    0x000028BE, 0x0000145F, 0x00000A30, 0x00000518, 0x0000028C, 0x00000146, 0x000000A3, 0x00000051,
+];
+
+/// `1 / K` in Q31, where `K` is the gain of `ITERATIONS` CORDIC rotations
+const INVERSE_GAIN: i32 = 1304065748;
+
+/// Half a turn as a binary angle
+pub const HALF_TURN: u32 = 0x8000_0000;
+
+/// Convert a binary angle to radians in [-pi, pi), for comparisons with the float path.
+pub fn angle_to_radians(angle: u32) -> f32 {
+    angle as i32 as f32 * (core::f32::consts::PI / HALF_TURN as f32)
+}
+
+/// Convert radians to a binary angle.
+pub fn radians_to_angle(radians: f32) -> u32 {
+    let turns = radians / (2.0 * core::f32::consts::PI);
+    let fraction = turns - libm::floorf(turns);
+    (fraction as f64 * 4294967296.0) as u64 as u32
+}
+
+/// Magnitude and phase of `(x, y)`, the fixed point `sqrtf`/`atan2f`.
+///
+/// The vector length must not exceed `MAX_MAGNITUDE`.
+pub fn vector(x: i32, y: i32) -> (i32, u32) {
+    let (mut x, mut y, mut angle) = if x < 0 {
+        // Rotate by half a turn so the vector is within the CORDIC's convergence range
+        (-x, -y, HALF_TURN)
+    } else {
+        (x, y, 0)
+    };
+
+    for (i, atan) in ATAN_TABLE.iter().enumerate() {
+        let (dx, dy) = (x >> i, y >> i);
+        if y > 0 {
+            x += dy;
+            y -= dx;
+            angle = angle.wrapping_add(*atan);
+        } else {
+            x -= dy;
+            y += dx;
+            angle = angle.wrapping_sub(*atan);
+        }
+    }
+
+    (mul_q31(x, INVERSE_GAIN), angle)
+}
+
+/// Rotate a vector of length `magnitude` to `angle`, the fixed point
+/// `magnitude * cosf`/`magnitude * sinf`.
+///
+/// `magnitude` must be in `0..=MAX_MAGNITUDE`.
+pub fn rotate(magnitude: i32, angle: u32) -> (i32, i32) {
+    let mut x = mul_q31(magnitude, INVERSE_GAIN);
+    let mut y = 0;
+    let mut z = angle as i32;
+
+    // Fold the left half plane onto the right one
+    if !(-(1 << 30)..=(1 << 30)).contains(&z) {
+        x = -x;
+        z = z.wrapping_sub(HALF_TURN as i32);
+    }
+
+    for (i, atan) in ATAN_TABLE.iter().enumerate() {
+        let (dx, dy) = (x >> i, y >> i);
+        if z >= 0 {
+            x -= dy;
+            y += dx;
+            z = z.wrapping_sub(*atan as i32);
+        } else {
+            x += dy;
+            y -= dx;
+            z = z.wrapping_add(*atan as i32);
+        }
+    }
+
+    (x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libm::{atan2f, cosf, sinf, sqrtf};
+
+    const SCALE: f32 = (1 << 27) as f32;
+
+    #[test]
+    fn vector_matches_sqrtf_and_atan2f() {
+        for step in 0..360 {
+            let radians = step as f32 * core::f32::consts::PI / 180.0;
+            for length in [0.001, 0.1, 0.5, 1.0, 7.9] {
+                let x = length * cosf(radians);
+                let y = length * sinf(radians);
+                let (magnitude, angle) = vector((x * SCALE) as i32, (y * SCALE) as i32);
+
+                let magnitude_error = (magnitude as f32 / SCALE - sqrtf(x * x + y * y)).abs();
+                assert!(
+                    magnitude_error < 1e-6 * length.max(1.0),
+                    "{magnitude_error}"
+                );
+
+                let phase_error =
+                    angle_to_radians(angle.wrapping_sub(radians_to_angle(atan2f(y, x))));
+                assert!(phase_error.abs() < 1e-5 / length.min(1.0), "{phase_error}");
+            }
+        }
+    }
+
+    #[test]
+    fn rotate_matches_cosf_and_sinf() {
+        for step in 0..720 {
+            let radians = step as f32 * core::f32::consts::PI / 360.0 - core::f32::consts::PI;
+            let (x, y) = rotate(MAX_MAGNITUDE, radians_to_angle(radians));
+            let scale = MAX_MAGNITUDE as f32;
+            assert!((x as f32 / scale - cosf(radians)).abs() < 1e-6);
+            assert!((y as f32 / scale - sinf(radians)).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Fixed point 1024-point real FFT and inverse, built on a 512-point radix-2 complex
+//! FFT the same way `microfft::real::rfft_1024` and `irfft::irfft_1024` are.
+//!
+//! Data is `i32` in whatever Q format the caller uses; the forward transform scales by
+//! `1/1024` so it can't overflow, and the inverse is unscaled so a round trip gives
+//! back the input.
+
+use crate::cordic;
+use crate::fixed_point::mul_q30;
+
+const FFT_SIZE: usize = 1024;
+const COMPLEX_SIZE: usize = FFT_SIZE / 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ComplexFixed {
+    pub re: i32,
+    pub im: i32,
+}
+
+impl ComplexFixed {
+    fn conj(self) -> ComplexFixed {
+        ComplexFixed {
+            re: self.re,
+            im: -self.im,
+        }
+    }
+
+    /// Multiply by a Q30 twiddle
+    fn mul_twiddle(self, twiddle: ComplexFixed) -> ComplexFixed {
+        ComplexFixed {
+            re: mul_q30(self.re, twiddle.re) - mul_q30(self.im, twiddle.im),
+            im: mul_q30(self.re, twiddle.im) + mul_q30(self.im, twiddle.re),
+        }
+    }
+}
+
+/// Twiddle factors `e^(-j*2*pi*k/FFT_SIZE)` in Q30, shared by the complex FFT stages
+/// and the real/complex recombination.
+pub type FixedTwiddles = [ComplexFixed; COMPLEX_SIZE];
+
+/// Build the twiddle table with the CORDIC, so no floating point is needed.
+pub fn generate_twiddles() -> FixedTwiddles {
+    let mut twiddles = [ComplexFixed::default(); COMPLEX_SIZE];
+    for (k, twiddle) in twiddles.iter_mut().enumerate() {
+        let angle = (k as u32).wrapping_mul(((1u64 << 32) / FFT_SIZE as u64) as u32);
+        let (re, im) = cordic::rotate(cordic::MAX_MAGNITUDE, angle.wrapping_neg());
+        *twiddle = ComplexFixed { re, im };
+    }
+    twiddles
+}
+
+/// In-place radix-2 complex FFT. The forward transform halves every stage to stay in
+/// range; the inverse uses conjugate twiddles and no scaling.
+fn fft_512(data: &mut [ComplexFixed; COMPLEX_SIZE], twiddles: &FixedTwiddles, inverse: bool) {
+    // Bit reversal permutation
+    let bits = COMPLEX_SIZE.trailing_zeros();
+    for i in 0..COMPLEX_SIZE {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            data.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= COMPLEX_SIZE {
+        let half = len / 2;
+        let stride = FFT_SIZE / len;
+        for start in (0..COMPLEX_SIZE).step_by(len) {
+            for k in 0..half {
+                let twiddle = if inverse {
+                    twiddles[k * stride].conj()
+                } else {
+                    twiddles[k * stride]
+                };
+                let a = data[start + k];
+                let b = data[start + k + half].mul_twiddle(twiddle);
+                if inverse {
+                    data[start + k] = ComplexFixed {
+                        re: a.re.saturating_add(b.re),
+                        im: a.im.saturating_add(b.im),
+                    };
+                    data[start + k + half] = ComplexFixed {
+                        re: a.re.saturating_sub(b.re),
+                        im: a.im.saturating_sub(b.im),
+                    };
+                } else {
+                    data[start + k] = ComplexFixed {
+                        re: (a.re >> 1) + (b.re >> 1),
+                        im: (a.im >> 1) + (b.im >> 1),
+                    };
+                    data[start + k + half] = ComplexFixed {
+                        re: (a.re >> 1) - (b.re >> 1),
+                        im: (a.im >> 1) - (b.im >> 1),
+                    };
+                }
+            }
+        }
+        len *= 2;
+    }
+}
+
+/// In-place 1024-point real FFT, scaled by `1/1024`.
+///
+/// The input is packed as complex pairs, with sample `2n` in `data[n].re` and
+/// sample `2n + 1` in `data[n].im`. The output uses the same packed format as
+/// `microfft::real::rfft_1024`, with the Nyquist bin in the imaginary part of DC.
+pub fn rfft_1024<'a>(
+    data: &'a mut [ComplexFixed; COMPLEX_SIZE],
+    twiddles: &FixedTwiddles,
+) -> &'a mut [ComplexFixed; COMPLEX_SIZE] {
+    fft_512(data, twiddles, false);
+
+    // Split the even and odd sample spectra back out, halving once more to reach 1/1024
+    let z0 = data[0];
+    data[0] = ComplexFixed {
+        re: (z0.re >> 1) + (z0.im >> 1),
+        im: (z0.re >> 1) - (z0.im >> 1),
+    };
+
+    for k in 1..COMPLEX_SIZE / 2 {
+        let (z_k, z_mk) = (data[k], data[COMPLEX_SIZE - k]);
+        let even = ComplexFixed {
+            re: (z_k.re >> 2) + (z_mk.re >> 2),
+            im: (z_k.im >> 2) - (z_mk.im >> 2),
+        };
+        // -j * (z_k - conj(z_mk)) / 4
+        let odd = ComplexFixed {
+            re: (z_k.im >> 2) + (z_mk.im >> 2),
+            im: (z_mk.re >> 2) - (z_k.re >> 2),
+        }
+        .mul_twiddle(twiddles[k]);
+
+        data[k] = ComplexFixed {
+            re: even.re + odd.re,
+            im: even.im + odd.im,
+        };
+        data[COMPLEX_SIZE - k] = ComplexFixed {
+            re: even.re - odd.re,
+            im: odd.im - even.im,
+        };
+    }
+
+    let middle = data[COMPLEX_SIZE / 2];
+    data[COMPLEX_SIZE / 2] = ComplexFixed {
+        re: middle.re >> 1,
+        im: -(middle.im >> 1),
+    };
+
+    data
+}
+
+/// In-place 1024-point inverse real FFT, the unscaled inverse of `rfft_1024`.
+///
+/// Takes the packed half spectrum and returns the samples packed the same way
+/// `rfft_1024` expects its input.
+pub fn irfft_1024<'a>(
+    data: &'a mut [ComplexFixed; COMPLEX_SIZE],
+    twiddles: &FixedTwiddles,
+) -> &'a mut [ComplexFixed; COMPLEX_SIZE] {
+    let x0 = data[0];
+    data[0] = ComplexFixed {
+        re: x0.re.saturating_add(x0.im),
+        im: x0.re.saturating_sub(x0.im),
+    };
+
+    for k in 1..COMPLEX_SIZE / 2 {
+        let (x_k, x_mk) = (data[k], data[COMPLEX_SIZE - k]);
+        let even = ComplexFixed {
+            re: x_k.re.saturating_add(x_mk.re),
+            im: x_k.im.saturating_sub(x_mk.im),
+        };
+        // (x_k - conj(x_mk)) * e^(j*2*pi*k/FFT_SIZE)
+        let odd = ComplexFixed {
+            re: x_k.re.saturating_sub(x_mk.re),
+            im: x_k.im.saturating_add(x_mk.im),
+        }
+        .mul_twiddle(twiddles[k].conj());
+
+        // even + j * odd, and its mirror for bin m - k
+        data[k] = ComplexFixed {
+            re: even.re.saturating_sub(odd.im),
+            im: even.im.saturating_add(odd.re),
+        };
+        data[COMPLEX_SIZE - k] = ComplexFixed {
+            re: even.re.saturating_add(odd.im),
+            im: odd.re.saturating_sub(even.im),
+        };
+    }
+
+    data[COMPLEX_SIZE / 2] = data[COMPLEX_SIZE / 2].conj();
+
+    fft_512(data, twiddles, true);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libm::sinf;
+
+    const SCALE: f32 = (1 << 27) as f32;
+    // Scaling the forward transform costs ten bits, which should leave the round trip
+    // within a couple of Q15 steps
+    const ROUND_TRIP_TOLERANCE: f32 = 2.0 / 32768.0;
+
+    fn test_signal() -> [f32; FFT_SIZE] {
+        let mut signal = [0.0; FFT_SIZE];
+        for (n, value) in signal.iter_mut().enumerate() {
+            *value = 0.5 * sinf(0.05 * n as f32) + 0.25 * sinf(0.31 * n as f32 + 1.0) + 0.1;
+        }
+        signal
+    }
+
+    fn pack(signal: &[f32; FFT_SIZE]) -> [ComplexFixed; COMPLEX_SIZE] {
+        let mut data = [ComplexFixed::default(); COMPLEX_SIZE];
+        for (n, value) in data.iter_mut().enumerate() {
+            value.re = (signal[2 * n] * SCALE) as i32;
+            value.im = (signal[2 * n + 1] * SCALE) as i32;
+        }
+        data
+    }
+
+    #[test]
+    fn rfft_matches_microfft() {
+        let twiddles = generate_twiddles();
+        let mut signal = test_signal();
+        let mut data = pack(&signal);
+
+        let fixed = rfft_1024(&mut data, &twiddles);
+        let float = microfft::real::rfft_1024(&mut signal);
+
+        for (fixed, float) in fixed.iter().zip(float.iter()) {
+            let re = fixed.re as f32 * FFT_SIZE as f32 / SCALE;
+            let im = fixed.im as f32 * FFT_SIZE as f32 / SCALE;
+            assert!((re - float.re).abs() < 2e-3, "{re} {}", float.re);
+            assert!((im - float.im).abs() < 2e-3, "{im} {}", float.im);
+        }
+    }
+
+    #[test]
+    fn round_trip_reconstructs_input() {
+        let twiddles = generate_twiddles();
+        let signal = test_signal();
+        let mut data = pack(&signal);
+
+        rfft_1024(&mut data, &twiddles);
+        let res = irfft_1024(&mut data, &twiddles);
+
+        for (n, value) in res.iter().enumerate() {
+            assert!((value.re as f32 / SCALE - signal[2 * n]).abs() < ROUND_TRIP_TOLERANCE);
+            assert!((value.im as f32 / SCALE - signal[2 * n + 1]).abs() < ROUND_TRIP_TOLERANCE);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use core::ops::AddAssign;
+
+/// Signed Q1.15 sample, used for audio in and out of the fixed point pipeline.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Q15(pub i16);
+
+/// Signed Q1.31 value, used where a sample needs more resolution or headroom.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Q31(pub i32);
+
+impl Q15 {
+    pub const ZERO: Q15 = Q15(0);
+
+    pub fn from_f32(value: f32) -> Q15 {
+        let scaled = value * 32768.0;
+        // float to int casts saturate, so out of range values clip instead of wrapping
+        Q15(scaled as i16)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        self.0 as f32 / 32768.0
+    }
+}
+
+impl Q31 {
+    pub const ZERO: Q31 = Q31(0);
+
+    pub fn from_f32(value: f32) -> Q31 {
+        Q31((value as f64 * 2147483648.0) as i32)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        (self.0 as f64 / 2147483648.0) as f32
+    }
+}
+
+// Overlap-add into a CircularBuffer goes through AddAssign, so clip rather than wrap
+impl AddAssign for Q15 {
+    fn add_assign(&mut self, rhs: Q15) {
+        self.0 = self.0.saturating_add(rhs.0);
+    }
+}
+
+impl AddAssign for Q31 {
+    fn add_assign(&mut self, rhs: Q31) {
+        self.0 = self.0.saturating_add(rhs.0);
+    }
+}
+
+/// Multiply two Q31 values, rounding to nearest.
+pub fn mul_q31(a: i32, b: i32) -> i32 {
+    ((a as i64 * b as i64 + (1 << 30)) >> 31) as i32
+}
+
+/// Multiply a value by a Q30 factor (1.0 = `1 << 30`), rounding to nearest.
+pub fn mul_q30(a: i32, b: i32) -> i32 {
+    ((a as i64 * b as i64 + (1 << 29)) >> 30) as i32
+}
+
+/// Convert a float table to Q15 at compile time, so the fixed point path never
+/// touches floats at runtime.
+pub const fn table_to_q15<const N: usize>(table: &[f32; N]) -> [Q15; N] {
+    let mut out = [Q15::ZERO; N];
+    let mut n = 0;
+    while n < N {
+        let scaled = table[n] * 32768.0;
+        out[n] = Q15(if scaled >= 32767.0 {
+            32767
+        } else {
+            scaled as i16
+        });
+        n += 1;
+    }
+    out
+}
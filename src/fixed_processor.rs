@@ -0,0 +1,309 @@
+//! Fixed point variant of `audio_processor::AudioProcessor` for MCUs without an FPU.
+//!
+//! Samples come in as Q15 and the FFT frame is Q27, leaving four bits of headroom for
+//! the unscaled inverse FFT. Phases are CORDIC binary angles (one turn is the whole
+//! `u32` range) and bin frequencies are Q16.16, so wrap_phase becomes wrapping
+//! arithmetic. No floating point is used at runtime.
+
+use crate::audio_processor::{BINS, BUFFER_SIZE, FFT_SIZE, HOP_SIZE, LATENCY, OVERLAP_GAIN};
+use crate::circular_buffer::CircularBuffer;
+use crate::cordic;
+use crate::fixed_fft::{self, ComplexFixed, FixedTwiddles};
+use crate::fixed_point::{table_to_q15, Q15, Q31};
+use crate::hann_window;
+
+const HANN_WINDOW_Q15: [Q15; FFT_SIZE] = table_to_q15(&hann_window::HANN_WINDOW);
+
+/// Phase advance of bin 1 over one hop, as a binary angle
+const BIN_PHASE_ADVANCE: u32 = ((1u64 << 32) * HOP_SIZE as u64 / FFT_SIZE as u64) as u32;
+
+/// `fixed_fft::rfft_1024` packs the real Nyquist bin into the imaginary part of the DC bin
+const NYQUIST: usize = FFT_SIZE / 2;
+
+/// Bits to drop going from a Q15 * Q15 product (Q30) to the Q27 FFT frame
+const FRAME_SHIFT: u32 = 3;
+
+/// `1 / OVERLAP_GAIN` in Q30, applied to each frame as it's added to the output
+const OUTPUT_SCALE: i64 = ((1u64 << 30) as f32 / OVERLAP_GAIN) as i64;
+
+/// `1.0` in Q16.16, the format of bin frequencies and the pitch shift ratio
+pub const UNITY_PITCH: i32 = 1 << 16;
+
+/// Convert a pitch shift ratio to the Q16.16 format `FixedPointProcessor` takes.
+pub fn pitch_shift_to_q16(pitch_shift: f32) -> i32 {
+    (pitch_shift * UNITY_PITCH as f32) as i32
+}
+
+/// Working memory for one hop, owned by the processor like `audio_processor::Scratch`
+struct FixedScratch {
+    frame: [ComplexFixed; FFT_SIZE / 2],
+    analysis_magnitudes: [i32; BINS],
+    analysis_frequencies: [i32; BINS],
+    synthesis_magnitudes: [i32; BINS],
+    synthesis_frequencies: [i32; BINS],
+}
+
+/// Fixed point `AudioProcessor`, taking and returning Q15 samples.
+///
+/// Like `AudioProcessor` it owns all of its memory. In a release build for
+/// `thumbv6m-none-eabi`, `process_sample` needs at most 512 bytes of stack (384 bytes
+/// measured with `-Zemit-stack-sizes`). The processor itself is about 27 KB.
+pub struct FixedPointProcessor {
+    in_buffer: CircularBuffer<Q15, BUFFER_SIZE>,
+    /// Holds final Q31 output samples, the overlap scaling of `1 / OVERLAP_GAIN` is
+    /// applied before adding to it
+    out_buffer: CircularBuffer<Q31, BUFFER_SIZE>,
+    last_input_phases: [u32; BINS],
+    last_output_phases: [u32; BINS],
+    twiddles: FixedTwiddles,
+    scratch: FixedScratch,
+    hop_counter: usize,
+    /// Q16.16
+    pitch_shift: i32,
+}
+
+impl FixedPointProcessor {
+    /// `pitch_shift` is a Q16.16 ratio, see `pitch_shift_to_q16`
+    pub fn new(pitch_shift: i32) -> FixedPointProcessor {
+        // Start a hop ahead of the read index, like AudioProcessor
+        let mut out_buffer = CircularBuffer::new(Q31::ZERO, Some(HOP_SIZE));
+        out_buffer.next_hop();
+
+        FixedPointProcessor {
+            in_buffer: CircularBuffer::new(Q15::ZERO, Some(0)),
+            out_buffer,
+            last_input_phases: [0; BINS],
+            last_output_phases: [0; BINS],
+            twiddles: fixed_fft::generate_twiddles(),
+            scratch: FixedScratch {
+                frame: [ComplexFixed::default(); FFT_SIZE / 2],
+                analysis_magnitudes: [0; BINS],
+                analysis_frequencies: [0; BINS],
+                synthesis_magnitudes: [0; BINS],
+                synthesis_frequencies: [0; BINS],
+            },
+            hop_counter: 0,
+            pitch_shift,
+        }
+    }
+
+    pub fn set_pitch_shift(&mut self, pitch_shift: i32) {
+        self.pitch_shift = pitch_shift;
+    }
+
+    /// Delay of the output in samples, the same as `AudioProcessor`
+    pub fn latency(&self) -> usize {
+        LATENCY
+    }
+
+    /// Push one input sample and get one output sample back, running the FFTs once
+    /// every `HOP_SIZE` samples.
+    pub fn process_sample(&mut self, sample: Q15) -> Q15 {
+        self.in_buffer.write(sample);
+
+        // Hop before reading, like AudioProcessor::process_sample
+        if self.hop_counter >= HOP_SIZE {
+            self.hop_counter = 0;
+            self.process_fft();
+            self.out_buffer.next_hop();
+            debug_assert!(
+                self.in_buffer.check().is_ok() && self.out_buffer.overruns() == 0,
+                "BUFFER_SIZE is too small for FFT_SIZE and HOP_SIZE"
+            );
+        }
+        self.hop_counter += 1;
+
+        // The overlap scaling is already applied in process_fft
+        output_to_q15(self.out_buffer.read_and_reset())
+    }
+
+    /// Process one hop. Mirrors `AudioProcessor::process_fft`, except the overlap
+    /// scaling is applied to what is added to `out_buffer`.
+    fn process_fft(&mut self) {
+        let FixedScratch {
+            frame,
+            analysis_magnitudes,
+            analysis_frequencies,
+            synthesis_magnitudes,
+            synthesis_frequencies,
+        } = &mut self.scratch;
+        let in_buffer = &mut self.in_buffer;
+        let last_input_phases = &mut self.last_input_phases;
+        let last_output_phases = &mut self.last_output_phases;
+        let twiddles = &self.twiddles;
+        let pitch_shift = self.pitch_shift;
+
+        // copy buffer into FFT input, starting one window ago, packing pairs of samples
+        in_buffer.push_read_back(FFT_SIZE - HOP_SIZE);
+        let (first, second) = in_buffer.read_segments(FFT_SIZE);
+        let samples = first.iter().chain(second).zip(HANN_WINDOW_Q15.iter());
+        for (n, (sample, window)) in samples.enumerate() {
+            let windowed = (sample.0 as i32 * window.0 as i32) >> FRAME_SHIFT;
+            if n % 2 == 0 {
+                frame[n / 2].re = windowed;
+            } else {
+                frame[n / 2].im = windowed;
+            }
+        }
+        in_buffer.skip_read(FFT_SIZE);
+
+        let fft = fixed_fft::rfft_1024(frame, twiddles);
+
+        // ANALYSIS
+        for i in 0..BINS {
+            let bin = unpack_bin(fft, i);
+            let (amplitude, phase) = cordic::vector(bin.re, bin.im);
+
+            // Phase difference from the last hop, less the advance expected at the bin
+            // centre frequency. Wrapping the subtraction wraps the phase.
+            let expected_advance = (i as u32).wrapping_mul(BIN_PHASE_ADVANCE);
+            let phase_diff = phase
+                .wrapping_sub(last_input_phases[i])
+                .wrapping_sub(expected_advance) as i32;
+
+            // Deviation from the bin centre in Q16.16 bins
+            let bin_deviation = ((phase_diff as i64 * (FFT_SIZE / HOP_SIZE) as i64) >> 16) as i32;
+
+            analysis_frequencies[i] = ((i as i32) << 16) + bin_deviation;
+            analysis_magnitudes[i] = amplitude;
+            last_input_phases[i] = phase;
+        }
+
+        // Zero out the synthesis bins, ready for new data, since the scratch buffers are reused
+        synthesis_magnitudes.fill(0);
+        synthesis_frequencies.fill(0);
+
+        // Handle the pitch shift, storing frequencies into new bins
+        for i in 0..BINS {
+            // find the nearest bin to the shifted frequency, negative bins land on DC
+            // like the saturating cast in process_fft
+            let new_bin = ((i as i64 * pitch_shift as i64 + (1 << 15)) >> 16).max(0) as usize;
+
+            // Ignore any bins that have shifted above Nyquist
+            if new_bin < BINS {
+                synthesis_magnitudes[new_bin] =
+                    synthesis_magnitudes[new_bin].saturating_add(analysis_magnitudes[i]);
+                synthesis_frequencies[new_bin] =
+                    ((analysis_frequencies[i] as i64 * pitch_shift as i64) >> 16) as i32;
+            }
+        }
+
+        // SYNTHESIS
+        for i in 0..BINS {
+            let amplitude = synthesis_magnitudes[i].min(cordic::MAX_MAGNITUDE);
+
+            // Turn the deviation from the bin centre back into a phase difference and add
+            // the expected advance at the bin centre frequency
+            let bin_deviation = synthesis_frequencies[i] - ((i as i32) << 16);
+            let phase_diff = ((bin_deviation as i64) << 16) * HOP_SIZE as i64 / FFT_SIZE as i64;
+            let out_phase = last_output_phases[i]
+                .wrapping_add(phase_diff as u32)
+                .wrapping_add((i as u32).wrapping_mul(BIN_PHASE_ADVANCE));
+
+            let (re, im) = cordic::rotate(amplitude, out_phase);
+            pack_bin(fft, i, ComplexFixed { re, im });
+
+            last_output_phases[i] = out_phase;
+        }
+
+        let res = fixed_fft::irfft_1024(fft, twiddles);
+
+        // Window again and overlap-add. Q27 * Q15 goes back to Q27, then the Q30 overlap
+        // scaling and a shift of 26 take it to Q31.
+        let samples = res.iter().flat_map(|val| [val.re, val.im]);
+        let (first, second) = self.out_buffer.write_segments(FFT_SIZE);
+        for ((out, sample), window) in first
+            .iter_mut()
+            .chain(second)
+            .zip(samples)
+            .zip(HANN_WINDOW_Q15.iter())
+        {
+            let windowed_val = (((sample as i64 * window.0 as i64) >> 15) * OUTPUT_SCALE) >> 26;
+            *out += Q31(windowed_val.clamp(i32::MIN as i64, i32::MAX as i64) as i32);
+        }
+        self.out_buffer.skip_write(FFT_SIZE);
+    }
+}
+
+/// Bin `i` of a packed half spectrum, like `audio_processor::unpack_bin`
+fn unpack_bin(fft: &[ComplexFixed; FFT_SIZE / 2], i: usize) -> ComplexFixed {
+    match i {
+        0 => ComplexFixed { re: fft[0].re, im: 0 },
+        NYQUIST => ComplexFixed { re: fft[0].im, im: 0 },
+        _ => fft[i],
+    }
+}
+
+/// Store bin `i` into a packed half spectrum, keeping only the real part of DC and Nyquist
+fn pack_bin(fft: &mut [ComplexFixed; FFT_SIZE / 2], i: usize, bin: ComplexFixed) {
+    match i {
+        0 => fft[0].re = bin.re,
+        NYQUIST => fft[0].im = bin.re,
+        _ => fft[i] = bin,
+    }
+}
+
+/// Round a Q31 output sample to Q15, saturating.
+fn output_to_q15(sample: Q31) -> Q15 {
+    Q15((sample.0.saturating_add(1 << 15) >> 16) as i16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_processor::AudioProcessor;
+    use libm::{log10f, sinf};
+
+    // Output phases integrate the estimated frequencies, so small differences between
+    // the two paths accumulate over time. Keep the comparison short.
+    const SAMPLES: usize = 16384;
+
+    fn test_signal() -> [f32; SAMPLES] {
+        let mut signal = [0.0; SAMPLES];
+        for (n, value) in signal.iter_mut().enumerate() {
+            *value = 0.4 * sinf(0.031 * n as f32) + 0.2 * sinf(0.173 * n as f32 + 1.0);
+        }
+        signal
+    }
+
+    fn render_float(input: &[f32; SAMPLES], pitch_shift: f32) -> [f32; SAMPLES] {
+        let mut processor = AudioProcessor::new(pitch_shift);
+        let mut output = [0.0; SAMPLES];
+        for (sample, out) in input.iter().zip(output.iter_mut()) {
+            *out = processor.process_sample(*sample);
+        }
+        output
+    }
+
+    fn render_fixed(input: &[f32; SAMPLES], pitch_shift: f32) -> [f32; SAMPLES] {
+        let mut processor = FixedPointProcessor::new(pitch_shift_to_q16(pitch_shift));
+        let mut output = [0.0; SAMPLES];
+        for (sample, out) in input.iter().zip(output.iter_mut()) {
+            *out = processor.process_sample(Q15::from_f32(*sample)).to_f32();
+        }
+        output
+    }
+
+    /// Signal to noise ratio of the fixed point output, taking the float output as the signal
+    fn snr_against_float(pitch_shift: f32) -> f32 {
+        let input = test_signal();
+        let float = render_float(&input, pitch_shift);
+        let fixed = render_fixed(&input, pitch_shift);
+
+        let mut signal_power = 0.0;
+        let mut noise_power = 0.0;
+        for (float, fixed) in float.iter().zip(fixed.iter()) {
+            signal_power += float * float;
+            noise_power += (float - fixed) * (float - fixed);
+        }
+        10.0 * log10f(signal_power / noise_power)
+    }
+
+    #[test]
+    fn fixed_point_tracks_float_path() {
+        for pitch_shift in [1.0, 1.5, 0.75, 1.2] {
+            let snr = snr_against_float(pitch_shift);
+            assert!(snr > 45.0, "pitch shift {pitch_shift}: {snr} dB");
+        }
+    }
+}
@@ -3,15 +3,14 @@ use libm::cosf;
 #[allow(dead_code)]
 const FFT_SIZE: usize = 1024;
 #[allow(dead_code)]
-const PI: f32 = 3.14159265358979323846264338327950288f32;
+const PI: f32 = core::f32::consts::PI;
 #[allow(dead_code)]
 pub fn generate_hanning_window() -> [f32; FFT_SIZE] {
     
     let mut window = [0.0; FFT_SIZE];
-    for n in 0..FFT_SIZE {
-        window[n] = 0.5 * (1.0 - cosf(2.0 * PI * n as f32 / (FFT_SIZE - 1) as f32));
+    for (n, value) in window.iter_mut().enumerate() {
+        *value = 0.5 * (1.0 - cosf(2.0 * PI * n as f32 / (FFT_SIZE - 1) as f32));
     }
-    println!("{:?}",window);
     window
 
 }
@@ -1044,3 +1043,27 @@ pub const HANN_WINDOW: [f32; 1024] = [
     9.417534e-6,
     0.0,
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_matches_generated_window() {
+        // The table came from a different cosf, so allow for the last bit
+        let generated = generate_hanning_window();
+        for (n, (table, generated)) in HANN_WINDOW.iter().zip(generated.iter()).enumerate() {
+            assert!((table - generated).abs() <= 1e-7, "sample {n}: {table} != {generated}");
+        }
+    }
+
+    #[test]
+    fn window_is_symmetric_and_peaks_at_one() {
+        assert_eq!(HANN_WINDOW[0], 0.0);
+        for n in 0..FFT_SIZE / 2 {
+            assert!((HANN_WINDOW[n] - HANN_WINDOW[FFT_SIZE - 1 - n]).abs() <= 1e-6);
+        }
+        let peak = HANN_WINDOW.iter().cloned().fold(0.0, f32::max);
+        assert!((peak - 1.0).abs() <= 1e-5);
+    }
+}
@@ -0,0 +1,123 @@
+use libm::{cosf, sinf};
+use microfft::Complex32;
+
+const FFT_SIZE: usize = 1024;
+const PI: f32 = core::f32::consts::PI;
+
+/// Twiddle factors `e^(j*2*pi*k/FFT_SIZE)` for the first quarter of the spectrum,
+/// which is all `irfft_1024` needs since bins are recombined in pairs.
+pub fn generate_twiddles() -> [Complex32; FFT_SIZE / 4] {
+    let mut twiddles = [Complex32 { re: 0.0, im: 0.0 }; FFT_SIZE / 4];
+    fill_twiddles(&mut twiddles);
+    twiddles
+}
+
+/// `generate_twiddles` into an existing table
+pub fn fill_twiddles(twiddles: &mut [Complex32; FFT_SIZE / 4]) {
+    for (k, twiddle) in twiddles.iter_mut().enumerate() {
+        let angle = 2.0 * PI * k as f32 / FFT_SIZE as f32;
+        *twiddle = Complex32 {
+            re: cosf(angle),
+            im: sinf(angle),
+        };
+    }
+}
+
+/// In-place 1024-point inverse real FFT.
+///
+/// Takes the half spectrum in the packed format produced by `microfft::real::rfft_1024`
+/// (DC in `input[0].re`, Nyquist in `input[0].im`) and returns the real time domain
+/// signal, normalised the same way as `microfft::inverse::ifft_1024`. Internally this
+/// recombines the spectrum into a 512-point complex spectrum whose inverse holds the
+/// even samples in the real parts and the odd samples in the imaginary parts, so only
+/// a 512-point complex IFFT is needed.
+pub fn irfft_1024<'a>(
+    input: &'a mut [Complex32; FFT_SIZE / 2],
+    twiddles: &[Complex32; FFT_SIZE / 4],
+) -> &'a mut [f32; FFT_SIZE] {
+    let m = FFT_SIZE / 2;
+    let j = Complex32 { re: 0.0, im: 1.0 };
+
+    // Unpack DC and Nyquist, which are both real
+    let dc = input[0].re;
+    let nyquist = input[0].im;
+    input[0] = Complex32 {
+        re: (dc + nyquist) * 0.5,
+        im: (dc - nyquist) * 0.5,
+    };
+
+    // Bins k and m - k depend on each other, so recombine them together
+    for k in 1..m / 2 {
+        let (x_k, x_mk) = (input[k], input[m - k]);
+        let twiddle = twiddles[k];
+        // The twiddle for m - k is e^(j*pi) * conj(twiddle)
+        let twiddle_mk = -twiddle.conj();
+
+        let even_k = (x_k + x_mk.conj()) * 0.5;
+        let odd_k = (x_k - x_mk.conj()) * 0.5 * twiddle;
+        let even_mk = (x_mk + x_k.conj()) * 0.5;
+        let odd_mk = (x_mk - x_k.conj()) * 0.5 * twiddle_mk;
+
+        input[k] = even_k + j * odd_k;
+        input[m - k] = even_mk + j * odd_mk;
+    }
+
+    // The middle bin pairs with itself and reduces to its conjugate
+    input[m / 2] = input[m / 2].conj();
+
+    let output = microfft::inverse::ifft_512(input);
+
+    // SAFETY: Complex32 is num_complex's `#[repr(C)] struct { re: f32, im: f32 }`: 8
+    // bytes, aligned to 4 like f32, without padding. An array of 512 of them is therefore
+    // 4096 contiguous bytes laid out re, im, re, im, ..., which is exactly
+    // `[f32; 1024]`, and its alignment satisfies f32's. The cast reference reborrows
+    // `output` (itself `input`) for `'a`, so nothing else can alias it. After the IFFT the
+    // real parts hold the even samples and the imaginary parts the odd samples.
+    unsafe { &mut *(output as *mut [Complex32; FFT_SIZE / 2]).cast::<[f32; FFT_SIZE]>() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_complex_ifft_on_a_hermitian_spectrum() {
+        let mut seed = 7u32;
+        let mut random = || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            seed as f32 / u32::MAX as f32 * 2.0 - 1.0
+        };
+
+        // Bins 1 to 511 at random, DC and Nyquist real and non-zero, as rfft packs them
+        let mut packed = [Complex32 { re: 0.0, im: 0.0 }; FFT_SIZE / 2];
+        for bin in packed.iter_mut() {
+            *bin = Complex32 {
+                re: random(),
+                im: random(),
+            };
+        }
+        packed[0] = Complex32 { re: 0.7, im: -0.4 };
+
+        // The same spectrum in full, mirrored with conjugates
+        let mut full = [Complex32 { re: 0.0, im: 0.0 }; FFT_SIZE];
+        full[0].re = packed[0].re;
+        full[FFT_SIZE / 2].re = packed[0].im;
+        for k in 1..FFT_SIZE / 2 {
+            full[k] = packed[k];
+            full[FFT_SIZE - k] = packed[k].conj();
+        }
+
+        let twiddles = generate_twiddles();
+        let real = irfft_1024(&mut packed, &twiddles);
+        let complex = microfft::inverse::ifft_1024(&mut full);
+        let peak = complex.iter().map(|x| x.re.abs()).fold(0.0, f32::max);
+        for (n, (real, complex)) in real.iter().zip(complex.iter()).enumerate() {
+            assert!(complex.im.abs() < 1e-4 * peak, "{n}: {}", complex.im);
+            assert!(
+                (real - complex.re).abs() < 1e-4 * peak,
+                "{n}: {real} {}",
+                complex.re
+            );
+        }
+    }
+}
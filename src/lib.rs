@@ -0,0 +1,24 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod audio_io;
+pub mod audio_processor;
+#[cfg(feature = "std")]
+pub mod batch;
+pub mod circular_buffer;
+pub mod config;
+pub mod cordic;
+pub mod fixed_fft;
+pub mod fixed_point;
+pub mod fixed_processor;
+pub mod hann_window;
+pub mod irfft;
+#[cfg(feature = "live")]
+pub mod live;
+pub mod math;
+pub mod midi;
+pub mod spsc_buffer;
+#[cfg(feature = "std")]
+pub mod stream;
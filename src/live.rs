@@ -0,0 +1,162 @@
+//! Live pitch shifting as a JACK client, with an audio input and output and a MIDI input
+//! playing the pitch, mix and formants as described in `vocoder::midi`.
+//!
+//! The processor runs in JACK's process callback. It's allocated up front and the pitch
+//! shift reaches it through an atomic, so the callback never allocates or locks.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use jack::{
+    AsyncClient, AudioIn, AudioOut, Client, ClientOptions, Control, MidiIn, Port, ProcessHandler,
+    ProcessScope,
+};
+
+use crate::midi::MidiProcessor;
+
+/// Name of the input port, under the client's name
+pub const INPUT_PORT: &str = "in";
+/// Name of the output port, under the client's name
+pub const OUTPUT_PORT: &str = "out";
+/// Name of the MIDI input port, under the client's name
+pub const MIDI_PORT: &str = "midi_in";
+
+/// Pitch shift shared between the control thread and the process callback
+#[derive(Clone)]
+pub struct PitchControl(Arc<AtomicU32>);
+
+impl PitchControl {
+    pub fn new(pitch_shift: f32) -> PitchControl {
+        PitchControl(Arc::new(AtomicU32::new(pitch_shift.to_bits())))
+    }
+
+    /// Takes effect from the next block the callback processes
+    pub fn set(&self, pitch_shift: f32) {
+        self.0.store(pitch_shift.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// The processor and its pitch control, without the JACK ports, so the processing can
+/// run without a server
+pub struct LiveProcessor {
+    processor: Box<MidiProcessor>,
+    pitch_control: PitchControl,
+    pitch_shift: f32,
+}
+
+impl LiveProcessor {
+    pub fn new(pitch_control: PitchControl) -> LiveProcessor {
+        let pitch_shift = pitch_control.get();
+        LiveProcessor {
+            processor: Box::new(MidiProcessor::new(pitch_shift)),
+            pitch_control,
+            pitch_shift,
+        }
+    }
+
+    /// Shift one block, picking up the pitch shift first. `output` is as long as `input`.
+    pub fn process_block(&mut self, input: &[f32], output: &mut [f32]) {
+        let pitch_shift = self.pitch_control.get();
+        if pitch_shift != self.pitch_shift {
+            self.pitch_shift = pitch_shift;
+            self.processor.set_pitch_shift(pitch_shift);
+        }
+        for (input, output) in input.iter().zip(output) {
+            *output = self.processor.process_sample(*input);
+        }
+    }
+
+    /// Apply a MIDI message from the next sample on
+    pub fn handle_midi(&mut self, message: &[u8]) {
+        self.processor.handle_message(message);
+    }
+}
+
+struct Handler {
+    input: Port<AudioIn>,
+    output: Port<AudioOut>,
+    midi: Port<MidiIn>,
+    processor: LiveProcessor,
+}
+
+impl ProcessHandler for Handler {
+    fn process(&mut self, _: &Client, scope: &ProcessScope) -> Control {
+        let input = self.input.as_slice(scope);
+        let output = self.output.as_mut_slice(scope);
+        // Process up to each MIDI event, so it applies from the frame it's timed at
+        let mut start = 0;
+        for event in self.midi.iter(scope) {
+            let time = (event.time as usize).clamp(start, input.len());
+            self.processor
+                .process_block(&input[start..time], &mut output[start..time]);
+            self.processor.handle_midi(event.bytes);
+            start = time;
+        }
+        self.processor
+            .process_block(&input[start..], &mut output[start..]);
+        Control::Continue
+    }
+}
+
+/// A running client, which leaves the JACK graph when dropped
+pub struct Live {
+    client: AsyncClient<(), Handler>,
+    pitch_control: PitchControl,
+}
+
+impl Live {
+    /// Register a client called `name` and start processing. It doesn't start a JACK
+    /// server, and the ports start out unconnected.
+    pub fn start(name: &str, pitch_shift: f32) -> Result<Live, jack::Error> {
+        let (client, _) = Client::new(name, ClientOptions::NO_START_SERVER)?;
+        let input = client.register_port(INPUT_PORT, AudioIn)?;
+        let output = client.register_port(OUTPUT_PORT, AudioOut)?;
+        let midi = client.register_port(MIDI_PORT, MidiIn)?;
+        let pitch_control = PitchControl::new(pitch_shift);
+        let handler = Handler {
+            input,
+            output,
+            midi,
+            processor: LiveProcessor::new(pitch_control.clone()),
+        };
+        Ok(Live {
+            client: client.activate_async((), handler)?,
+            pitch_control,
+        })
+    }
+
+    /// The client's name, which JACK may have changed to make it unique
+    pub fn name(&self) -> &str {
+        self.client.as_client().name()
+    }
+
+    pub fn client(&self) -> &Client {
+        self.client.as_client()
+    }
+
+    pub fn pitch_control(&self) -> &PitchControl {
+        &self.pitch_control
+    }
+
+    /// Connect `port` to the input, e.g. `system:capture_1`
+    pub fn connect_input(&self, port: &str) -> Result<(), jack::Error> {
+        let input = format!("{}:{INPUT_PORT}", self.name());
+        self.client().connect_ports_by_name(port, &input)
+    }
+
+    /// Connect `port` to the MIDI input, e.g. a keyboard's `system:midi_capture_1`
+    pub fn connect_midi(&self, port: &str) -> Result<(), jack::Error> {
+        let midi = format!("{}:{MIDI_PORT}", self.name());
+        self.client().connect_ports_by_name(port, &midi)
+    }
+
+    /// Connect the output to `port`, e.g. `system:playback_1`
+    pub fn connect_output(&self, port: &str) -> Result<(), jack::Error> {
+        let output = format!("{}:{OUTPUT_PORT}", self.name());
+        self.client().connect_ports_by_name(&output, port)
+    }
+}
@@ -1,203 +1,259 @@
-mod circular_buffer;
-mod hann_window;
-
-use circular_buffer::CircularBuffer;
-
-use hound::{WavReader, WavSpec, WavWriter};
-use libm::{atan2f, cosf, floorf, fmodf, sinf, sqrtf};
 use std::error::Error;
-const PI: f32 = 3.14159265358979323846264338327950288f32;
-const BUFFER_SIZE: usize = 3000;
-const FFT_SIZE: usize = 1024;
-const HOP_SIZE: usize = 128;
-const SCALE_FACTOR: f32 = 0.5;
-const PITCH_SHIFT: f32 = -1.0;
+use vocoder::audio_io::{self, Audio, FileFormat};
+use vocoder::audio_processor::{check_pitch_shift, shift_channel};
+use vocoder::stream::{StreamSpec, DEFAULT_RAW_SPEC};
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let path = "sample_f32.wav";
-    let path = "WeChooseToGoToTheMoon_f32.wav";
-    let mut reader = WavReader::open(path)?;
-    let spec = reader.spec();
-
-    match spec.sample_format {
-        hound::SampleFormat::Int => match spec.bits_per_sample {
-            _ => return Err(Box::from("Unsupported bit depth")),
-        },
-        hound::SampleFormat::Float => match spec.bits_per_sample {
-            32 => read_and_write_samples::<f32>(&mut reader, &spec)?,
-            _ => return Err(Box::from("Unsupported bit depth")),
-        },
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("analyze") => return analyze(&args[1..]),
+        Some("null") => return null(&args[1..]),
+        Some("live") => return live(&args[1..]),
+        Some("midi") => return midi(&args[1..]),
+        Some("stream") => return stream(&args[1..]),
+        Some("batch") => return batch(&args[1..]),
+        _ => {}
     }
 
+    // `vocoder [input] [output] [pitch shift] [--input-format F] [--output-format F]
+    // [--rate N] [--channels N]`, with the formats going by the extensions unless given.
+    // `--rate` and `--channels` describe raw input.
+    let mut paths = Vec::new();
+    let mut pitch_shift = 1.5;
+    let (mut input_format, mut output_format) = (None, None);
+    let mut raw = DEFAULT_RAW_SPEC;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--input-format" => input_format = Some(value()?.parse()?),
+            "--output-format" => output_format = Some(value()?.parse()?),
+            "--rate" => raw.sample_rate = value()?.parse()?,
+            "--channels" => raw.channels = value()?.parse()?,
+            _ if paths.len() < 2 => paths.push(arg.as_str()),
+            _ => pitch_shift = parse_pitch_shift(arg)?,
+        }
+    }
+    let input_path = paths
+        .first()
+        .copied()
+        .unwrap_or("WeChooseToGoToTheMoon_f32.wav");
+    let output_path = paths.get(1).copied().unwrap_or("processed_sample.wav");
+
+    let input_format = match input_format {
+        Some(format) => format,
+        None => FileFormat::from_path(input_path.as_ref())?,
+    };
+    let input_format = match input_format {
+        FileFormat::Raw(spec) => FileFormat::Raw(StreamSpec {
+            encoding: spec.encoding,
+            ..raw
+        }),
+        format => format,
+    };
+    let input = audio_io::read(input_path.as_ref(), Some(input_format))?;
+    let channels = input
+        .channels
+        .iter()
+        .map(|channel| shift_channel(channel, pitch_shift));
+    let output = input.with_channels(channels.collect());
+    audio_io::write(output_path.as_ref(), output_format, &output)?;
     Ok(())
 }
 
-fn read_and_write_samples<S>(
-    reader: &mut WavReader<std::io::BufReader<std::fs::File>>,
-    spec: &WavSpec,
-) -> Result<(), Box<dyn Error>>
-where
-    S: hound::Sample + std::fmt::Debug + hound::Sample,
-{
-    let output_spec = WavSpec { ..*spec };
-
-    let output_path = "processed_sample.wav";
-    let mut writer = WavWriter::create(output_path, output_spec)?;
-    let mut buffer_in: CircularBuffer<f32, BUFFER_SIZE> = CircularBuffer::new(0.0, Some(0));
-    let mut hop_counter = 0;
-    let mut buffer_out: CircularBuffer<f32, BUFFER_SIZE> = CircularBuffer::new(0.0, Some(HOP_SIZE));
-
-    let mut last_input_phases = [0.0; FFT_SIZE];
-    let mut last_output_phases = [0.0; FFT_SIZE];
-    let mut bin_frequencies = [0.0; FFT_SIZE / 2];
-
-    for sample in reader.samples::<f32>() {
-        let sample = sample.expect("Error reading sample");
-        // println!("Sample: {:?}", sample);
-
-        // Store the sample in the input buffer
-        buffer_in.write(sample);
-
-        // Read from the output buffer and reset the value
-        let out_sample = buffer_out.read_and_reset();
-
-        // Scale the output dow by the overlap factor
-        let scaled_out_sample = out_sample * HOP_SIZE as f32 / FFT_SIZE as f32;
-
-        // Increment the hop counter
-        if hop_counter >= HOP_SIZE {
-            hop_counter = 0;
-            process_fft(
-                &mut buffer_in,
-                &mut buffer_out,
-                &mut last_input_phases,
-                &mut last_output_phases,
-                &mut bin_frequencies,
-            );
-            // update the output buffer write index to the start of the next hop
-            // println!("-------- NEW HOP ------------------------");
-            buffer_out.next_hop();
-        }
-        hop_counter += 1;
-        writer.write_sample(scaled_out_sample)?;
-    }
+/// `vocoder analyze [input.wav] [pitch shift]` prints the quality metrics of
+/// `vocoder::analysis` for the first channel of the input
+fn analyze(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let path = args.first().map_or("WeChooseToGoToTheMoon_f32.wav", String::as_str);
+    let pitch_shift = match args.get(1) {
+        Some(pitch_shift) => parse_pitch_shift(pitch_shift)?,
+        None => 1.5,
+    };
+
+    let (input, sample_rate) = read_first_channel(path)?;
+    let report = vocoder::analysis::analyze(shift_channel, &input, sample_rate, pitch_shift);
+    println!("{path} at {pitch_shift}:");
+    println!("{report}");
+    Ok(())
+}
 
-    writer.finalize()?;
+/// `vocoder null [input.wav]` renders the first channel of the input at unity shift and
+/// prints what's left after subtracting the input
+fn null(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let path = args.first().map_or("WeChooseToGoToTheMoon_f32.wav", String::as_str);
+    let (input, _) = read_first_channel(path)?;
+    let null_test = vocoder::analysis::null_test(&input, &shift_channel(&input, 1.0));
+    println!("{path}:");
+    println!("{null_test}");
     Ok(())
 }
 
-fn process_fft(
-    in_buffer: &mut CircularBuffer<f32, BUFFER_SIZE>,
-    out_buffer: &mut CircularBuffer<f32, BUFFER_SIZE>,
-    last_input_phases: &mut [f32; FFT_SIZE],
-    last_output_phases: &mut [f32; FFT_SIZE],
-    bin_frequencies: &mut [f32; FFT_SIZE / 2],
-) {
-    let analysis_window_buffer: [f32; FFT_SIZE] = hann_window::HANN_WINDOW;
-
-    let mut unwrapped_buffer: [f32; FFT_SIZE] = [0.0; FFT_SIZE];
-    let mut full_spectrum: [microfft::Complex32; FFT_SIZE] =
-        [microfft::Complex32 { re: 0.0, im: 0.0 }; FFT_SIZE];
-    let mut analysis_magnitudes = [0.0; FFT_SIZE / 2];
-    let mut analysis_frequencies = [0.0; FFT_SIZE / 2];
-    let mut synthesis_magnitudes = [0.0; FFT_SIZE / 2];
-    let mut synthesis_frequencies = [0.0; FFT_SIZE / 2];
-    let mut synthesis_count = [0; FFT_SIZE / 2];
-
-    // copy buffer into FFT input, starting one window ago
-    in_buffer.push_read_back(FFT_SIZE - HOP_SIZE);
-    for n in 0..FFT_SIZE {
-        unwrapped_buffer[n] = in_buffer.read() * analysis_window_buffer[n]
+/// `vocoder stream [pitch shift] [--input f32|s16|wav] [--output f32|s16|wav] [--rate N]
+/// [--channels N]` shifts stdin to stdout, for pipelines like
+/// `sox in.flac -t wav - | vocoder stream 1.5 | ffmpeg -i - out.mp3`. Input defaults to
+/// WAV and output to the input's format. `--rate` and `--channels` describe raw input,
+/// 48000 and 1 by default.
+fn stream(args: &[String]) -> Result<(), Box<dyn Error>> {
+    use vocoder::stream::Format;
+
+    let mut pitch_shift = 1.5;
+    let mut input_format = Format::Wav;
+    let mut output_format = None;
+    let mut spec = DEFAULT_RAW_SPEC;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--input" => input_format = value()?.parse()?,
+            "--output" => output_format = Some(value()?.parse()?),
+            "--rate" => spec.sample_rate = value()?.parse()?,
+            "--channels" => spec.channels = value()?.parse()?,
+            _ => pitch_shift = parse_pitch_shift(arg)?,
+        }
     }
 
-    // Process the FFT based on the time domain input
-    let fft = microfft::real::rfft_1024(&mut unwrapped_buffer);
-
-    // ANALYSIS
-    for i in 0..fft.len() {
-        // Turn real and imaginary components into amplitude and phase
-        let amplitude = sqrtf(fft[i].re * fft[i].re + fft[i].im * fft[i].im);
-        let phase = atan2f(fft[i].im, fft[i].re);
-
-        // Calculate the phase difference in this bin between the last
-        // hop and this one, which will indirectly give us the exact frequency
-        let mut phase_diff = phase - last_input_phases[i];
-
-        // Subtract the amount of phase increment we'd expect to see based
-        // on the centre frequency of this bin (2*pi*n/gFftSize) for this
-        // hop size, then wrap to the range -pi to pi
-        let bin_centre_frequency = 2.0 * PI * i as f32 / FFT_SIZE as f32;
-        phase_diff = wrap_phase(phase_diff - bin_centre_frequency * HOP_SIZE as f32);
-
-        // Find deviation from the centre frequency
-        let bin_deviation = phase_diff * FFT_SIZE as f32 / HOP_SIZE as f32 / (2.0 * PI);
-
-        // Add the original bin number to get the fractional bin where this partial belongs
-        analysis_frequencies[i] = i as f32 + bin_deviation;
-        // Save the magnitude for later
-        analysis_magnitudes[i] = amplitude;
-        // Save the phase for next hop
-        last_input_phases[i] = phase;
+    let result = vocoder::stream::stream(
+        std::io::stdin().lock(),
+        input_format,
+        spec,
+        std::io::stdout().lock(),
+        output_format.unwrap_or(input_format),
+        pitch_shift,
+    );
+    match result {
+        // Whatever reads the output stopped, like `head` does, so stop quietly too
+        Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
     }
+}
 
-    // Zero out the synthesis bins, ready for new data (NOT done since it should already be zero)
-
-    // Handle the pitch shift, storing frequencies into new bins
-    for i in 0..FFT_SIZE / 2 {
-        // find the nearest bin to the shifted frequency
-        let new_bin = floorf(i as f32  * PITCH_SHIFT + 0.5) as usize;
-
-        // Ignore any bins that have shifted above Nyquist
-        if new_bin < FFT_SIZE / 2 {
-            synthesis_magnitudes[new_bin] += analysis_magnitudes[i];
-            synthesis_frequencies[new_bin] = analysis_frequencies[i] * PITCH_SHIFT;
+/// `vocoder batch <input dir|glob> <output dir> [pitch shift] [--name TEMPLATE]
+/// [--jobs N]` renders every WAV, FLAC or AIFF in a directory or matching a glob into
+/// the output directory, in parallel, and prints how each file went. Output names follow
+/// the template, `{name}_shifted.wav` by default, and their extensions pick the format.
+/// Fails if any file failed.
+fn batch(args: &[String]) -> Result<(), Box<dyn Error>> {
+    use vocoder::batch::{find_inputs, run, BatchOptions, DEFAULT_TEMPLATE};
+
+    let usage = "usage: vocoder batch <input dir|glob> <output dir> [pitch shift] \
+                 [--name TEMPLATE] [--jobs N]";
+    let mut paths = Vec::new();
+    let mut options = BatchOptions {
+        pitch_shift: 1.5,
+        template: DEFAULT_TEMPLATE.to_string(),
+        jobs: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--name" => options.template = value()?.clone(),
+            "--jobs" => options.jobs = Some(value()?.parse()?),
+            _ if paths.len() < 2 => paths.push(arg),
+            _ => options.pitch_shift = parse_pitch_shift(arg)?,
         }
     }
+    let [pattern, output_dir] = paths[..] else {
+        return Err(Box::from(usage));
+    };
 
-    // SYNTHESIS
-    for i in 0..FFT_SIZE / 2 {
-        let amplitude = synthesis_magnitudes[i];
-        // Get the fractional offset from the bin centre frequency
-
-        let bin_deviation = synthesis_frequencies[i] - i as f32;
-        // Multiply to get back to a phase value
-        let mut phase_diff = bin_deviation * 2.0 * PI * HOP_SIZE as f32 /FFT_SIZE as f32;
-        // Add the expected phase increment based on the bin centre frequency
-        let bin_centre_frequency = 2.0 * PI * i as f32 /FFT_SIZE as f32;
-        phase_diff += bin_centre_frequency * HOP_SIZE as f32;
-        // Advance the phase from the previous hop
-        let out_phase = wrap_phase(last_output_phases[i] + phase_diff);
-
-        // Now convert magnitude and phase back to real and imaginary components
-        fft[i].re = amplitude * cosf(out_phase);
-        fft[i].im = amplitude * sinf(out_phase);
-        // Also store the complex conjugate in the upper half of the spectrum
-
-        // Save the phase for the next hop
-        last_output_phases[i] = out_phase;
+    let inputs = find_inputs(pattern)?;
+    if inputs.is_empty() {
+        return Err(Box::from(format!("no input files in {pattern}")));
     }
+    let summary = run(&inputs, output_dir.as_ref(), &options);
+    println!("{summary}");
+    match summary.failures() {
+        0 => Ok(()),
+        failures => Err(Box::from(format!(
+            "{failures} of {} files failed",
+            summary.files.len()
+        ))),
+    }
+}
+
+/// `vocoder midi <input.wav> <control.mid> [output.wav]` renders the first channel of
+/// the input with its pitch, mix and formants played from a Standard MIDI File, see
+/// `vocoder::midi`
+fn midi(args: &[String]) -> Result<(), Box<dyn Error>> {
+    use vocoder::midi::{read_smf, render, MidiProcessor};
+
+    let [input_path, midi_path, ..] = args else {
+        return Err(Box::from("usage: vocoder midi <input.wav> <control.mid> [output.wav]"));
+    };
+    let output_path = args.get(2).map_or("processed_sample.wav", String::as_str);
+
+    let (input, sample_rate) = read_first_channel(input_path)?;
+    let events = read_smf(&std::fs::read(midi_path)?, sample_rate)?;
+    let output = render(&mut MidiProcessor::new(1.0), &input, &events);
+
+    let output = Audio {
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+        channels: vec![output],
+    };
+    audio_io::write(output_path.as_ref(), None, &output)?;
+    Ok(())
+}
 
-    // Reconstruct the full spectrum for the IFFT
-    for i in 0..(FFT_SIZE / 2) {
-        full_spectrum[i] = fft[i]; // First half directly
-        if i > 0 && i < (FFT_SIZE / 2) {
-            full_spectrum[FFT_SIZE - i] = fft[i].conj(); // Conjugate symmetry for the second half
+/// `vocoder live [pitch shift] [input port] [output port] [MIDI port]` shifts JACK audio
+/// live, as a client called `vocoder`. Each line on stdin sets a new pitch shift, and MIDI
+/// notes, pitch bend and CCs play it as in `vocoder::midi`; EOF or `q` quits.
+#[cfg(feature = "live")]
+fn live(args: &[String]) -> Result<(), Box<dyn Error>> {
+    use std::io::BufRead;
+
+    let pitch_shift = match args.first() {
+        Some(pitch_shift) => parse_pitch_shift(pitch_shift)?,
+        None => 1.5,
+    };
+    let live = vocoder::live::Live::start("vocoder", pitch_shift)?;
+    if let Some(port) = args.get(1) {
+        live.connect_input(port)?;
+    }
+    if let Some(port) = args.get(2) {
+        live.connect_output(port)?;
+    }
+    if let Some(port) = args.get(3) {
+        live.connect_midi(port)?;
+    }
+    println!(
+        "{name}:{} -> {name}:{} at {pitch_shift}, enter a pitch shift to change it or q to quit",
+        vocoder::live::INPUT_PORT,
+        vocoder::live::OUTPUT_PORT,
+        name = live.name()
+    );
+
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        match line.trim() {
+            "q" => break,
+            "" => {}
+            pitch_shift => match parse_pitch_shift(pitch_shift) {
+                Ok(pitch_shift) => live.pitch_control().set(pitch_shift),
+                Err(err) => eprintln!("{pitch_shift}: {err}"),
+            },
         }
     }
+    Ok(())
+}
 
-    // Run the inverse FFT
-    let res = microfft::inverse::ifft_1024(&mut full_spectrum);
+#[cfg(not(feature = "live"))]
+fn live(_: &[String]) -> Result<(), Box<dyn Error>> {
+    Err(Box::from("Built without JACK support, rebuild with `--features live`"))
+}
 
-    // Add time domain into the output buffer
-    for (n, val) in res.iter().enumerate() {
-        let windowed_val = val.re * analysis_window_buffer[n]; // Window again and scale
-        out_buffer.add_value(windowed_val);
-    }
+/// A pitch shift argument, refused unless the processor can shift by it
+fn parse_pitch_shift(arg: &str) -> Result<f32, Box<dyn Error>> {
+    Ok(check_pitch_shift(arg.parse()?).map_err(|err| err.to_string())?)
 }
 
-fn wrap_phase(phase_in: f32) -> f32 {
-    if phase_in >= 0.0 {
-        return fmodf(phase_in + PI, 2.0 * PI) - PI;
+/// The first channel of an audio file and its sample rate
+fn read_first_channel(path: &str) -> Result<(Vec<f32>, u32), Box<dyn Error>> {
+    let mut audio = audio_io::read(path.as_ref(), None)?;
+    if audio.channels.is_empty() {
+        return Err(Box::from("no channels"));
     }
-    fmodf(phase_in - PI, -2.0 * PI) + PI
+    Ok((audio.channels.swap_remove(0), audio.sample_rate))
 }
@@ -0,0 +1,122 @@
+//! Math backends for the analysis and synthesis loops in `process_fft`, which call
+//! `sqrt`, `atan2`, `sin` and `cos` once per bin every hop.
+//!
+//! `Libm` is accurate to within an ulp or so. `FastMath` uses micromath's
+//! approximations and is selected as the default by the `fast-math` feature. Its
+//! worst case errors against libm, checked by the tests below, are:
+//!
+//! | function | error                                         |
+//! |----------|-----------------------------------------------|
+//! | `sqrt`   | 0.2% relative (one Newton step on micromath)  |
+//! | `atan2`  | 0.003 rad                                     |
+//! | `sin`    | 0.002 absolute, for arguments in [-pi, pi]    |
+//! | `cos`    | 0.002 absolute, for arguments in [-pi, pi]    |
+//!
+//! `cargo bench --bench fast_math` compares the two on a rendered test signal.
+
+use micromath::F32Ext;
+
+pub trait MathBackend {
+    fn sqrt(x: f32) -> f32;
+    fn atan2(y: f32, x: f32) -> f32;
+    fn sin(x: f32) -> f32;
+    fn cos(x: f32) -> f32;
+}
+
+pub struct Libm;
+
+impl MathBackend for Libm {
+    fn sqrt(x: f32) -> f32 {
+        libm::sqrtf(x)
+    }
+
+    fn atan2(y: f32, x: f32) -> f32 {
+        libm::atan2f(y, x)
+    }
+
+    fn sin(x: f32) -> f32 {
+        libm::sinf(x)
+    }
+
+    fn cos(x: f32) -> f32 {
+        libm::cosf(x)
+    }
+}
+
+pub struct FastMath;
+
+// Called through F32Ext explicitly, since with std the inherent f32 methods would win
+impl MathBackend for FastMath {
+    fn sqrt(x: f32) -> f32 {
+        if x <= 0.0 {
+            return 0.0;
+        }
+        // micromath's bit trick is only within ~5%, one Newton step brings that to 0.2%
+        let estimate = F32Ext::sqrt(x);
+        0.5 * (estimate + x / estimate)
+    }
+
+    fn atan2(y: f32, x: f32) -> f32 {
+        // micromath gives NaN for the origin, which silent input produces in every bin
+        if x == 0.0 && y == 0.0 {
+            return 0.0;
+        }
+        F32Ext::atan2(y, x)
+    }
+
+    fn sin(x: f32) -> f32 {
+        F32Ext::sin(x)
+    }
+
+    fn cos(x: f32) -> f32 {
+        F32Ext::cos(x)
+    }
+}
+
+#[cfg(feature = "fast-math")]
+pub type DefaultMath = FastMath;
+#[cfg(not(feature = "fast-math"))]
+pub type DefaultMath = Libm;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::PI;
+
+    const STEPS: usize = 10_000;
+
+    fn sweep(min: f32, max: f32) -> impl Iterator<Item = f32> {
+        (0..=STEPS).map(move |step| min + (max - min) * step as f32 / STEPS as f32)
+    }
+
+    #[test]
+    fn sqrt_within_bound() {
+        for x in sweep(1e-6, 1e3) {
+            let error = (FastMath::sqrt(x) - Libm::sqrt(x)).abs() / Libm::sqrt(x);
+            assert!(error < 0.002, "sqrt({x}): {error}");
+        }
+        assert_eq!(FastMath::sqrt(0.0), 0.0);
+    }
+
+    #[test]
+    fn atan2_within_bound() {
+        for angle in sweep(-PI, PI) {
+            for length in [1e-4, 1.0, 300.0] {
+                let (y, x) = (length * Libm::sin(angle), length * Libm::cos(angle));
+                let error = (FastMath::atan2(y, x) - Libm::atan2(y, x)).abs();
+                // -pi and pi are the same angle
+                let error = error.min(2.0 * PI - error);
+                assert!(error < 0.003, "atan2({y}, {x}): {error}");
+            }
+        }
+        assert_eq!(FastMath::atan2(0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn sin_and_cos_within_bound() {
+        for x in sweep(-PI, PI) {
+            assert!((FastMath::sin(x) - Libm::sin(x)).abs() < 0.002, "sin({x})");
+            assert!((FastMath::cos(x) - Libm::cos(x)).abs() < 0.002, "cos({x})");
+        }
+    }
+}
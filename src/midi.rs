@@ -0,0 +1,288 @@
+//! MIDI control of the processor: a held note sets the pitch relative to a reference
+//! note, so a keyboard plays the shifted voice like a harmonizer, pitch bend bends the
+//! ratio, and two CCs set the dry/wet mix and the formant preservation.
+//!
+//! `MidiProcessor` takes raw channel messages on any channel, so it works the same from
+//! a live MIDI port or a Standard MIDI File. With `std`, `read_smf` turns a file into
+//! events timed in samples and `render` applies them to a signal offline.
+
+use libm::exp2f;
+
+use crate::audio_processor::{AudioProcessor, LATENCY};
+
+/// Note that plays the input at its own pitch
+pub const REFERENCE_NOTE: u8 = 60;
+/// Semitones either way at full pitch bend
+pub const BEND_RANGE: f32 = 2.0;
+/// Modulation wheel, sets the dry/wet mix
+pub const MIX_CC: u8 = 1;
+/// Timbre, sets the formant preservation
+pub const FORMANT_CC: u8 = 71;
+/// Held notes remembered for last note priority. A note pressed while this many are
+/// held pushes out the oldest.
+const MAX_HELD: usize = 16;
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const CONTROL_CHANGE: u8 = 0xB0;
+const PITCH_BEND: u8 = 0xE0;
+const RESET_ALL_CONTROLLERS: u8 = 121;
+const ALL_NOTES_OFF: u8 = 123;
+
+/// `AudioProcessor` with a dry path and settings driven by MIDI messages
+pub struct MidiProcessor {
+    processor: AudioProcessor,
+    /// The input delayed by the processor's latency, to mix with the shifted signal
+    dry: [f32; LATENCY],
+    dry_position: usize,
+    /// Pitch shift while no note is held
+    pitch_shift: f32,
+    held: [u8; MAX_HELD],
+    held_count: usize,
+    /// In semitones
+    bend: f32,
+    mix: f32,
+}
+
+impl MidiProcessor {
+    /// Starts with no note held, so `pitch_shift` applies, fully wet and without formant
+    /// preservation
+    pub fn new(pitch_shift: f32) -> MidiProcessor {
+        MidiProcessor {
+            processor: AudioProcessor::new(pitch_shift),
+            dry: [0.0; LATENCY],
+            dry_position: 0,
+            pitch_shift,
+            held: [0; MAX_HELD],
+            held_count: 0,
+            bend: 0.0,
+            mix: 1.0,
+        }
+    }
+
+    /// Pitch shift for when no note is held, bent like a note
+    pub fn set_pitch_shift(&mut self, pitch_shift: f32) {
+        self.pitch_shift = pitch_shift;
+        self.update_pitch_shift();
+    }
+
+    /// The ratio the processor shifts by now
+    pub fn current_pitch_shift(&self) -> f32 {
+        let base = match self.held_count {
+            0 => self.pitch_shift,
+            count => exp2f((self.held[count - 1] as f32 - REFERENCE_NOTE as f32) / 12.0),
+        };
+        base * exp2f(self.bend / 12.0)
+    }
+
+    /// Dry/wet mix, 0.0 is only the input delayed by the latency
+    pub fn mix(&self) -> f32 {
+        self.mix
+    }
+
+    /// Apply one channel message. Anything other than notes, pitch bend and the CCs
+    /// above is ignored, as are incomplete messages.
+    pub fn handle_message(&mut self, message: &[u8]) {
+        let [status, data1, data2] = match *message {
+            [status, data1, data2, ..] => [status, data1, data2],
+            _ => return,
+        };
+        match status & 0xF0 {
+            NOTE_ON if data2 > 0 => {
+                self.release(data1);
+                if self.held_count == MAX_HELD {
+                    self.held.copy_within(1.., 0);
+                    self.held_count -= 1;
+                }
+                self.held[self.held_count] = data1;
+                self.held_count += 1;
+            }
+            // A note on with no velocity is a note off
+            NOTE_ON | NOTE_OFF => self.release(data1),
+            PITCH_BEND => {
+                let bend = ((data2 as i32) << 7 | data1 as i32) - 8192;
+                self.bend = bend as f32 / 8192.0 * BEND_RANGE;
+            }
+            CONTROL_CHANGE => {
+                let value = data2 as f32 / 127.0;
+                match data1 {
+                    MIX_CC => self.mix = value,
+                    FORMANT_CC => self.processor.set_formant_preservation(value),
+                    RESET_ALL_CONTROLLERS => self.bend = 0.0,
+                    ALL_NOTES_OFF => self.held_count = 0,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        self.update_pitch_shift();
+    }
+
+    pub fn process_sample(&mut self, sample: f32) -> f32 {
+        let wet = self.processor.process_sample(sample);
+        let dry = core::mem::replace(&mut self.dry[self.dry_position], sample);
+        self.dry_position = (self.dry_position + 1) % LATENCY;
+        // Fully wet is exactly the processor's output
+        if self.mix == 1.0 {
+            wet
+        } else {
+            dry + self.mix * (wet - dry)
+        }
+    }
+
+    /// Delay of the output in samples, see `LATENCY`
+    pub fn latency(&self) -> usize {
+        LATENCY
+    }
+
+    fn release(&mut self, note: u8) {
+        if let Some(index) = self.held[..self.held_count]
+            .iter()
+            .position(|held| *held == note)
+        {
+            self.held.copy_within(index + 1..self.held_count, index);
+            self.held_count -= 1;
+        }
+    }
+
+    fn update_pitch_shift(&mut self) {
+        let pitch_shift = self.current_pitch_shift();
+        self.processor.set_pitch_shift(pitch_shift);
+    }
+}
+
+/// A channel message and the input sample it applies from. Two byte messages are
+/// padded with a zero.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MidiEvent {
+    pub sample: usize,
+    pub message: [u8; 3],
+}
+
+/// The channel messages of all tracks of a Standard MIDI File, in order, timed in
+/// samples at `sample_rate` following the file's tempo changes
+#[cfg(feature = "std")]
+pub fn read_smf(smf: &[u8], sample_rate: u32) -> Result<Vec<MidiEvent>, midly::Error> {
+    use midly::live::LiveEvent;
+    use midly::{MetaMessage, Smf, Timing, TrackEventKind};
+
+    let smf = Smf::parse(smf)?;
+    // Events of every track by absolute tick, keeping the order within a track
+    let mut events = Vec::new();
+    for track in &smf.tracks {
+        let mut tick = 0u64;
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            events.push((tick, event.kind));
+        }
+    }
+    events.sort_by_key(|(tick, _)| *tick);
+
+    // Seconds per tick, which tempo changes update for metrical timing
+    let mut tick_seconds = match smf.header.timing {
+        Timing::Metrical(ticks_per_beat) => 0.5 / ticks_per_beat.as_int() as f64,
+        Timing::Timecode(fps, subframes) => 1.0 / (fps.as_f32() as f64 * subframes as f64),
+    };
+    let (mut last_tick, mut seconds) = (0, 0.0);
+    let mut midi_events = Vec::new();
+    for (tick, kind) in events {
+        seconds += (tick - last_tick) as f64 * tick_seconds;
+        last_tick = tick;
+        match kind {
+            TrackEventKind::Meta(MetaMessage::Tempo(beat_micros)) => {
+                if let Timing::Metrical(ticks_per_beat) = smf.header.timing {
+                    tick_seconds =
+                        beat_micros.as_int() as f64 / 1e6 / ticks_per_beat.as_int() as f64;
+                }
+            }
+            TrackEventKind::Midi { channel, message } => {
+                let mut bytes = [0; 3];
+                LiveEvent::Midi { channel, message }
+                    .write_std(&mut bytes[..])
+                    .expect("channel messages are at most 3 bytes");
+                midi_events.push(MidiEvent {
+                    sample: (seconds * sample_rate as f64).round() as usize,
+                    message: bytes,
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(midi_events)
+}
+
+/// Render `input` through `processor`, applying each event from its sample on. The
+/// latency is taken out, so the output lines up with `input` and is as long.
+#[cfg(feature = "std")]
+pub fn render(processor: &mut MidiProcessor, input: &[f32], events: &[MidiEvent]) -> Vec<f32> {
+    use crate::audio_processor::render_aligned;
+
+    let mut events = events.iter().peekable();
+    let mut index = 0;
+    render_aligned(input.iter().copied(), 0.0, |sample| {
+        while let Some(event) = events.next_if(|event| event.sample <= index) {
+            processor.handle_message(&event.message);
+        }
+        index += 1;
+        processor.process_sample(sample)
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ratio(processor: &MidiProcessor, semitones: f32) {
+        let expected = exp2f(semitones / 12.0);
+        let ratio = processor.current_pitch_shift();
+        assert!(
+            (ratio - expected).abs() < 1e-5,
+            "{ratio}, expected {expected}"
+        );
+    }
+
+    #[test]
+    fn last_held_note_sets_the_pitch() {
+        let mut processor = MidiProcessor::new(1.0);
+        processor.handle_message(&[NOTE_ON, 67, 100]);
+        assert_ratio(&processor, 7.0);
+        processor.handle_message(&[NOTE_ON | 3, 55, 100]);
+        assert_ratio(&processor, -5.0);
+        // Releasing the last note goes back to the one still held
+        processor.handle_message(&[NOTE_ON, 55, 0]);
+        assert_ratio(&processor, 7.0);
+        processor.handle_message(&[NOTE_OFF, 67, 64]);
+        assert_eq!(processor.current_pitch_shift(), 1.0);
+
+        processor.set_pitch_shift(1.5);
+        processor.handle_message(&[NOTE_ON, 72, 100]);
+        assert_ratio(&processor, 12.0);
+        processor.handle_message(&[CONTROL_CHANGE, ALL_NOTES_OFF, 0]);
+        assert_eq!(processor.current_pitch_shift(), 1.5);
+    }
+
+    #[test]
+    fn pitch_bend_bends_the_ratio() {
+        let mut processor = MidiProcessor::new(1.0);
+        processor.handle_message(&[NOTE_ON, 64, 100]);
+        processor.handle_message(&[PITCH_BEND, 0, 0]);
+        assert_ratio(&processor, 4.0 - BEND_RANGE);
+        processor.handle_message(&[PITCH_BEND, 0, 0x60]);
+        assert_ratio(&processor, 4.0 + BEND_RANGE / 2.0);
+        processor.handle_message(&[CONTROL_CHANGE, RESET_ALL_CONTROLLERS, 0]);
+        assert_ratio(&processor, 4.0);
+    }
+
+    #[test]
+    fn dry_mix_is_the_input_delayed() {
+        let mut processor = MidiProcessor::new(1.5);
+        processor.handle_message(&[CONTROL_CHANGE, MIX_CC, 0]);
+        assert_eq!(processor.mix(), 0.0);
+        let input: Vec<f32> = (0..3 * LATENCY).map(|n| (n as f32 * 0.01).sin()).collect();
+        let output: Vec<f32> = input.iter().map(|x| processor.process_sample(*x)).collect();
+        assert!(output[..LATENCY].iter().all(|sample| *sample == 0.0));
+        assert_eq!(output[LATENCY..], input[..2 * LATENCY]);
+    }
+}
@@ -0,0 +1,286 @@
+//! Lock-free single-producer/single-consumer variant of `CircularBuffer`, for handing
+//! samples between an audio callback and a worker running the processor.
+//!
+//! Only atomic loads and stores are used, so it also works on cores without
+//! compare-and-swap such as the Cortex-M0+.
+
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::circular_buffer::BufferError;
+
+/// Ring buffer that is split into a `Producer` and a `Consumer` which can live on
+/// different threads, or in an interrupt and the main loop.
+///
+/// Instead of overwriting unread data or re-reading stale data like `CircularBuffer`,
+/// a full buffer rejects writes (an overrun) and an empty one rejects reads (an
+/// underrun). Both are counted so a misbehaving stream shows up in the stats.
+pub struct SpscBuffer<T, const N: usize> {
+    buffer: UnsafeCell<[T; N]>,
+    // Both positions count up to 2 * N before wrapping, so a full buffer can be told
+    // apart from an empty one
+    write_position: AtomicUsize,
+    read_position: AtomicUsize,
+    overruns: AtomicUsize,
+    underruns: AtomicUsize,
+}
+
+// The producer only writes slots the consumer has released and the other way round,
+// with the positions published using release/acquire ordering.
+unsafe impl<T: Send, const N: usize> Sync for SpscBuffer<T, N> {}
+
+impl<T: Copy, const N: usize> SpscBuffer<T, N> {
+    /// `const` so the buffer can be a `static` on targets without a heap.
+    pub const fn new(default_value: T) -> SpscBuffer<T, N> {
+        assert!(N > 0);
+        SpscBuffer {
+            buffer: UnsafeCell::new([default_value; N]),
+            write_position: AtomicUsize::new(0),
+            read_position: AtomicUsize::new(0),
+            overruns: AtomicUsize::new(0),
+            underruns: AtomicUsize::new(0),
+        }
+    }
+
+    /// Split into the two ends. Borrowing mutably guarantees there is only ever one of each.
+    pub fn split(&mut self) -> (Producer<'_, T, N>, Consumer<'_, T, N>) {
+        (Producer { buffer: self }, Consumer { buffer: self })
+    }
+
+    /// Number of values written but not read yet
+    pub fn len(&self) -> usize {
+        let write = self.write_position.load(Ordering::Acquire);
+        let read = self.read_position.load(Ordering::Acquire);
+        Self::distance(read, write)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Values the producer has dropped because the buffer was full
+    pub fn overruns(&self) -> usize {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Reads the consumer has attempted while the buffer was empty
+    pub fn underruns(&self) -> usize {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    fn distance(from: usize, to: usize) -> usize {
+        (to + 2 * N - from) % (2 * N)
+    }
+
+    fn advance(position: usize, len: usize) -> usize {
+        (position + len) % (2 * N)
+    }
+
+    /// Only one side ever updates each counter, so a load and a store is enough and
+    /// no read-modify-write atomics are needed.
+    fn count(counter: &AtomicUsize, amount: usize) {
+        counter.store(
+            counter.load(Ordering::Relaxed).wrapping_add(amount),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// The first slot. The two ends only ever touch their own slots through raw pointers
+    /// from here, never through a reference to the whole array, which would overlap the
+    /// other end's slots while it works on them.
+    fn slots(&self) -> *mut T {
+        self.buffer.get().cast()
+    }
+}
+
+pub struct Producer<'a, T, const N: usize> {
+    buffer: &'a SpscBuffer<T, N>,
+}
+
+impl<T: Copy, const N: usize> Producer<'_, T, N> {
+    /// Number of values that can be pushed before the buffer overruns
+    pub fn free(&self) -> usize {
+        N - self.buffer.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn overruns(&self) -> usize {
+        self.buffer.overruns()
+    }
+
+    pub fn push(&mut self, value: T) -> Result<(), BufferError> {
+        if self.push_slice(&[value]) == 1 {
+            Ok(())
+        } else {
+            Err(BufferError::Overrun)
+        }
+    }
+
+    /// Push as many of `values` as fit, returning how many were pushed. Anything that
+    /// didn't fit counts as overrun.
+    pub fn push_slice(&mut self, values: &[T]) -> usize {
+        let write = self.buffer.write_position.load(Ordering::Relaxed);
+        let read = self.buffer.read_position.load(Ordering::Acquire);
+        let free = N - SpscBuffer::<T, N>::distance(read, write);
+        let len = values.len().min(free);
+
+        let start = write % N;
+        let first_len = len.min(N - start);
+        let slots = self.buffer.slots();
+        // SAFETY: `start + first_len` and `len - first_len` are at most N, so both
+        // segments are in the array. Slots between the write and read positions belong
+        // to the producer until the new write position is published below, and `values`
+        // can't overlap them since nothing hands out references into the array.
+        unsafe {
+            ptr::copy_nonoverlapping(values.as_ptr(), slots.add(start), first_len);
+            ptr::copy_nonoverlapping(values[first_len..].as_ptr(), slots, len - first_len);
+        }
+
+        self.buffer
+            .write_position
+            .store(SpscBuffer::<T, N>::advance(write, len), Ordering::Release);
+
+        if len < values.len() {
+            SpscBuffer::<T, N>::count(&self.buffer.overruns, values.len() - len);
+        }
+        len
+    }
+}
+
+pub struct Consumer<'a, T, const N: usize> {
+    buffer: &'a SpscBuffer<T, N>,
+}
+
+impl<T: Copy, const N: usize> Consumer<'_, T, N> {
+    /// Number of values ready to be popped
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn underruns(&self) -> usize {
+        self.buffer.underruns()
+    }
+
+    pub fn pop(&mut self) -> Result<T, BufferError> {
+        let write = self.buffer.write_position.load(Ordering::Acquire);
+        let read = self.buffer.read_position.load(Ordering::Relaxed);
+        if write == read {
+            SpscBuffer::<T, N>::count(&self.buffer.underruns, 1);
+            return Err(BufferError::Underrun);
+        }
+
+        // SAFETY: the slot at the read position was published by the producer and stays
+        // with the consumer until the read position moves past it
+        let value = unsafe { ptr::read(self.buffer.slots().add(read % N)) };
+        self.buffer
+            .read_position
+            .store(SpscBuffer::<T, N>::advance(read, 1), Ordering::Release);
+        Ok(value)
+    }
+
+    /// Fill as much of `out` as there is data for, returning how many values were
+    /// popped. Anything left unfilled counts as underrun.
+    pub fn pop_slice(&mut self, out: &mut [T]) -> usize {
+        let write = self.buffer.write_position.load(Ordering::Acquire);
+        let read = self.buffer.read_position.load(Ordering::Relaxed);
+        let len = out.len().min(SpscBuffer::<T, N>::distance(read, write));
+
+        let start = read % N;
+        let first_len = len.min(N - start);
+        let slots = self.buffer.slots();
+        // SAFETY: both segments are in the array as in `push_slice`, and as in `pop`
+        // every slot up to the write position is the consumer's
+        unsafe {
+            ptr::copy_nonoverlapping(slots.add(start), out.as_mut_ptr(), first_len);
+            ptr::copy_nonoverlapping(slots, out[first_len..].as_mut_ptr(), len - first_len);
+        }
+
+        self.buffer
+            .read_position
+            .store(SpscBuffer::<T, N>::advance(read, len), Ordering::Release);
+
+        if len < out.len() {
+            SpscBuffer::<T, N>::count(&self.buffer.underruns, out.len() - len);
+        }
+        len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_overrun_and_underrun() {
+        let mut buffer: SpscBuffer<i32, 4> = SpscBuffer::new(0);
+        let (mut producer, mut consumer) = buffer.split();
+
+        assert_eq!(consumer.pop(), Err(BufferError::Underrun));
+        assert_eq!(producer.push_slice(&[1, 2, 3]), 3);
+        assert_eq!(producer.free(), 1);
+        assert_eq!(producer.push_slice(&[4, 5, 6]), 1);
+        assert_eq!(producer.push(7), Err(BufferError::Overrun));
+        assert_eq!(consumer.len(), 4);
+
+        let mut out = [0; 6];
+        assert_eq!(consumer.pop_slice(&mut out), 4);
+        assert_eq!(out, [1, 2, 3, 4, 0, 0]);
+
+        assert_eq!(buffer.overruns(), 3);
+        assert_eq!(buffer.underruns(), 3);
+        assert!(buffer.is_empty());
+    }
+
+    /// Also what `cargo +nightly miri test --lib spsc_buffer` checks for data races, with
+    /// the two ends wrapping around a small buffer many times
+    #[test]
+    fn hands_samples_between_threads_in_order() {
+        const TOTAL: u32 = if cfg!(miri) { 2_000 } else { 100_000 };
+        let mut buffer: SpscBuffer<u32, 64> = SpscBuffer::new(0);
+        let (mut producer, mut consumer) = buffer.split();
+
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                let mut next = 0;
+                while next < TOTAL {
+                    let block = [next, next + 1, next + 2, next + 3, next + 4];
+                    let len = (TOTAL - next).min(5) as usize;
+                    let pushed = producer.push_slice(&block[..len]);
+                    if pushed == 0 {
+                        std::thread::yield_now();
+                    }
+                    next += pushed as u32;
+                }
+            });
+
+            let mut expected = 0;
+            let mut out = [0; 7];
+            while expected < TOTAL {
+                let len = consumer.pop_slice(&mut out);
+                if len == 0 {
+                    std::thread::yield_now();
+                }
+                for value in &out[..len] {
+                    assert_eq!(*value, expected);
+                    expected += 1;
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,329 @@
+//! Streaming from a reader to a writer, for `vocoder stream` in shell pipelines.
+//!
+//! Input is raw interleaved PCM or a WAV, and so is output. Pipes can't seek, so a WAV
+//! written here has its lengths set to the maximum, the way sox and ffmpeg write them
+//! on pipes, and a WAV read here is read to the end of the data chunk or of the input,
+//! whichever comes first, so those placeholder lengths work too. Each channel gets its
+//! own processor, and the output is as long as the input and lined up with it. Output is
+//! written and flushed a hop at a time, so whatever reads it doesn't wait on a buffer.
+
+use std::cell::Cell;
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+
+use crate::audio_processor::{render_aligned, AudioProcessor, HOP_SIZE};
+
+/// How samples are stored, little endian
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    F32,
+    S16,
+}
+
+impl Encoding {
+    pub(crate) fn bytes(self) -> usize {
+        match self {
+            Encoding::F32 => 4,
+            Encoding::S16 => 2,
+        }
+    }
+
+    pub(crate) fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            Encoding::F32 => f32::from_le_bytes(bytes.try_into().unwrap()),
+            Encoding::S16 => i16::from_le_bytes(bytes.try_into().unwrap()) as f32 / 32768.0,
+        }
+    }
+
+    pub(crate) fn encode(self, sample: f32, out: &mut Vec<u8>) {
+        match self {
+            Encoding::F32 => out.extend_from_slice(&sample.to_le_bytes()),
+            Encoding::S16 => {
+                let sample = (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16;
+                out.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Raw(Encoding),
+    Wav,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Format, String> {
+        match format {
+            "f32" => Ok(Format::Raw(Encoding::F32)),
+            "s16" => Ok(Format::Raw(Encoding::S16)),
+            "wav" => Ok(Format::Wav),
+            _ => Err(format!("unknown format {format}, expected f32, s16 or wav")),
+        }
+    }
+}
+
+/// What raw input is, and what a WAV input says it is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamSpec {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub encoding: Encoding,
+}
+
+/// What raw input is taken to be unless told otherwise
+pub const DEFAULT_RAW_SPEC: StreamSpec = StreamSpec {
+    sample_rate: 48_000,
+    channels: 1,
+    encoding: Encoding::F32,
+};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// Longest fmt chunk read, the 40 bytes of `WAVE_FORMAT_EXTENSIBLE` with room to spare.
+/// Its length comes from the input, so don't allocate whatever it says.
+const MAX_FMT_LENGTH: usize = 64;
+/// Most channels streamed. Each gets a processor of about 28 KB, and the count comes from
+/// the input, so don't allocate for whatever it says.
+pub const MAX_CHANNELS: u16 = 32;
+
+/// One frame of samples, of which only the stream's channels are used. A fixed array, so
+/// frames are decoded without allocating.
+type Frame = [f32; MAX_CHANNELS as usize];
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes(bytes[..2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+/// Read a WAV header up to the start of the samples, returning what they are and the
+/// length of the data chunk in bytes
+pub fn read_wav_header(input: &mut impl Read) -> io::Result<(StreamSpec, u32)> {
+    let mut riff = [0; 12];
+    input.read_exact(&mut riff)?;
+    if &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+        return Err(invalid("not a WAV"));
+    }
+
+    let mut spec = None;
+    loop {
+        let mut header = [0; 8];
+        input.read_exact(&mut header)?;
+        let length = read_u32(&header[4..]);
+        match &header[..4] {
+            b"fmt " => {
+                if length as usize > MAX_FMT_LENGTH {
+                    return Err(invalid("fmt chunk too long"));
+                }
+                let mut fmt = [0; MAX_FMT_LENGTH];
+                let fmt = &mut fmt[..length as usize];
+                input.read_exact(fmt)?;
+                if fmt.len() < 16 {
+                    return Err(invalid("fmt chunk too short"));
+                }
+                let mut format = read_u16(fmt);
+                if format == WAVE_FORMAT_EXTENSIBLE && fmt.len() >= 26 {
+                    // The format is the start of the subformat GUID
+                    format = read_u16(&fmt[24..]);
+                }
+                let encoding = match (format, read_u16(&fmt[14..])) {
+                    (WAVE_FORMAT_IEEE_FLOAT, 32) => Encoding::F32,
+                    (WAVE_FORMAT_PCM, 16) => Encoding::S16,
+                    _ => return Err(invalid("Unsupported bit depth, expected f32 or s16")),
+                };
+                spec = Some(StreamSpec {
+                    sample_rate: read_u32(&fmt[4..]),
+                    channels: read_u16(&fmt[2..]),
+                    encoding,
+                });
+            }
+            b"data" => {
+                let spec = spec.ok_or_else(|| invalid("data before fmt chunk"))?;
+                return Ok((spec, length));
+            }
+            // Chunks are padded to an even length
+            _ => {
+                let skip = length as u64 + (length & 1) as u64;
+                io::copy(&mut input.take(skip), &mut io::sink())?;
+            }
+        }
+    }
+}
+
+/// A WAV header for a stream of unknown length, or `InvalidInput` if `spec` has more
+/// channels or a higher rate than a WAV header can describe
+pub fn wav_header(spec: &StreamSpec) -> io::Result<Vec<u8>> {
+    let (format, bits) = match spec.encoding {
+        Encoding::F32 => (WAVE_FORMAT_IEEE_FLOAT, 32),
+        Encoding::S16 => (WAVE_FORMAT_PCM, 16),
+    };
+    let too_big = |what: &str| {
+        io::Error::new(ErrorKind::InvalidInput, format!("{what} too big for a WAV"))
+    };
+    let block_align = spec
+        .channels
+        .checked_mul(bits / 8)
+        .ok_or_else(|| too_big("channel count"))?;
+    let byte_rate = spec
+        .sample_rate
+        .checked_mul(block_align as u32)
+        .ok_or_else(|| too_big("byte rate"))?;
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&format.to_le_bytes());
+    header.extend_from_slice(&spec.channels.to_le_bytes());
+    header.extend_from_slice(&spec.sample_rate.to_le_bytes());
+    header.extend_from_slice(&byte_rate.to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&bits.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    Ok(header)
+}
+
+/// Fill `buffer` as far as the input goes, returning how much was read
+fn read_full(input: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// Decoded frames of an input, up to the end of it or of its data chunk. A trailing
+/// partial frame is dropped, and a read error ends the frames and is put in `error`,
+/// which the renderer checks while the frames are borrowed away from it.
+struct Frames<'a, R> {
+    input: R,
+    encoding: Encoding,
+    /// Bytes left in the data chunk
+    remaining: u64,
+    /// One frame
+    bytes: Vec<u8>,
+    error: &'a Cell<Option<io::Error>>,
+}
+
+impl<R: Read> Iterator for Frames<'_, R> {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        let wanted = self.remaining.min(self.bytes.len() as u64) as usize;
+        match read_full(&mut self.input, &mut self.bytes[..wanted]) {
+            Ok(read) if read == self.bytes.len() => {
+                self.remaining -= read as u64;
+                let mut frame = [0.0; MAX_CHANNELS as usize];
+                let samples = self.bytes.chunks_exact(self.encoding.bytes());
+                for (sample, bytes) in frame.iter_mut().zip(samples) {
+                    *sample = self.encoding.decode(bytes);
+                }
+                Some(frame)
+            }
+            Ok(_) => None,
+            Err(err) => {
+                self.error.set(Some(err));
+                None
+            }
+        }
+    }
+}
+
+/// Shift `input` into `output` by `pitch_shift`. `raw_spec` describes raw input, a WAV
+/// input describes itself. WAV output has the input's encoding, raw output the one
+/// given. A trailing partial frame is dropped. A read error is returned without
+/// rendering the latency's worth of tail a complete input ends with, so the output stops
+/// `LATENCY` frames short of where the input broke off.
+pub fn stream(
+    mut input: impl Read,
+    input_format: Format,
+    raw_spec: StreamSpec,
+    mut output: impl Write,
+    output_format: Format,
+    pitch_shift: f32,
+) -> io::Result<()> {
+    let (input_spec, remaining) = match input_format {
+        Format::Raw(encoding) => (
+            StreamSpec {
+                encoding,
+                ..raw_spec
+            },
+            u64::MAX,
+        ),
+        Format::Wav => {
+            let (spec, length) = read_wav_header(&mut input)?;
+            (spec, length as u64)
+        }
+    };
+    if input_spec.channels == 0 {
+        return Err(invalid("no channels"));
+    }
+    if input_spec.channels > MAX_CHANNELS {
+        return Err(invalid("too many channels"));
+    }
+    let output_encoding = match output_format {
+        Format::Raw(encoding) => encoding,
+        Format::Wav => {
+            output.write_all(&wav_header(&input_spec)?)?;
+            input_spec.encoding
+        }
+    };
+
+    let channels = input_spec.channels as usize;
+    let frame_bytes = channels * input_spec.encoding.bytes();
+    let mut processors: Vec<AudioProcessor> = (0..channels)
+        .map(|_| AudioProcessor::new(pitch_shift))
+        .collect();
+    let error = Cell::new(None);
+    let frames = Frames {
+        input: BufReader::with_capacity(HOP_SIZE * frame_bytes, input),
+        encoding: input_spec.encoding,
+        remaining,
+        bytes: vec![0; frame_bytes],
+        error: &error,
+    };
+    // Up to a hop's worth of frames at a time, so the pipe keeps moving
+    let chunk_bytes = HOP_SIZE * channels * output_encoding.bytes();
+    let mut out_bytes = Vec::with_capacity(chunk_bytes);
+
+    let silence = [0.0; MAX_CHANNELS as usize];
+    let shifted = render_aligned(frames, silence, |mut frame: Frame| {
+        for (processor, sample) in processors.iter_mut().zip(&mut frame) {
+            *sample = processor.process_sample(*sample);
+        }
+        frame
+    });
+    for frame in shifted {
+        // The input broke off, so this and the rest is the tail of a complete input
+        if let Some(err) = error.take() {
+            output.write_all(&out_bytes)?;
+            output.flush()?;
+            return Err(err);
+        }
+        for sample in &frame[..channels] {
+            output_encoding.encode(*sample, &mut out_bytes);
+        }
+        if out_bytes.len() >= chunk_bytes {
+            output.write_all(&out_bytes)?;
+            output.flush()?;
+            out_bytes.clear();
+        }
+    }
+    output.write_all(&out_bytes)?;
+    output.flush()
+}
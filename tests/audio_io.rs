@@ -0,0 +1,213 @@
+//! Reading and writing WAV, FLAC, AIFF and raw PCM, and the binary rendering between them.
+
+mod common;
+
+use std::path::Path;
+use std::process::Command;
+
+use hound::SampleFormat;
+use vocoder::analysis::sine_sweep;
+use vocoder::audio_io::{read, write, Audio, FileFormat};
+use vocoder::stream::StreamSpec;
+
+use common::scratch;
+
+const SAMPLE_RATE: u32 = 44_100;
+
+/// Stereo sweep already on the grid of `bits` bit integers
+fn stereo(frames: usize, bits: u16) -> Audio {
+    let scale = (1 << (bits - 1)) as f32;
+    let sweep = sine_sweep(SAMPLE_RATE);
+    let channel = |gain: f32| {
+        let samples = sweep.iter().take(frames);
+        samples
+            .map(|sample| (gain * sample * scale).round() / scale)
+            .collect()
+    };
+    Audio {
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: bits,
+        sample_format: SampleFormat::Int,
+        channels: vec![channel(0.8), channel(-0.4)],
+    }
+}
+
+/// An AIFF of 16 bit big endian samples, which nothing in the crate writes
+fn aiff(audio: &Audio) -> Vec<u8> {
+    let frames = audio.frames() as u32;
+    let channels = audio.channels.len() as u16;
+    let mut comm = Vec::new();
+    comm.extend_from_slice(&channels.to_be_bytes());
+    comm.extend_from_slice(&frames.to_be_bytes());
+    comm.extend_from_slice(&16u16.to_be_bytes());
+    // The sample rate as an 80 bit float
+    let exponent = 31 - audio.sample_rate.leading_zeros();
+    comm.extend_from_slice(&(16383 + exponent as u16).to_be_bytes());
+    comm.extend_from_slice(&((audio.sample_rate as u64) << (63 - exponent)).to_be_bytes());
+
+    let mut ssnd = vec![0; 8];
+    for frame in 0..audio.frames() {
+        for channel in &audio.channels {
+            let sample = (channel[frame] * 32768.0) as i16;
+            ssnd.extend_from_slice(&sample.to_be_bytes());
+        }
+    }
+
+    let mut form = b"AIFF".to_vec();
+    for (id, chunk) in [(b"COMM", comm), (b"SSND", ssnd)] {
+        form.extend_from_slice(id);
+        form.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+        form.extend_from_slice(&chunk);
+    }
+    let mut bytes = b"FORM".to_vec();
+    bytes.extend_from_slice(&(form.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&form);
+    bytes
+}
+
+#[test]
+fn formats_follow_extensions_and_flags() {
+    let path = Path::new("clips/take.FLAC");
+    assert_eq!(FileFormat::from_path(path), Ok(FileFormat::Flac));
+    assert_eq!(
+        FileFormat::from_path(Path::new("a.aif")),
+        Ok(FileFormat::Aiff)
+    );
+    assert!(FileFormat::from_path(Path::new("a.mp3")).is_err());
+    assert_eq!("wav".parse(), Ok(FileFormat::Wav));
+
+    let dir = scratch("flags");
+    let audio = stereo(1_000, 16);
+    // The flag wins over the extension
+    let path = dir.join("actually_flac.wav");
+    write(&path, Some(FileFormat::Flac), &audio).unwrap();
+    assert_eq!(&std::fs::read(&path).unwrap()[..4], b"fLaC");
+    assert_eq!(read(&path, Some(FileFormat::Flac)).unwrap(), audio);
+    assert!(read(&path, None).is_err());
+    assert!(write(&dir.join("out.aiff"), None, &audio).is_err());
+}
+
+#[test]
+fn flac_round_trips_exactly() {
+    let dir = scratch("flac");
+    for bits in [16, 24] {
+        let audio = stereo(30_000, bits);
+        let path = dir.join(format!("{bits}.flac"));
+        write(&path, None, &audio).unwrap();
+        assert_eq!(read(&path, None).unwrap(), audio, "{bits} bit");
+    }
+
+    // Float is written as 24 bit
+    let float = Audio {
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+        ..stereo(5_000, 24)
+    };
+    let path = dir.join("float.flac");
+    write(&path, None, &float).unwrap();
+    let read_back = read(&path, None).unwrap();
+    assert_eq!(read_back.bits_per_sample, 24);
+    assert_eq!(read_back.channels, float.channels);
+}
+
+#[test]
+fn aiff_decodes_like_wav() {
+    let dir = scratch("aiff");
+    let audio = stereo(10_000, 16);
+    let path = dir.join("input.aiff");
+    std::fs::write(&path, aiff(&audio)).unwrap();
+    assert_eq!(read(&path, None).unwrap(), audio);
+
+    let wav = dir.join("input.wav");
+    write(&wav, None, &audio).unwrap();
+    assert_eq!(read(&wav, None).unwrap(), audio);
+}
+
+#[test]
+fn binary_renders_aiff_to_flac() {
+    let dir = scratch("binary");
+    let audio = stereo(SAMPLE_RATE as usize / 2, 16);
+    let input = dir.join("input.aiff");
+    std::fs::write(&input, aiff(&audio)).unwrap();
+
+    let output = dir.join("output.flac");
+    let status = Command::new(env!("CARGO_BIN_EXE_vocoder"))
+        .args([&input, &output])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let rendered = read(&output, None).unwrap();
+    assert_eq!(rendered.channels.len(), 2);
+    assert_eq!(rendered.bits_per_sample, 16);
+    assert_eq!(rendered.frames(), audio.frames());
+
+    // And back to WAV with the format given, in the input's format
+    let wav = dir.join("output");
+    let status = Command::new(env!("CARGO_BIN_EXE_vocoder"))
+        .args([&output, &wav])
+        .args(["--output-format", "wav"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let spec = hound::WavReader::open(&wav).unwrap().spec();
+    assert_eq!((spec.channels, spec.bits_per_sample), (2, 16));
+}
+
+#[test]
+fn raw_round_trips_with_the_spec_given() {
+    let dir = scratch("raw");
+    let audio = stereo(2_000, 16);
+    let path = dir.join("take.s16");
+    write(&path, None, &audio).unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 2_000 * 2 * 2);
+
+    let FileFormat::Raw(spec) = FileFormat::from_path(&path).unwrap() else {
+        panic!("s16 is raw");
+    };
+    let spec = StreamSpec {
+        sample_rate: SAMPLE_RATE,
+        channels: 2,
+        ..spec
+    };
+    assert_eq!(read(&path, Some(FileFormat::Raw(spec))).unwrap(), audio);
+}
+
+#[test]
+fn binary_renders_raw_to_wav() {
+    let dir = scratch("binary_raw");
+    let audio = stereo(SAMPLE_RATE as usize / 2, 16);
+    let input = dir.join("input.pcm");
+    write(&input, Some("s16".parse().unwrap()), &audio).unwrap();
+
+    let output = dir.join("output.wav");
+    let status = Command::new(env!("CARGO_BIN_EXE_vocoder"))
+        .args([&input, &output])
+        .args(["--input-format", "s16", "--channels", "2"])
+        .args(["--rate", "44100"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let rendered = read(&output, None).unwrap();
+    assert_eq!(rendered.sample_rate, SAMPLE_RATE);
+    assert_eq!(rendered.channels.len(), 2);
+    assert_eq!(rendered.frames(), audio.frames());
+}
+
+#[test]
+fn binary_takes_the_pitch_shift() {
+    let dir = scratch("binary_pitch");
+    let input = dir.join("input.wav");
+    write(&input, None, &stereo(SAMPLE_RATE as usize / 4, 16)).unwrap();
+
+    let render = |pitch_shift: &str| {
+        let output = dir.join(format!("output_{pitch_shift}.wav"));
+        let status = Command::new(env!("CARGO_BIN_EXE_vocoder"))
+            .args([&input, &output])
+            .arg(pitch_shift)
+            .status()
+            .unwrap();
+        status.success().then(|| read(&output, None).unwrap())
+    };
+    assert_ne!(render("1.5"), render("0.75"));
+    assert_eq!(render("-1"), None);
+}
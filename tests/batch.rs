@@ -0,0 +1,194 @@
+//! `vocoder batch`: many files rendered in parallel, with failures reported and not
+//! stopping the rest.
+
+mod common;
+
+use std::path::Path;
+use std::process::Command;
+
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use vocoder::analysis::sine_sweep;
+use vocoder::audio_processor::shift_channel;
+use vocoder::batch::{find_inputs, output_path, run, BatchOptions, DEFAULT_TEMPLATE};
+
+use common::scratch;
+
+const SAMPLE_RATE: u32 = 44_100;
+
+fn sweep(frames: usize) -> Vec<f32> {
+    sine_sweep(SAMPLE_RATE).into_iter().take(frames).collect()
+}
+
+/// A mono float WAV of a sweep, a stereo 16 bit one and one that isn't a WAV at all
+fn write_inputs(dir: &Path) -> (Vec<f32>, Vec<[i16; 2]>) {
+    let mono = sweep(20_000);
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(dir.join("mono.wav"), spec).unwrap();
+    for sample in &mono {
+        writer.write_sample(*sample).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let stereo: Vec<[i16; 2]> = sweep(15_000)
+        .iter()
+        .map(|sample| {
+            let sample = (sample * 0.5 * 32768.0).round() as i16;
+            [sample, -sample]
+        })
+        .collect();
+    let spec = WavSpec {
+        channels: 2,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::create(dir.join("stereo.wav"), spec).unwrap();
+    for frame in &stereo {
+        writer.write_sample(frame[0]).unwrap();
+        writer.write_sample(frame[1]).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    std::fs::write(dir.join("corrupt.wav"), b"RIFF and nothing else").unwrap();
+    std::fs::write(dir.join("notes.txt"), b"not audio").unwrap();
+    (mono, stereo)
+}
+
+#[test]
+fn files_render_like_the_library() {
+    let dir = scratch("library");
+    let inputs_dir = dir.join("in");
+    std::fs::create_dir_all(&inputs_dir).unwrap();
+    let (mono, stereo) = write_inputs(&inputs_dir);
+
+    let inputs = find_inputs(inputs_dir.to_str().unwrap()).unwrap();
+    let names: Vec<_> = inputs
+        .iter()
+        .map(|path| path.file_name().unwrap())
+        .collect();
+    assert_eq!(names, ["corrupt.wav", "mono.wav", "stereo.wav"]);
+
+    let options = BatchOptions {
+        pitch_shift: 1.5,
+        template: "{name}_{pitch}.wav".to_string(),
+        jobs: Some(2),
+    };
+    let output_dir = dir.join("out");
+    let summary = run(&inputs, &output_dir, &options);
+    assert_eq!(summary.jobs, 2);
+    assert_eq!(summary.failures(), 1);
+    assert!(summary.files[0].result.is_err());
+    let text = summary.to_string();
+    assert!(text.contains("FAILED"), "{text}");
+    assert!(text.contains("2 succeeded, 1 failed"), "{text}");
+
+    let mut reader = WavReader::open(output_dir.join("mono_1.5.wav")).unwrap();
+    assert_eq!(reader.spec().sample_format, SampleFormat::Float);
+    let output: Vec<f32> = reader.samples::<f32>().map(Result::unwrap).collect();
+    assert_eq!(output, shift_channel(&mono, 1.5));
+
+    let mut reader = WavReader::open(output_dir.join("stereo_1.5.wav")).unwrap();
+    assert_eq!(reader.spec().channels, 2);
+    assert_eq!(reader.spec().bits_per_sample, 16);
+    let output: Vec<i16> = reader.samples::<i16>().map(Result::unwrap).collect();
+    for channel in 0..2 {
+        let input: Vec<f32> = stereo
+            .iter()
+            .map(|frame| frame[channel] as f32 / 32768.0)
+            .collect();
+        let expected: Vec<i16> = shift_channel(&input, 1.5)
+            .iter()
+            .map(|sample| (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16)
+            .collect();
+        let channel: Vec<i16> = output.iter().skip(channel).step_by(2).copied().collect();
+        assert_eq!(channel, expected);
+    }
+}
+
+#[test]
+fn inputs_named_alike_are_not_overwritten() {
+    let dir = scratch("clash");
+    for sub in ["a", "b"] {
+        std::fs::create_dir_all(dir.join(sub)).unwrap();
+        write_inputs(&dir.join(sub));
+    }
+    let pattern = dir.join("*").join("mono.wav");
+    let inputs = find_inputs(pattern.to_str().unwrap()).unwrap();
+    assert_eq!(inputs.len(), 2);
+    assert_eq!(
+        output_path(DEFAULT_TEMPLATE, &inputs[0], &dir, 2.0),
+        dir.join("mono_shifted.wav")
+    );
+
+    let options = BatchOptions {
+        pitch_shift: 2.0,
+        template: DEFAULT_TEMPLATE.to_string(),
+        jobs: None,
+    };
+    let summary = run(&inputs, &dir, &options);
+    assert_eq!(summary.failures(), 2);
+    assert!(!dir.join("mono_shifted.wav").exists());
+}
+
+#[test]
+fn binary_exits_with_an_error_if_any_file_failed() {
+    let dir = scratch("binary");
+    write_inputs(&dir);
+    let binary = env!("CARGO_BIN_EXE_vocoder");
+    let output_dir = dir.join("out");
+
+    let output = Command::new(binary)
+        .args([
+            "batch",
+            dir.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+            "0.75",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("2 succeeded, 1 failed"), "{stdout}");
+    assert!(output_dir.join("stereo_shifted.wav").exists());
+
+    std::fs::remove_file(dir.join("corrupt.wav")).unwrap();
+    let pattern = dir.join("*.wav");
+    let output = Command::new(binary)
+        .args([
+            "batch",
+            pattern.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+        ])
+        .args(["--name", "{name}.wav", "--jobs", "1"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("2 succeeded, 0 failed"), "{stdout}");
+    assert!(output_dir.join("mono.wav").exists());
+}
+
+#[test]
+fn binary_refuses_a_pitch_shift_that_is_not_positive() {
+    let dir = scratch("negative");
+    write_inputs(&dir);
+    let output_dir = dir.join("out");
+    let output = Command::new(env!("CARGO_BIN_EXE_vocoder"))
+        .args([
+            "batch",
+            dir.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+            "-1",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("isn't a positive ratio"), "{stderr}");
+    assert!(!output_dir.exists());
+}
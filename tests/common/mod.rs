@@ -0,0 +1,13 @@
+//! Fixtures shared by the integration tests
+
+use std::path::PathBuf;
+
+/// An empty directory of its own for each test, under one for the test binary
+pub fn scratch(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join(env!("CARGO_CRATE_NAME"))
+        .join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
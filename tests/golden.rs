@@ -0,0 +1,175 @@
+//! Renders excerpts of the WAV files in the repo root at a few pitch shift ratios and
+//! compares them against the references in `tests/golden`, sample by sample and by
+//! spectrum, so changes to `process_fft` can't silently change the sound.
+//!
+//! After an intended change to the output, regenerate the references with
+//! `UPDATE_GOLDEN=1 cargo test --test golden` and listen to them before committing.
+
+use std::path::{Path, PathBuf};
+
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use libm::{log10f, sqrtf};
+use vocoder::audio_processor::{shift_channel_with, FFT_SIZE};
+use vocoder::hann_window::HANN_WINDOW;
+use vocoder::math::Libm;
+
+const INPUTS: [&str; 3] = ["test_1.wav", "test_2.wav", "we_choose_r2d2.wav"];
+const PITCH_SHIFTS: [f32; 3] = [0.75, 1.0, 1.5];
+
+/// Excerpt of the first channel to render, from one second in
+const START: usize = 48_000;
+const LENGTH: usize = 16_384;
+
+/// Loosest sample-wise match to accept, as the SNR of the rendered output against the
+/// reference. Bit-identical output is infinite.
+const MIN_SNR_DB: f32 = 80.0;
+/// Loosest spectral match to accept, as the mean difference in dB between the magnitude
+/// spectra of the rendered output and the reference, over bins within 60 dB of the peak
+const MAX_SPECTRAL_DIFFERENCE_DB: f32 = 0.05;
+
+fn root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn reference_path(input: &str, pitch_shift: f32) -> PathBuf {
+    let name = Path::new(input).file_stem().unwrap().to_str().unwrap();
+    root()
+        .join("tests/golden")
+        .join(format!("{name}_{pitch_shift}.wav"))
+}
+
+/// The excerpt of the first channel of `path`, as f32, and its sample rate
+fn read_excerpt(path: &Path) -> (Vec<f32>, u32) {
+    let mut reader = WavReader::open(path).unwrap();
+    let spec = reader.spec();
+    let channels = spec.channels as usize;
+    let samples: Vec<f32> = match spec.sample_format {
+        SampleFormat::Float => reader.samples::<f32>().map(Result::unwrap).collect(),
+        SampleFormat::Int => {
+            let scale = (1u32 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.unwrap() as f32 / scale)
+                .collect()
+        }
+    };
+    let excerpt = samples
+        .chunks(channels)
+        .map(|frame| frame[0])
+        .skip(START)
+        .take(LENGTH)
+        .collect();
+    (excerpt, spec.sample_rate)
+}
+
+fn read_reference(path: &Path) -> Vec<f32> {
+    let mut reader = WavReader::open(path).unwrap_or_else(|err| {
+        panic!(
+            "{}: {err}, generate it with UPDATE_GOLDEN=1",
+            path.display()
+        )
+    });
+    reader.samples::<f32>().map(Result::unwrap).collect()
+}
+
+/// The binary's render, lined up with the input, with the backend pinned so the fast-math
+/// and fixed-point features don't change what's compared
+fn render(input: &[f32], pitch_shift: f32) -> Vec<f32> {
+    shift_channel_with::<Libm>(input, pitch_shift)
+}
+
+fn write_reference(path: &Path, samples: &[f32], sample_rate: u32) {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(path, spec).unwrap();
+    for sample in samples {
+        writer.write_sample(*sample).unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+fn snr_db(reference: &[f32], output: &[f32]) -> f32 {
+    let signal_power: f32 = reference.iter().map(|x| x * x).sum();
+    let noise_power: f32 = reference
+        .iter()
+        .zip(output)
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum();
+    10.0 * log10f(signal_power / noise_power)
+}
+
+/// Magnitude spectra of Hann windowed frames, hopping by half a frame
+fn spectra(samples: &[f32]) -> Vec<[f32; FFT_SIZE / 2]> {
+    samples
+        .windows(FFT_SIZE)
+        .step_by(FFT_SIZE / 2)
+        .map(|frame| {
+            let mut windowed = [0.0; FFT_SIZE];
+            for ((out, sample), window) in windowed.iter_mut().zip(frame).zip(HANN_WINDOW) {
+                *out = sample * window;
+            }
+            let fft = microfft::real::rfft_1024(&mut windowed);
+            fft.map(|bin| sqrtf(bin.re * bin.re + bin.im * bin.im))
+        })
+        .collect()
+}
+
+fn spectral_difference_db(reference: &[f32], output: &[f32]) -> f32 {
+    let reference = spectra(reference);
+    let output = spectra(output);
+    let peak = reference.iter().flatten().cloned().fold(0.0, f32::max);
+    let floor = peak * 1e-3;
+
+    let mut total = 0.0;
+    let mut bins = 0;
+    for (reference, output) in reference.iter().zip(&output) {
+        for (reference, output) in reference.iter().zip(output) {
+            if *reference > floor {
+                total += (20.0 * log10f(output.max(floor) / reference)).abs();
+                bins += 1;
+            }
+        }
+    }
+    total / bins as f32
+}
+
+#[test]
+fn output_matches_references() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+
+    for input in INPUTS {
+        let (excerpt, sample_rate) = read_excerpt(&root().join(input));
+        assert_eq!(excerpt.len(), LENGTH, "{input} is too short");
+
+        for pitch_shift in PITCH_SHIFTS {
+            let output = render(&excerpt, pitch_shift);
+            let path = reference_path(input, pitch_shift);
+            if update {
+                write_reference(&path, &output, sample_rate);
+                continue;
+            }
+
+            let reference = read_reference(&path);
+            assert_eq!(reference.len(), output.len(), "{}", path.display());
+            let snr = snr_db(&reference, &output);
+            let spectral = spectral_difference_db(&reference, &output);
+            if snr < MIN_SNR_DB || spectral > MAX_SPECTRAL_DIFFERENCE_DB {
+                failures.push(format!(
+                    "{input} at {pitch_shift}: {snr:.1} dB SNR, {spectral:.3} dB spectral difference"
+                ));
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "output no longer matches tests/golden, rerun with UPDATE_GOLDEN=1 if that's \
+         intended:\n{}",
+        failures.join("\n")
+    );
+}
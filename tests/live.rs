@@ -0,0 +1,172 @@
+//! `vocoder live` against a JACK server. The server test starts `jackd -d dummy`, which
+//! needs no sound card, so it runs on a headless box with jackd2 installed.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::process::{Child, Command};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use jack::{AudioIn, AudioOut, Client, ClientOptions, ClosureProcessHandler, Control};
+use vocoder::audio_processor::{AudioProcessor, FFT_SIZE, LATENCY};
+use vocoder::live::{Live, LiveProcessor, PitchControl};
+
+const SAMPLE_RATE: u32 = 48_000;
+const FREQUENCY: f32 = 440.0;
+
+/// Counts the allocations made by each thread, so tests running alongside don't count
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn sine(start: usize, output: &mut [f32]) {
+    for (n, sample) in output.iter_mut().enumerate() {
+        let phase = (start + n) as f32 * FREQUENCY / SAMPLE_RATE as f32;
+        *sample = 0.5 * (2.0 * std::f32::consts::PI * phase.fract()).sin();
+    }
+}
+
+#[test]
+fn processing_a_block_does_not_allocate() {
+    let pitch_control = PitchControl::new(1.5);
+    let mut live = LiveProcessor::new(pitch_control.clone());
+    let mut input = vec![0.0; 256];
+    let mut output = vec![0.0; 256];
+    let mut expected = Box::new(AudioProcessor::new(1.5));
+
+    for block in 0..100 {
+        sine(block * input.len(), &mut input);
+        let before = ALLOCATIONS.with(Cell::get);
+        if block == 50 {
+            pitch_control.set(0.5);
+            expected.set_pitch_shift(0.5);
+        }
+        // A note an octave above the reference
+        if block == 75 {
+            live.handle_midi(&[0x90, 72, 100]);
+            expected.set_pitch_shift(2.0);
+        }
+        live.process_block(&input, &mut output);
+        assert_eq!(
+            ALLOCATIONS.with(Cell::get),
+            before,
+            "block {block} allocated"
+        );
+
+        for (input, output) in input.iter().zip(&output) {
+            assert_eq!(output.to_bits(), expected.process_sample(*input).to_bits());
+        }
+    }
+}
+
+/// A `jackd -d dummy` server of its own, killed when dropped
+struct Server(Child);
+
+impl Server {
+    fn start() -> Server {
+        let name = format!("vocoder-test-{}", std::process::id());
+        // Clients in this process find the server through the environment
+        std::env::set_var("JACK_DEFAULT_SERVER", &name);
+        let child = Command::new("jackd")
+            .args(["--no-realtime", "-n", &name, "-d", "dummy", "-r"])
+            .arg(SAMPLE_RATE.to_string())
+            .args(["-p", "256"])
+            .spawn()
+            .expect("jackd not found, install jackd2");
+        Server(child)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Frequency of a sine from its rising zero crossings
+fn frequency(samples: &[f32]) -> f32 {
+    let crossings: Vec<usize> = (1..samples.len())
+        .filter(|n| samples[n - 1] < 0.0 && samples[*n] >= 0.0)
+        .collect();
+    let (first, last) = (crossings[0], crossings[crossings.len() - 1]);
+    (crossings.len() - 1) as f32 * SAMPLE_RATE as f32 / (last - first) as f32
+}
+
+#[test]
+fn shifts_through_a_dummy_jack_server() {
+    let _server = Server::start();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let (client, _) = loop {
+        match Client::new("vocoder-test", ClientOptions::NO_START_SERVER) {
+            Ok(client) => break client,
+            Err(err) if Instant::now() > deadline => panic!("jackd didn't start: {err}"),
+            Err(_) => thread::sleep(Duration::from_millis(100)),
+        }
+    };
+
+    // A test client plays a sine into the shifter and records what comes back
+    let mut play = client.register_port("play", AudioOut).unwrap();
+    let record = client.register_port("record", AudioIn).unwrap();
+    let recorded = Arc::new(Mutex::new(Vec::new()));
+    let mut played = 0;
+    let handler = ClosureProcessHandler::new({
+        let recorded = recorded.clone();
+        move |_: &Client, scope: &jack::ProcessScope| {
+            let output = play.as_mut_slice(scope);
+            sine(played, output);
+            played += output.len();
+            recorded
+                .lock()
+                .unwrap()
+                .extend_from_slice(record.as_slice(scope));
+            Control::Continue
+        }
+    });
+    let client = client.activate_async((), handler).unwrap();
+    assert_eq!(client.as_client().sample_rate(), SAMPLE_RATE as usize);
+
+    let live = Live::start("vocoder", 1.5).unwrap();
+    live.connect_input("vocoder-test:play").unwrap();
+    live.connect_output("vocoder-test:record").unwrap();
+
+    // Past the latency and the first frames, half a second of the shifted sine
+    let record_shifted = || {
+        recorded.lock().unwrap().clear();
+        let length = LATENCY + 2 * FFT_SIZE + SAMPLE_RATE as usize / 2;
+        while recorded.lock().unwrap().len() < length {
+            assert!(
+                Instant::now() < deadline + Duration::from_secs(10),
+                "JACK stalled"
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+        frequency(&recorded.lock().unwrap()[LATENCY + 2 * FFT_SIZE..length])
+    };
+
+    let shifted = record_shifted();
+    assert!((shifted - 1.5 * FREQUENCY).abs() < 2.0, "{shifted} Hz");
+    live.pitch_control().set(0.5);
+    let shifted = record_shifted();
+    assert!((shifted - 0.5 * FREQUENCY).abs() < 2.0, "{shifted} Hz");
+
+    drop(live);
+    drop(client);
+}
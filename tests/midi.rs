@@ -0,0 +1,134 @@
+//! MIDI control from a Standard MIDI File, rendered offline.
+
+use midly::num::{u15, u24, u28, u4, u7};
+use midly::{
+    Format, Header, MetaMessage, MidiMessage, PitchBend, Smf, Timing, TrackEvent, TrackEventKind,
+};
+use vocoder::analysis::pitch_error_cents;
+use vocoder::midi::{read_smf, render, MidiEvent, MidiProcessor, MIX_CC};
+
+const SAMPLE_RATE: u32 = 48_000;
+/// Ticks per quarter note
+const TICKS: u16 = 480;
+
+/// A format 1 file of `(tick, event)` tracks, at the default 120 bpm unless they change it
+fn smf(tracks: &[&[(u32, TrackEventKind<'static>)]]) -> Vec<u8> {
+    let mut smf = Smf::new(Header::new(
+        Format::Parallel,
+        Timing::Metrical(u15::new(TICKS)),
+    ));
+    for track in tracks {
+        let mut last = 0;
+        let mut events: Vec<TrackEvent> = track
+            .iter()
+            .map(|(tick, kind)| {
+                let delta = tick - last;
+                last = *tick;
+                TrackEvent {
+                    delta: u28::new(delta),
+                    kind: *kind,
+                }
+            })
+            .collect();
+        events.push(TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+        smf.tracks.push(events);
+    }
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes).unwrap();
+    bytes
+}
+
+fn midi(message: MidiMessage) -> TrackEventKind<'static> {
+    TrackEventKind::Midi {
+        channel: u4::new(0),
+        message,
+    }
+}
+
+fn note_on(key: u8) -> TrackEventKind<'static> {
+    midi(MidiMessage::NoteOn {
+        key: u7::new(key),
+        vel: u7::new(100),
+    })
+}
+
+fn note_off(key: u8) -> TrackEventKind<'static> {
+    midi(MidiMessage::NoteOff {
+        key: u7::new(key),
+        vel: u7::new(0),
+    })
+}
+
+#[test]
+fn events_are_timed_through_tempo_changes() {
+    let bytes = smf(&[
+        &[
+            (TICKS as u32, note_on(67)),
+            // Twice as fast from one second in
+            (
+                2 * TICKS as u32,
+                TrackEventKind::Meta(MetaMessage::Tempo(u24::new(250_000))),
+            ),
+            (3 * TICKS as u32, note_off(67)),
+        ],
+        &[(
+            TICKS as u32 / 2,
+            midi(MidiMessage::Controller {
+                controller: u7::new(MIX_CC),
+                value: u7::new(64),
+            }),
+        )],
+    ]);
+
+    let events = read_smf(&bytes, SAMPLE_RATE).unwrap();
+    assert_eq!(
+        events,
+        [
+            MidiEvent {
+                sample: 12_000,
+                message: [0xB0, MIX_CC, 64],
+            },
+            MidiEvent {
+                sample: 24_000,
+                message: [0x90, 67, 100],
+            },
+            MidiEvent {
+                sample: 60_000,
+                message: [0x80, 67, 0],
+            },
+        ]
+    );
+}
+
+#[test]
+fn notes_and_pitch_bend_play_the_pitch() {
+    // A note at half a second, bent up a whole tone at one, released at one and a half
+    let bytes = smf(&[&[
+        (TICKS as u32, note_on(67)),
+        (
+            2 * TICKS as u32,
+            midi(MidiMessage::PitchBend {
+                bend: PitchBend::from_int(8191),
+            }),
+        ),
+        (3 * TICKS as u32, note_off(67)),
+    ]]);
+    let events = read_smf(&bytes, SAMPLE_RATE).unwrap();
+
+    let input: Vec<f32> = (0..2 * SAMPLE_RATE)
+        .map(|n| (2.0 * std::f32::consts::PI * 440.0 * n as f32 / SAMPLE_RATE as f32).sin())
+        .collect();
+    let output = render(&mut MidiProcessor::new(1.0), &input, &events);
+    assert_eq!(output.len(), input.len());
+
+    let half_second = SAMPLE_RATE as usize / 2;
+    for (section, semitones) in [0.0, 7.0, 9.0, 2.0].into_iter().enumerate() {
+        let range = section * half_second..(section + 1) * half_second;
+        let ratio = (semitones / 12.0f32).exp2();
+        let error = pitch_error_cents(&input[range.clone()], &output[range], 0, ratio);
+        assert!(error < 5.0, "section {section}: {error} cents");
+    }
+}
@@ -0,0 +1,60 @@
+//! At unity shift the phase vocoder should give back its input, delayed by its latency.
+//! Nulling the output against the input catches changes to the windows, the overlap
+//! scaling or the handling of the DC and Nyquist bins that a listening test would miss.
+
+use libm::sinf;
+use vocoder::analysis::{null_test, sine_sweep, NullTest};
+use vocoder::audio_processor::{AudioProcessor, LATENCY};
+use vocoder::fixed_point::Q15;
+use vocoder::fixed_processor::{FixedPointProcessor, UNITY_PITCH};
+use vocoder::math::Libm;
+
+const SAMPLE_RATE: u32 = 48_000;
+
+/// A sweep over a DC offset with some white noise, so every bin from DC to Nyquist sees
+/// energy.
+fn test_signal() -> Vec<f32> {
+    let mut seed = 1u32;
+    sine_sweep(SAMPLE_RATE)
+        .into_iter()
+        .take(SAMPLE_RATE as usize * 2)
+        .enumerate()
+        .map(|(n, sample)| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let noise = seed as f32 / u32::MAX as f32 - 0.5;
+            0.1 + 0.6 * sample + 0.05 * noise + 0.05 * sinf(0.9 * n as f32)
+        })
+        .collect()
+}
+
+fn null_float(input: &[f32]) -> NullTest {
+    // Pin the backend, fast-math's approximations are measured in src/math.rs
+    let mut processor = AudioProcessor::<Libm>::with_backend(1.0);
+    let output: Vec<f32> = input.iter().map(|x| processor.process_sample(*x)).collect();
+    null_test(input, &output)
+}
+
+fn null_fixed(input: &[f32]) -> NullTest {
+    let mut processor = FixedPointProcessor::new(UNITY_PITCH);
+    let output: Vec<f32> = input
+        .iter()
+        .map(|x| processor.process_sample(Q15::from_f32(*x)).to_f32())
+        .collect();
+    null_test(input, &output)
+}
+
+#[test]
+fn float_path_reconstructs_input_at_unity() {
+    let null = null_float(&test_signal());
+    println!("{null}");
+    assert_eq!(null.delay, LATENCY);
+    assert!(null.residual_db < -85.0, "{null}");
+}
+
+#[test]
+fn fixed_point_path_reconstructs_input_at_unity() {
+    let null = null_fixed(&test_signal());
+    println!("{null}");
+    assert_eq!(null.delay, LATENCY);
+    assert!(null.residual_db < -45.0, "{null}");
+}
@@ -0,0 +1,241 @@
+//! `vocoder stream`: raw PCM and WAV through readers and writers, and through the binary
+//! on real pipes.
+
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::process::{Command, Stdio};
+
+use vocoder::analysis::sine_sweep;
+use vocoder::audio_processor::{render_aligned, AudioProcessor, LATENCY};
+use vocoder::stream::{
+    read_wav_header, stream, wav_header, Encoding, Format, StreamSpec, MAX_CHANNELS,
+};
+
+const SAMPLE_RATE: u32 = 44_100;
+
+/// Two channels of sweep, interleaved, the right one quieter and inverted
+fn stereo(frames: usize) -> Vec<f32> {
+    sine_sweep(SAMPLE_RATE)
+        .into_iter()
+        .take(frames)
+        .flat_map(|sample| [0.8 * sample, -0.3 * sample])
+        .collect()
+}
+
+/// What the library renders for each channel, with the latency taken out
+fn expected(interleaved: &[f32], channels: usize, pitch_shift: f32) -> Vec<f32> {
+    let frames = interleaved.len() / channels;
+    let mut output = vec![0.0; interleaved.len()];
+    for channel in 0..channels {
+        let mut processor = AudioProcessor::new(pitch_shift);
+        let input = interleaved.iter().skip(channel).step_by(channels);
+        let shifted = render_aligned(input.copied(), 0.0, |sample| {
+            processor.process_sample(sample)
+        });
+        for (frame, sample) in shifted.take(frames).enumerate() {
+            output[frame * channels + channel] = sample;
+        }
+    }
+    output
+}
+
+fn f32_bytes(samples: &[f32]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes())
+        .collect()
+}
+
+fn f32_samples(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect()
+}
+
+const RAW_STEREO: StreamSpec = StreamSpec {
+    sample_rate: SAMPLE_RATE,
+    channels: 2,
+    encoding: Encoding::F32,
+};
+
+#[test]
+fn raw_f32_matches_the_library() {
+    let input = stereo(20_000);
+    // A partial frame at the end is dropped
+    let mut bytes = f32_bytes(&input);
+    bytes.extend_from_slice(&[0; 3]);
+
+    let mut output = Vec::new();
+    let raw = Format::Raw(Encoding::F32);
+    stream(&bytes[..], raw, RAW_STEREO, &mut output, raw, 1.5).unwrap();
+    assert_eq!(f32_samples(&output), expected(&input, 2, 1.5));
+}
+
+#[test]
+fn wav_through_pipes_keeps_its_format() {
+    // As written by hound, with its lengths filled in
+    let input = stereo(10_000);
+    let mut wav = Cursor::new(Vec::new());
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::new(&mut wav, spec).unwrap();
+    for sample in &input {
+        writer
+            .write_sample((sample * 32768.0).round() as i16)
+            .unwrap();
+    }
+    writer.finalize().unwrap();
+    // Anything after the data chunk isn't audio
+    wav.seek(SeekFrom::End(0)).unwrap();
+    wav.write_all(b"LIST\x04\x00\x00\x00abcd").unwrap();
+
+    let mut output = Vec::new();
+    stream(
+        &wav.get_ref()[..],
+        Format::Wav,
+        RAW_STEREO,
+        &mut output,
+        Format::Wav,
+        0.75,
+    )
+    .unwrap();
+
+    let mut reader = &output[..];
+    let (spec, length) = read_wav_header(&mut reader).unwrap();
+    assert_eq!(
+        spec,
+        StreamSpec {
+            sample_rate: SAMPLE_RATE,
+            channels: 2,
+            encoding: Encoding::S16,
+        }
+    );
+    // Lengths can't be filled in on a pipe
+    assert_eq!(length, u32::MAX);
+    assert_eq!(reader.len(), input.len() * 2);
+
+    // The s16 input, rendered and rounded back to s16
+    let quantized: Vec<f32> = input
+        .iter()
+        .map(|sample| (sample * 32768.0).round() / 32768.0)
+        .collect();
+    let expected: Vec<i16> = expected(&quantized, 2, 0.75)
+        .iter()
+        .map(|sample| (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16)
+        .collect();
+    let output: Vec<i16> = reader
+        .chunks_exact(2)
+        .map(|bytes| i16::from_le_bytes(bytes.try_into().unwrap()))
+        .collect();
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn placeholder_lengths_are_read_to_the_end() {
+    let input = stereo(5_000);
+    let mut wav = wav_header(&RAW_STEREO).unwrap();
+    wav.extend_from_slice(&f32_bytes(&input));
+
+    let mut output = Vec::new();
+    let raw = Format::Raw(Encoding::F32);
+    stream(&wav[..], Format::Wav, RAW_STEREO, &mut output, raw, 1.25).unwrap();
+    assert_eq!(f32_samples(&output), expected(&input, 2, 1.25));
+}
+
+#[test]
+fn headers_that_overflow_are_rejected() {
+    let channels = StreamSpec {
+        channels: u16::MAX,
+        ..RAW_STEREO
+    };
+    let rate = StreamSpec {
+        sample_rate: u32::MAX / 4,
+        ..RAW_STEREO
+    };
+    for spec in [channels, rate] {
+        let err = wav_header(&spec).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput, "{spec:?}");
+    }
+
+    // A fmt chunk claiming 4 GB, which would otherwise be allocated before reading
+    let mut wav = wav_header(&RAW_STEREO).unwrap();
+    wav[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+    let err = read_wav_header(&mut &wav[..]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn channel_counts_past_the_limit_are_rejected() {
+    let spec = StreamSpec {
+        channels: MAX_CHANNELS + 1,
+        ..RAW_STEREO
+    };
+    let wav = wav_header(&spec).unwrap();
+    let mut output = Vec::new();
+    let err = stream(&wav[..], Format::Wav, spec, &mut output, Format::Wav, 1.5);
+    assert_eq!(err.unwrap_err().kind(), ErrorKind::InvalidData);
+    assert!(output.is_empty());
+}
+
+/// Fails every read, like a pipe whose writer crashed
+struct Broken;
+
+impl Read for Broken {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::other("broken"))
+    }
+}
+
+#[test]
+fn a_broken_input_ends_without_the_tail() {
+    let input = stereo(5_000);
+    let bytes = f32_bytes(&input);
+    let raw = Format::Raw(Encoding::F32);
+    let mut output = Vec::new();
+    let err = stream(bytes.chain(Broken), raw, RAW_STEREO, &mut output, raw, 1.5);
+    assert_eq!(err.unwrap_err().kind(), ErrorKind::Other);
+    let rendered = (5_000 - LATENCY) * 2;
+    assert_eq!(f32_samples(&output), expected(&input, 2, 1.5)[..rendered]);
+}
+
+#[test]
+fn binary_streams_stdin_to_stdout() {
+    let input = stereo(SAMPLE_RATE as usize);
+    let bytes = f32_bytes(&input);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_vocoder"))
+        .args(["stream", "1.5", "--input", "f32", "--output", "wav"])
+        .args(["--channels", "2", "--rate", &SAMPLE_RATE.to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Write from another thread, so a full stdout pipe can't stall the writes
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = std::thread::spawn(move || stdin.write_all(&bytes).unwrap());
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap();
+    assert!(output.status.success());
+
+    let mut reader = &output.stdout[..];
+    let (spec, _) = read_wav_header(&mut reader).unwrap();
+    assert_eq!(spec, RAW_STEREO);
+    assert_eq!(f32_samples(reader), expected(&input, 2, 1.5));
+}
+
+#[test]
+fn binary_refuses_a_pitch_shift_that_is_not_positive() {
+    for pitch_shift in ["-1", "0", "NaN"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_vocoder"))
+            .args(["stream", pitch_shift, "--input", "f32"])
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "{pitch_shift}");
+        assert!(output.stdout.is_empty(), "{pitch_shift}");
+    }
+}
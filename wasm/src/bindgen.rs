@@ -0,0 +1,41 @@
+//! wasm-bindgen wrapper for the main thread, where copying through JS arrays is fine.
+//! Build with `--features bindgen` and run the module through `wasm-bindgen --target web`.
+
+use wasm_bindgen::prelude::{wasm_bindgen, JsError};
+
+use crate::Shifter;
+
+#[wasm_bindgen]
+pub struct PitchShifter {
+    shifter: Box<Shifter>,
+}
+
+#[wasm_bindgen]
+impl PitchShifter {
+    /// Throws if the pitch shift isn't a positive ratio
+    #[wasm_bindgen(constructor)]
+    pub fn new(pitch_shift: f32) -> Result<PitchShifter, JsError> {
+        let shifter = Shifter::new(pitch_shift).map_err(|err| JsError::new(&err.to_string()))?;
+        Ok(PitchShifter {
+            shifter: Box::new(shifter),
+        })
+    }
+
+    /// Throws and keeps the current pitch shift if it isn't a positive ratio
+    #[wasm_bindgen(js_name = setPitchShift)]
+    pub fn set_pitch_shift(&mut self, pitch_shift: f32) -> Result<(), JsError> {
+        self.shifter
+            .set_pitch_shift(pitch_shift)
+            .map_err(|err| JsError::new(&err.to_string()))
+    }
+
+    /// Shift a `Float32Array` in place
+    pub fn process(&mut self, samples: &mut [f32]) {
+        self.shifter.process(samples);
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn latency(&self) -> usize {
+        crate::vocoder_latency()
+    }
+}
@@ -0,0 +1,148 @@
+//! The pitch shifter for `wasm32-unknown-unknown`, running the same `process_fft` as the
+//! firmware.
+//!
+//! The exported functions take and return plain numbers and pointers into the module's
+//! memory, so an `AudioWorkletProcessor` can drive the module with nothing but
+//! `WebAssembly.instantiate`: AudioWorklet scopes don't have the APIs wasm-bindgen's glue
+//! code needs. `worklet.js` shows the intended use. With the `bindgen` feature there's
+//! also a wasm-bindgen class for the main thread.
+
+#[cfg(feature = "bindgen")]
+pub mod bindgen;
+
+use vocoder::audio_processor::{check_pitch_shift, AudioProcessor, InvalidPitchShift, LATENCY};
+
+/// Frames per `process` call of an `AudioWorkletProcessor`
+pub const RENDER_QUANTUM: usize = 128;
+
+pub struct Shifter {
+    processor: AudioProcessor,
+    /// Scratch for one render quantum, so a worklet doesn't have to allocate its own
+    buffer: [f32; RENDER_QUANTUM],
+}
+
+impl Shifter {
+    pub fn new(pitch_shift: f32) -> Result<Shifter, InvalidPitchShift> {
+        Ok(Shifter {
+            processor: AudioProcessor::new(check_pitch_shift(pitch_shift)?),
+            buffer: [0.0; RENDER_QUANTUM],
+        })
+    }
+
+    /// Keeps the current pitch shift if `pitch_shift` isn't a positive ratio
+    pub fn set_pitch_shift(&mut self, pitch_shift: f32) -> Result<(), InvalidPitchShift> {
+        self.processor
+            .set_pitch_shift(check_pitch_shift(pitch_shift)?);
+        Ok(())
+    }
+
+    /// Shift `samples` in place. The output is `LATENCY` samples behind the input.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            *sample = self.processor.process_sample(*sample);
+        }
+    }
+
+    /// Shift the first `len` samples of the scratch buffer, at most `RENDER_QUANTUM`,
+    /// returning how many were shifted
+    fn process_buffer(&mut self, len: usize) -> usize {
+        let len = len.min(RENDER_QUANTUM);
+        for sample in &mut self.buffer[..len] {
+            *sample = self.processor.process_sample(*sample);
+        }
+        len
+    }
+}
+
+/// Create a shifter, freed with `vocoder_free`. Returns null if the pitch shift isn't a
+/// positive ratio.
+#[no_mangle]
+pub extern "C" fn vocoder_new(pitch_shift: f32) -> *mut Shifter {
+    match Shifter::new(pitch_shift) {
+        Ok(shifter) => Box::into_raw(Box::new(shifter)),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Does nothing if `shifter` is null, like the one `vocoder_new` returns when it refuses
+/// a pitch shift
+///
+/// # Safety
+/// `shifter` must be null or come from `vocoder_new`, and not be used again
+#[no_mangle]
+pub unsafe extern "C" fn vocoder_free(shifter: *mut Shifter) {
+    if shifter.is_null() {
+        return;
+    }
+    drop(Box::from_raw(shifter));
+}
+
+/// The shifter's `vocoder_buffer_len` samples of scratch, to copy a block into and
+/// process
+///
+/// # Safety
+/// `shifter` must come from `vocoder_new`
+#[no_mangle]
+pub unsafe extern "C" fn vocoder_buffer(shifter: *mut Shifter) -> *mut f32 {
+    (*shifter).buffer.as_mut_ptr()
+}
+
+/// Samples in `vocoder_buffer`, `RENDER_QUANTUM`
+#[no_mangle]
+pub extern "C" fn vocoder_buffer_len() -> usize {
+    RENDER_QUANTUM
+}
+
+/// Shift `len` samples at `samples` in place and return how many were shifted. That's
+/// all of them, except from the shifter's own buffer, which only holds
+/// `RENDER_QUANTUM`, so any more are left alone.
+///
+/// # Safety
+/// `shifter` must come from `vocoder_new`, and `samples` must either be its
+/// `vocoder_buffer` or point to `len` samples that aren't in a shifter
+#[no_mangle]
+pub unsafe extern "C" fn vocoder_process(
+    shifter: *mut Shifter,
+    samples: *mut f32,
+    len: usize,
+) -> usize {
+    let shifter = &mut *shifter;
+    if samples == shifter.buffer.as_mut_ptr() {
+        shifter.process_buffer(len)
+    } else {
+        shifter.process(std::slice::from_raw_parts_mut(samples, len));
+        len
+    }
+}
+
+/// Returns false and keeps the current pitch shift if `pitch_shift` isn't a positive
+/// ratio
+///
+/// # Safety
+/// `shifter` must come from `vocoder_new`
+#[no_mangle]
+pub unsafe extern "C" fn vocoder_set_pitch_shift(shifter: *mut Shifter, pitch_shift: f32) -> bool {
+    (*shifter).set_pitch_shift(pitch_shift).is_ok()
+}
+
+/// Samples the output is delayed by
+#[no_mangle]
+pub extern "C" fn vocoder_latency() -> usize {
+    LATENCY
+}
+
+/// Allocate `len` samples of zeros in the module's memory, for processing more than
+/// `RENDER_QUANTUM` at a time. Free them with `vocoder_dealloc`.
+#[no_mangle]
+pub extern "C" fn vocoder_alloc(len: usize) -> *mut f32 {
+    Box::into_raw(vec![0.0f32; len].into_boxed_slice()) as *mut f32
+}
+
+/// # Safety
+/// `samples` and `len` must come from one `vocoder_alloc` call
+#[no_mangle]
+pub unsafe extern "C" fn vocoder_dealloc(samples: *mut f32, len: usize) {
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        samples, len,
+    )));
+}
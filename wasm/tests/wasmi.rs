@@ -0,0 +1,206 @@
+//! Builds the module for `wasm32-unknown-unknown`, runs it in the wasmi interpreter the
+//! way `worklet.js` drives it, and checks it renders exactly what the native build does.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use vocoder::analysis::sine_sweep;
+use vocoder::audio_processor::LATENCY;
+use vocoder_wasm::{Shifter, RENDER_QUANTUM};
+use wasmi::{Engine, Instance, Linker, Memory, Module, Store, TypedFunc};
+
+const SAMPLE_RATE: u32 = 48_000;
+
+fn build_module() -> Vec<u8> {
+    // A target directory of its own, so this doesn't wait on the one running the tests
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("wasm");
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--release", "-p", "vocoder-wasm"])
+        .args(["--target", "wasm32-unknown-unknown", "--target-dir"])
+        .arg(&target_dir)
+        .status()
+        .unwrap();
+    assert!(
+        status.success(),
+        "building the module failed, try `rustup target add wasm32-unknown-unknown`"
+    );
+    fs::read(target_dir.join("wasm32-unknown-unknown/release/vocoder_wasm.wasm")).unwrap()
+}
+
+struct Wasm {
+    store: Store<()>,
+    instance: Instance,
+    memory: Memory,
+}
+
+impl Wasm {
+    fn func<Params: wasmi::WasmParams, Results: wasmi::WasmResults>(
+        &self,
+        name: &str,
+    ) -> TypedFunc<Params, Results> {
+        self.instance
+            .get_typed_func(&self.store, name)
+            .unwrap_or_else(|err| panic!("{name}: {err}"))
+    }
+
+    fn write(&mut self, pointer: i32, samples: &[f32]) {
+        let bytes: Vec<u8> = samples.iter().flat_map(|x| x.to_le_bytes()).collect();
+        self.memory
+            .write(&mut self.store, pointer as usize, &bytes)
+            .unwrap();
+    }
+
+    fn read(&self, pointer: i32, samples: &mut [f32]) {
+        let mut bytes = vec![0; samples.len() * 4];
+        self.memory
+            .read(&self.store, pointer as usize, &mut bytes)
+            .unwrap();
+        for (sample, bytes) in samples.iter_mut().zip(bytes.chunks_exact(4)) {
+            *sample = f32::from_le_bytes(bytes.try_into().unwrap());
+        }
+    }
+}
+
+fn instantiate(wasm: &[u8]) -> Wasm {
+    let engine = Engine::default();
+    let module = Module::new(&engine, wasm).unwrap();
+    // A worklet instantiates it with an empty import object
+    let imports: Vec<_> = module
+        .imports()
+        .map(|import| import.name().to_string())
+        .collect();
+    assert!(imports.is_empty(), "the module imports {imports:?}");
+
+    let mut store = Store::new(&engine, ());
+    let instance = Linker::<()>::new(&engine)
+        .instantiate(&mut store, &module)
+        .unwrap()
+        .start(&mut store)
+        .unwrap();
+    let memory = instance.get_memory(&store, "memory").unwrap();
+    Wasm {
+        store,
+        instance,
+        memory,
+    }
+}
+
+#[test]
+fn module_renders_like_the_native_build() {
+    let mut module = instantiate(&build_module());
+    let new: TypedFunc<f32, i32> = module.func("vocoder_new");
+    let buffer: TypedFunc<i32, i32> = module.func("vocoder_buffer");
+    let process: TypedFunc<(i32, i32, i32), i32> = module.func("vocoder_process");
+    let set_pitch_shift: TypedFunc<(i32, f32), i32> = module.func("vocoder_set_pitch_shift");
+    let latency: TypedFunc<(), i32> = module.func("vocoder_latency");
+    let alloc: TypedFunc<i32, i32> = module.func("vocoder_alloc");
+    let dealloc: TypedFunc<(i32, i32), ()> = module.func("vocoder_dealloc");
+    let free: TypedFunc<i32, ()> = module.func("vocoder_free");
+
+    assert_eq!(latency.call(&mut module.store, ()).unwrap(), LATENCY as i32);
+    let input: Vec<f32> = sine_sweep(SAMPLE_RATE)
+        .into_iter()
+        .take(SAMPLE_RATE as usize / 2)
+        .collect();
+    let (first, second) = input.split_at(input.len() / 2);
+
+    // First half a render quantum at a time through the shifter's buffer, like the
+    // worklet, then the rest in one go through an allocation at another ratio
+    let mut native = Shifter::new(1.5).unwrap();
+    let mut expected = input.clone();
+    let (expected_first, expected_second) = expected.split_at_mut(first.len());
+    for block in expected_first.chunks_mut(RENDER_QUANTUM) {
+        native.process(block);
+    }
+    native.set_pitch_shift(0.75).unwrap();
+    native.process(expected_second);
+
+    let mut output = vec![0.0; input.len()];
+    let (output_first, output_second) = output.split_at_mut(first.len());
+    assert_eq!(new.call(&mut module.store, 0.0).unwrap(), 0);
+    let shifter = new.call(&mut module.store, 1.5).unwrap();
+    let quantum = buffer.call(&mut module.store, shifter).unwrap();
+    for (input, output) in first
+        .chunks(RENDER_QUANTUM)
+        .zip(output_first.chunks_mut(RENDER_QUANTUM))
+    {
+        module.write(quantum, input);
+        let shifted = process
+            .call(&mut module.store, (shifter, quantum, input.len() as i32))
+            .unwrap();
+        assert_eq!(shifted, input.len() as i32);
+        module.read(quantum, output);
+    }
+
+    // A ratio that would fold every bin onto DC is refused and changes nothing
+    let refused = set_pitch_shift.call(&mut module.store, (shifter, -1.0));
+    assert_eq!(refused.unwrap(), 0);
+    let accepted = set_pitch_shift.call(&mut module.store, (shifter, 0.75));
+    assert_eq!(accepted.unwrap(), 1);
+    let samples = alloc.call(&mut module.store, second.len() as i32).unwrap();
+    module.write(samples, second);
+    let shifted = process
+        .call(&mut module.store, (shifter, samples, second.len() as i32))
+        .unwrap();
+    assert_eq!(shifted, second.len() as i32);
+    module.read(samples, output_second);
+    dealloc
+        .call(&mut module.store, (samples, second.len() as i32))
+        .unwrap();
+    free.call(&mut module.store, shifter).unwrap();
+
+    let mismatch = output
+        .iter()
+        .zip(&expected)
+        .position(|(output, expected)| output.to_bits() != expected.to_bits());
+    assert_eq!(mismatch, None, "wasm and native output differ");
+    assert!(output.iter().any(|sample| *sample != 0.0));
+}
+
+#[test]
+fn processing_the_buffer_stops_at_its_end() {
+    let mut module = instantiate(&build_module());
+    let new: TypedFunc<f32, i32> = module.func("vocoder_new");
+    let buffer: TypedFunc<i32, i32> = module.func("vocoder_buffer");
+    let buffer_len: TypedFunc<(), i32> = module.func("vocoder_buffer_len");
+    let process: TypedFunc<(i32, i32, i32), i32> = module.func("vocoder_process");
+
+    assert_eq!(
+        buffer_len.call(&mut module.store, ()).unwrap(),
+        RENDER_QUANTUM as i32
+    );
+    let shifter = new.call(&mut module.store, 1.5).unwrap();
+    let quantum = buffer.call(&mut module.store, shifter).unwrap();
+    // Whatever follows the buffer in memory
+    let mut after = [0.0; RENDER_QUANTUM];
+    module.read(quantum + 4 * RENDER_QUANTUM as i32, &mut after);
+
+    let input = [0.5; RENDER_QUANTUM];
+    module.write(quantum, &input);
+    let len = 4 * RENDER_QUANTUM as i32;
+    let shifted = process
+        .call(&mut module.store, (shifter, quantum, len))
+        .unwrap();
+    assert_eq!(shifted, RENDER_QUANTUM as i32);
+
+    let mut untouched = [0.0; RENDER_QUANTUM];
+    module.read(quantum + 4 * RENDER_QUANTUM as i32, &mut untouched);
+    assert_eq!(untouched.map(f32::to_bits), after.map(f32::to_bits));
+}
+
+#[test]
+fn freeing_null_does_nothing() {
+    let mut module = instantiate(&build_module());
+    let new: TypedFunc<f32, i32> = module.func("vocoder_new");
+    let free: TypedFunc<i32, ()> = module.func("vocoder_free");
+
+    // What a refused pitch shift leaves a caller holding
+    let refused = new.call(&mut module.store, -1.0).unwrap();
+    assert_eq!(refused, 0);
+    free.call(&mut module.store, refused).unwrap();
+    free.call(&mut module.store, 0).unwrap();
+
+    let shifter = new.call(&mut module.store, 1.5).unwrap();
+    free.call(&mut module.store, shifter).unwrap();
+}
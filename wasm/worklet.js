@@ -0,0 +1,53 @@
+// An AudioWorkletProcessor around the exports in src/lib.rs. Worklets can't fetch, so
+// compile the module on the main thread and pass it in:
+//
+//   const module = await WebAssembly.compileStreaming(fetch("vocoder_wasm.wasm"));
+//   await context.audioWorklet.addModule("worklet.js");
+//   const node = new AudioWorkletNode(context, "vocoder", {
+//     processorOptions: { module, pitchShift: 1.5 },
+//   });
+//   node.port.postMessage({ pitchShift: 0.75 });
+//
+// The first input channel is shifted and written to every output channel. Pitch shifts
+// that aren't positive ratios are refused: the constructor throws, and a message
+// leaves the current one.
+
+class VocoderProcessor extends AudioWorkletProcessor {
+  constructor(options) {
+    super();
+    const { module, pitchShift } = options.processorOptions;
+    this.exports = new WebAssembly.Instance(module, {}).exports;
+    this.shifter = this.exports.vocoder_new(pitchShift);
+    if (!this.shifter) {
+      throw new RangeError(`pitch shift ${pitchShift} isn't a positive ratio`);
+    }
+    this.port.onmessage = ({ data }) =>
+      this.exports.vocoder_set_pitch_shift(this.shifter, data.pitchShift);
+  }
+
+  process(inputs, outputs) {
+    const input = inputs[0][0];
+    const frames = outputs[0][0]?.length ?? 0;
+    const pointer = this.exports.vocoder_buffer(this.shifter);
+    // The shifter's buffer holds one render quantum, so go through longer blocks a
+    // quantum at a time
+    const quantum = this.exports.vocoder_buffer_len();
+    for (let offset = 0; offset < frames; offset += quantum) {
+      const length = Math.min(quantum, frames - offset);
+      // Growing the memory detaches old views, so make a new one every time
+      const buffer = new Float32Array(this.exports.memory.buffer, pointer, length);
+      if (input) {
+        buffer.set(input.subarray(offset, offset + length));
+      } else {
+        buffer.fill(0);
+      }
+      this.exports.vocoder_process(this.shifter, pointer, length);
+      for (const channel of outputs[0]) {
+        channel.set(buffer, offset);
+      }
+    }
+    return true;
+  }
+}
+
+registerProcessor("vocoder", VocoderProcessor);